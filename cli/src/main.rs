@@ -0,0 +1,150 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use extism::{Error, Manifest, Plugin};
+
+/// Run and inspect Extism plugins
+#[derive(Parser)]
+#[command(name = "extism", version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Call a function and print the output
+    Call(CallArgs),
+
+    /// List the functions exported by a plugin
+    Exports(PluginArgs),
+
+    /// Check that a manifest and all of the modules it references can be loaded
+    Validate(PluginArgs),
+}
+
+#[derive(clap::Args)]
+struct PluginArgs {
+    /// A WebAssembly module or a JSON/TOML manifest
+    path: PathBuf,
+
+    /// Enable WASI
+    #[arg(long)]
+    wasi: bool,
+}
+
+#[derive(clap::Args)]
+struct CallArgs {
+    #[command(flatten)]
+    plugin: PluginArgs,
+
+    /// The name of the function to call
+    function: String,
+
+    /// Input data, use `-` to read from stdin
+    #[arg(short, long, conflicts_with = "input_file")]
+    input: Option<String>,
+
+    /// Read input data from a file
+    #[arg(long)]
+    input_file: Option<PathBuf>,
+
+    /// Set a config value, in the form `key=value`
+    #[arg(long = "config", value_parser = parse_key_value)]
+    config: Vec<(String, String)>,
+
+    /// Allow HTTP requests to a host, this can be specified multiple times
+    #[arg(long = "allow-host")]
+    allowed_hosts: Vec<String>,
+
+    /// Timeout in milliseconds
+    #[arg(long)]
+    timeout: Option<u64>,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) => Ok((k.to_string(), v.to_string())),
+        None => Err(format!("expected `key=value`, got `{s}`")),
+    }
+}
+
+// Load a manifest from disk, a WebAssembly module is wrapped in a new manifest
+fn load_manifest(path: &Path) -> Result<Manifest, Error> {
+    let data = std::fs::read(path)?;
+    let is_wasm = data.starts_with(b"\0asm")
+        || matches!(
+            path.extension().and_then(|x| x.to_str()),
+            Some("wasm" | "wat")
+        );
+    if is_wasm {
+        return Ok(Manifest::new([extism_manifest::Wasm::data(data)]));
+    }
+
+    let s = std::str::from_utf8(&data)?;
+    if path.extension().and_then(|x| x.to_str()) == Some("toml") {
+        return Ok(toml::from_str(s)?);
+    }
+    Ok(serde_json::from_str(s)?)
+}
+
+fn read_input(args: &CallArgs) -> Result<Vec<u8>, Error> {
+    if let Some(path) = &args.input_file {
+        return Ok(std::fs::read(path)?);
+    }
+
+    match args.input.as_deref() {
+        Some("-") => {
+            let mut buf = vec![];
+            std::io::stdin().read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+        Some(s) => Ok(s.as_bytes().to_vec()),
+        None => Ok(vec![]),
+    }
+}
+
+fn call(args: CallArgs) -> Result<(), Error> {
+    let mut manifest = load_manifest(&args.plugin.path)?;
+    manifest.config.extend(args.config.iter().cloned());
+    for host in args.allowed_hosts.iter() {
+        manifest = manifest.with_allowed_host(host);
+    }
+    if let Some(ms) = args.timeout {
+        manifest = manifest.with_timeout(std::time::Duration::from_millis(ms));
+    }
+
+    let input = read_input(&args)?;
+    let mut plugin = Plugin::new_with_manifest(&manifest, [], args.plugin.wasi)?;
+    let output = plugin.call_bytes(&args.function, &input)?;
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(output)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn exports(args: PluginArgs) -> Result<(), Error> {
+    let manifest = load_manifest(&args.path)?;
+    let plugin = Plugin::new_with_manifest(&manifest, [], args.wasi)?;
+    for name in plugin.function_names() {
+        println!("{name}");
+    }
+    Ok(())
+}
+
+fn validate(args: PluginArgs) -> Result<(), Error> {
+    let manifest = load_manifest(&args.path)?;
+    Plugin::new_with_manifest(&manifest, [], args.wasi)?;
+    println!("{}: OK", args.path.display());
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    match Args::parse().command {
+        Command::Call(args) => call(args),
+        Command::Exports(args) => exports(args),
+        Command::Validate(args) => validate(args),
+    }
+}
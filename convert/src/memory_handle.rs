@@ -6,6 +6,10 @@ pub struct MemoryHandle {
 
     /// The length of the memory region
     pub length: u64,
+
+    /// The memory the region is in, `0` is Extism memory and other values are returned by
+    /// `CurrentPlugin::memory_index` for plugins that use multiple memories
+    pub memory: u32,
 }
 
 impl MemoryHandle {
@@ -14,7 +18,11 @@ impl MemoryHandle {
     /// # Safety
     /// This function is unsafe because the specified memory region may not be valid.
     pub unsafe fn new(offset: u64, length: u64) -> MemoryHandle {
-        MemoryHandle { offset, length }
+        MemoryHandle {
+            offset,
+            length,
+            memory: 0,
+        }
     }
 
     /// `NULL` equivalent
@@ -22,6 +30,7 @@ impl MemoryHandle {
         MemoryHandle {
             offset: 0,
             length: 0,
+            memory: 0,
         }
     }
 
@@ -35,6 +44,11 @@ impl MemoryHandle {
         self.length as usize
     }
 
+    /// Get the memory a memory handle refers to, `0` is Extism memory
+    pub fn memory(&self) -> u32 {
+        self.memory
+    }
+
     /// Returns `true` when the length is 0
     pub fn is_empty(&self) -> bool {
         self.length == 0
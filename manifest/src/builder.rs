@@ -0,0 +1,197 @@
+use std::path::Path;
+
+use crate::{Include, Manifest, MemoryOptions, OptLevel, Problem, TrustedKey, WasiOptions, Wasm};
+
+/// Builds a `Manifest`, unlike the `Manifest::with_*` methods the result is validated when `build` is called
+#[derive(Clone, Debug)]
+pub struct ManifestBuilder {
+    manifest: Manifest,
+}
+
+/// Returned by `ManifestBuilder::build` when the manifest has problems
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildError {
+    /// Every problem found in the manifest
+    pub problems: Vec<Problem>,
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid manifest")?;
+        for (i, problem) in self.problems.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { "; " };
+            write!(f, "{sep}{problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl Default for ManifestBuilder {
+    fn default() -> Self {
+        ManifestBuilder::new()
+    }
+}
+
+impl Manifest {
+    /// Create a `ManifestBuilder`
+    pub fn builder() -> ManifestBuilder {
+        ManifestBuilder::new()
+    }
+}
+
+impl ManifestBuilder {
+    /// Create a builder for an empty manifest with the default timeout
+    pub fn new() -> ManifestBuilder {
+        ManifestBuilder {
+            manifest: Manifest::new([] as [Wasm; 0]),
+        }
+    }
+
+    fn map(self, f: impl FnOnce(Manifest) -> Manifest) -> ManifestBuilder {
+        ManifestBuilder {
+            manifest: f(self.manifest),
+        }
+    }
+
+    /// Add a module to `wasm`
+    pub fn with_wasm(self, wasm: impl Into<Wasm>) -> Self {
+        self.map(|mut m| {
+            m.wasm.push(wasm.into());
+            m
+        })
+    }
+
+    /// See `Manifest::disallow_all_hosts`
+    pub fn disallow_all_hosts(self) -> Self {
+        self.map(|m| m.disallow_all_hosts())
+    }
+
+    /// See `Manifest::with_memory_options`
+    pub fn with_memory_options(self, memory: MemoryOptions) -> Self {
+        self.map(|m| m.with_memory_options(memory))
+    }
+
+    /// See `Manifest::with_memory_max`
+    pub fn with_memory_max(self, max: u32) -> Self {
+        self.map(|m| m.with_memory_max(max))
+    }
+
+    /// See `Manifest::with_max_http_response_bytes`
+    pub fn with_max_http_response_bytes(self, max: u64) -> Self {
+        self.map(|m| m.with_max_http_response_bytes(max))
+    }
+
+    /// See `Manifest::with_allowed_host`
+    pub fn with_allowed_host(self, host: impl Into<String>) -> Self {
+        self.map(|m| m.with_allowed_host(host))
+    }
+
+    /// See `Manifest::with_denied_host`
+    pub fn with_denied_host(self, host: impl Into<String>) -> Self {
+        self.map(|m| m.with_denied_host(host))
+    }
+
+    /// See `Manifest::with_allowed_path`
+    pub fn with_allowed_path(self, src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Self {
+        self.map(|m| m.with_allowed_path(src, dest))
+    }
+
+    /// See `Manifest::with_readonly_path`
+    pub fn with_readonly_path(self, src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Self {
+        self.map(|m| m.with_readonly_path(src, dest))
+    }
+
+    /// See `Manifest::with_config_key`
+    pub fn with_config_key(self, k: impl Into<String>, v: impl Into<String>) -> Self {
+        self.map(|m| m.with_config_key(k, v))
+    }
+
+    /// See `Manifest::with_config_value`
+    pub fn with_config_value(self, k: impl Into<String>, v: impl Into<serde_json::Value>) -> Self {
+        self.map(|m| m.with_config_value(k, v))
+    }
+
+    /// See `Manifest::with_timeout`
+    pub fn with_timeout(self, timeout: std::time::Duration) -> Self {
+        self.map(|m| m.with_timeout(timeout))
+    }
+
+    /// See `Manifest::with_function_timeout`
+    pub fn with_function_timeout(
+        self,
+        name: impl Into<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        self.map(|m| m.with_function_timeout(name, timeout))
+    }
+
+    /// Remove the timeout, plugin functions will be allowed to run until they return
+    pub fn without_timeout(self) -> Self {
+        self.map(|mut m| {
+            m.timeout_ms = None;
+            m
+        })
+    }
+
+    /// See `Manifest::with_opt_level`
+    pub fn with_opt_level(self, opt_level: OptLevel) -> Self {
+        self.map(|m| m.with_opt_level(opt_level))
+    }
+
+    /// See `Manifest::with_include`
+    pub fn with_include(self, include: impl Into<Include>) -> Self {
+        self.map(|m| m.with_include(include))
+    }
+
+    /// See `Manifest::with_extends`
+    pub fn with_extends(self, base: impl Into<Include>) -> Self {
+        self.map(|m| m.with_extends(base))
+    }
+
+    /// See `Manifest::with_wasi_options`
+    pub fn with_wasi_options(self, wasi: WasiOptions) -> Self {
+        self.map(|m| m.with_wasi_options(wasi))
+    }
+
+    /// See `Manifest::with_wasi_args`
+    pub fn with_wasi_args(self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.map(|m| m.with_wasi_args(args))
+    }
+
+    /// See `Manifest::with_wasi_env`
+    pub fn with_wasi_env(self, k: impl Into<String>, v: impl Into<String>) -> Self {
+        self.map(|m| m.with_wasi_env(k, v))
+    }
+
+    /// See `Manifest::with_max_concurrent_calls`
+    pub fn with_max_concurrent_calls(self, n: u32) -> Self {
+        self.map(|m| m.with_max_concurrent_calls(n))
+    }
+
+    /// See `Manifest::with_max_instances`
+    pub fn with_max_instances(self, n: u32) -> Self {
+        self.map(|m| m.with_max_instances(n))
+    }
+
+    /// See `Manifest::with_deterministic_seed`
+    pub fn with_deterministic_seed(self, seed: u64) -> Self {
+        self.map(|m| m.with_deterministic_seed(seed))
+    }
+
+    /// See `Manifest::with_trusted_key`
+    pub fn with_trusted_key(self, key: TrustedKey) -> Self {
+        self.map(|m| m.with_trusted_key(key))
+    }
+
+    /// Validate the manifest using `Manifest::validate` and return it if no problems are found
+    pub fn build(self) -> Result<Manifest, BuildError> {
+        let problems = self.manifest.validate();
+        if problems.is_empty() {
+            Ok(self.manifest)
+        } else {
+            Err(BuildError { problems })
+        }
+    }
+}
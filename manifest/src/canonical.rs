@@ -0,0 +1,54 @@
+use crate::Manifest;
+
+// Write `value` as compact JSON with object keys in sorted order, this doesn't depend on whether `serde_json`
+// preserves insertion order
+fn write_canonical(value: &serde_json::Value, out: &mut Vec<u8>) -> Result<(), serde_json::Error> {
+    match value {
+        serde_json::Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out)?;
+            }
+            out.push(b']');
+        }
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push(b'{');
+            for (i, k) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, k)?;
+                out.push(b':');
+                write_canonical(&map[k], out)?;
+            }
+            out.push(b'}');
+        }
+        v => serde_json::to_writer(&mut *out, v)?,
+    }
+    Ok(())
+}
+
+impl Manifest {
+    /// Encode the manifest as compact JSON with sorted keys. Module data is always encoded as padded base64 and
+    /// unset fields are omitted, so manifests that deserialize to the same value produce the same bytes on any host.
+    /// An error is only returned if a path isn't valid UTF-8.
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>, serde_json::Error> {
+        let value = serde_json::to_value(self)?;
+        let mut out = Vec::new();
+        write_canonical(&value, &mut out)?;
+        Ok(out)
+    }
+
+    /// Hex-encoded SHA-256 digest of `canonical_bytes`, this can be used to cache anything derived from the
+    /// manifest. Modules referenced by a path or URL aren't included, only the reference is.
+    #[cfg(feature = "digest")]
+    pub fn content_hash(&self) -> Result<String, serde_json::Error> {
+        let data = self.canonical_bytes()?;
+        Ok(crate::HashAlgorithm::Sha256.digest(&data))
+    }
+}
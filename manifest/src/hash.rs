@@ -0,0 +1,113 @@
+/// Digest algorithms that can be used in `WasmMetadata::hash`. The algorithm is selected using a prefix, like
+/// `sha512:<hex digest>`, hashes without a prefix are SHA-256.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// SHA-256, the default
+    Sha256,
+
+    /// SHA-512
+    Sha512,
+
+    /// BLAKE3 with a 32 byte output
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// The prefix used to select the algorithm
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// The length of a hex-encoded digest
+    pub fn hex_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 64,
+            HashAlgorithm::Sha512 => 128,
+        }
+    }
+
+    /// Split a hash into its algorithm and hex-encoded digest, `None` is returned when the prefix isn't a
+    /// supported algorithm
+    pub fn split(hash: &str) -> Option<(HashAlgorithm, &str)> {
+        let (name, digest) = match hash.split_once(':') {
+            Some(x) => x,
+            None => return Some((HashAlgorithm::Sha256, hash)),
+        };
+        let alg = match name.to_ascii_lowercase().as_str() {
+            "sha256" => HashAlgorithm::Sha256,
+            "sha512" => HashAlgorithm::Sha512,
+            "blake3" => HashAlgorithm::Blake3,
+            _ => return None,
+        };
+        Some((alg, digest))
+    }
+
+    /// Compute the hex-encoded digest of `data`
+    #[cfg(feature = "digest")]
+    pub fn digest(&self, data: &[u8]) -> String {
+        let mut hasher = Hasher::new(*self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// Computes a digest incrementally using any `HashAlgorithm`
+#[cfg(feature = "digest")]
+pub struct Hasher(HasherInner);
+
+#[cfg(feature = "digest")]
+enum HasherInner {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+#[cfg(feature = "digest")]
+impl Hasher {
+    /// Create a new hasher
+    pub fn new(alg: HashAlgorithm) -> Hasher {
+        use sha2::Digest;
+
+        Hasher(match alg {
+            HashAlgorithm::Sha256 => HasherInner::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Sha512 => HasherInner::Sha512(sha2::Sha512::new()),
+            HashAlgorithm::Blake3 => HasherInner::Blake3(Box::new(blake3::Hasher::new())),
+        })
+    }
+
+    /// Add `data` to the digest
+    pub fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        match &mut self.0 {
+            HasherInner::Sha256(h) => h.update(data),
+            HasherInner::Sha512(h) => h.update(data),
+            HasherInner::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// Returns the hex-encoded digest
+    pub fn finalize(self) -> String {
+        use sha2::Digest;
+        use std::fmt::Write;
+
+        let mut s = String::new();
+        let mut hex = |bytes: &[u8]| {
+            for byte in bytes {
+                write!(&mut s, "{:02x}", byte).unwrap();
+            }
+        };
+        match self.0 {
+            HasherInner::Sha256(h) => hex(&h.finalize()),
+            HasherInner::Sha512(h) => hex(&h.finalize()),
+            HasherInner::Blake3(h) => hex(h.finalize().as_bytes()),
+        }
+        s
+    }
+}
@@ -1,22 +1,152 @@
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+mod builder;
+mod canonical;
+mod hash;
+mod lint;
+mod lock;
+mod migrate;
+mod redact;
+mod validate;
+mod wasm_serde;
+
+pub use builder::{BuildError, ManifestBuilder};
+pub use hash::HashAlgorithm;
+#[cfg(feature = "digest")]
+pub use hash::Hasher;
+pub use lint::Warning;
+pub use lock::{LockError, LockedModule, Lockfile, LOCKFILE_VERSION};
+pub use migrate::MANIFEST_VERSION;
+pub use redact::REDACTED;
+pub use validate::Problem;
+
 #[deprecated]
 pub type ManifestMemory = MemoryOptions;
 
 /// Configure memory settings
-#[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Default, Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(deny_unknown_fields)]
 pub struct MemoryOptions {
-    /// The max number of WebAssembly pages that should be allocated
+    /// The max number of WebAssembly pages that should be allocated. The `max` alias is from version 0 manifests,
+    /// `Manifest::migrate_from_value` renames it.
     #[serde(alias = "max")]
     pub max_pages: Option<u32>,
+
+    /// The max size of a response body read by the `extism_http_request` host function, requests with larger
+    /// responses fail. By default this is `DEFAULT_MAX_HTTP_RESPONSE_BYTES`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_http_response_bytes: Option<u64>,
+
+    /// The max size of the native stack used by WebAssembly code, calls that use more stack space fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_stack_bytes: Option<u64>,
+
+    /// The max number of elements in each table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_table_elements: Option<u32>,
+
+    /// The max number of module instances a plugin can create, including the modules linked into it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_instances: Option<u32>,
+}
+
+/// Configure which WASI capabilities are granted to a plugin, these only apply when the plugin is created with
+/// WASI enabled. Unset values use the default listed for each field.
+#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields)]
+pub struct WasiOptions {
+    /// Allow `random_get`, enabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub random: Option<bool>,
+
+    /// Allow reading the system and monotonic clocks, enabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clocks: Option<bool>,
+
+    /// Expose `config` as environment variables, enabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<bool>,
+
+    /// Environment variables for the plugin, these are set even when `environment` is disabled and take
+    /// precedence over `config` values with the same name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// Write stdout to the host's stdout, by default this is only enabled when the `EXTISM_ENABLE_WASI_OUTPUT`
+    /// environment variable is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<bool>,
+
+    /// Write stderr to the host's stderr, by default this is only enabled when the `EXTISM_ENABLE_WASI_OUTPUT`
+    /// environment variable is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<bool>,
+
+    /// Command line arguments for the plugin, including the program name. When this is empty the plugin has
+    /// no arguments, unless `inherit_args` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+
+    /// Pass the host process's command line arguments when `args` is empty, disabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherit_args: Option<bool>,
+
+    /// Use wasmtime's WASI preview 2 implementation, the preview 1 imports used by plugins are implemented on top
+    /// of it. Disabled by default, `random` and `clocks` can't be disabled when this is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview2: Option<bool>,
+
+    /// Models the plugin may load using wasi-nn, see `NnOptions`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub nn: NnOptions,
+}
+
+/// Configure which models a plugin may load using wasi-nn, these are only used when the runtime is built with the
+/// `wasi-nn` feature. By default no models can be loaded.
+#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields)]
+pub struct NnOptions {
+    /// Models that can be loaded using `load_by_name`, this is a mapping from the name used by the plugin to a
+    /// directory containing the model files. Models are loaded when the plugin is created.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub models: BTreeMap<String, PathBuf>,
+
+    /// Hashes of the model files the plugin may pass to `load`, every buffer must match one of these. Hashes use
+    /// the same format as `WasmMetadata::hash`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hashes: Vec<String>,
+}
+
+/// The response size limit used when `MemoryOptions::max_http_response_bytes` isn't set
+pub const DEFAULT_MAX_HTTP_RESPONSE_BYTES: u64 = 1024 * 1024 * 50;
+
+/// Cranelift optimization level
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "snake_case")]
+pub enum OptLevel {
+    /// No optimizations, this minimizes compilation time
+    None,
+
+    /// Generate the fastest possible code, this is the default
+    Speed,
+
+    /// Similar to `Speed`, but also reduces code size
+    SpeedAndSize,
 }
 
 /// Generic HTTP request structure
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(deny_unknown_fields)]
 pub struct HttpRequest {
     /// The request URL
@@ -29,8 +159,57 @@ pub struct HttpRequest {
 
     /// Request method
     pub method: Option<String>,
+
+    /// Request body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// The request timeout in milliseconds, by default requests don't time out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Follow redirects, by default this is `true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow_redirects: Option<bool>,
+
+    /// The maximum number of redirects to follow, by default this is `DEFAULT_MAX_REDIRECTS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_redirects: Option<u32>,
+}
+
+/// The number of redirects followed when `HttpRequest::max_redirects` isn't set
+pub const DEFAULT_MAX_REDIRECTS: u32 = 5;
+
+/// Retry settings used when downloading a `Wasm::Url` module. Connection errors are always retried, HTTP errors are
+/// only retried if the status code is listed in `status_codes`.
+#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields)]
+pub struct RetryOptions {
+    /// The total number of attempts, by default this is `DEFAULT_RETRY_ATTEMPTS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+
+    /// The delay before the first retry in milliseconds, the delay is doubled after each attempt. By default this
+    /// is `DEFAULT_RETRY_BACKOFF_MS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff_ms: Option<u64>,
+
+    /// HTTP status codes that are retried, by default this is `DEFAULT_RETRY_STATUS_CODES`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_codes: Option<Vec<u16>>,
 }
 
+/// The number of attempts made when `RetryOptions::attempts` isn't set
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+
+/// The initial delay between attempts when `RetryOptions::backoff_ms` isn't set
+pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 250;
+
+/// The status codes that are retried when `RetryOptions::status_codes` isn't set
+pub const DEFAULT_RETRY_STATUS_CODES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
 impl HttpRequest {
     /// Create a new `HttpRequest` to the given URL
     pub fn new(url: impl Into<String>) -> HttpRequest {
@@ -38,6 +217,10 @@ impl HttpRequest {
             url: url.into(),
             headers: Default::default(),
             method: None,
+            body: None,
+            timeout_ms: None,
+            follow_redirects: None,
+            max_redirects: None,
         }
     }
 
@@ -52,24 +235,268 @@ impl HttpRequest {
         self.headers.insert(key.into(), value.into());
         self
     }
+
+    /// Set the `Authorization` header to a bearer token
+    pub fn with_bearer_token(self, token: impl AsRef<str>) -> HttpRequest {
+        self.with_header("Authorization", format!("Bearer {}", token.as_ref()))
+    }
+
+    /// Set the `Authorization` header using HTTP basic authentication
+    pub fn with_basic_auth(
+        self,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+    ) -> HttpRequest {
+        use ::base64::Engine;
+
+        let credentials = format!("{}:{}", username.as_ref(), password.as_ref());
+        let encoded = ::base64::engine::general_purpose::STANDARD.encode(credentials);
+        self.with_header("Authorization", format!("Basic {encoded}"))
+    }
+
+    /// Append a percent-encoded query parameter to the URL
+    pub fn with_query_param(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> HttpRequest {
+        fn encode(s: &str) -> String {
+            let mut out = String::with_capacity(s.len());
+            for byte in s.bytes() {
+                if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                    out.push(byte as char);
+                } else {
+                    out.push_str(&format!("%{byte:02X}"));
+                }
+            }
+            out
+        }
+
+        let (url, fragment) = match self.url.split_once('#') {
+            Some((url, fragment)) => (url.to_string(), Some(fragment.to_string())),
+            None => (self.url.clone(), None),
+        };
+        let sep = if !url.contains('?') {
+            "?"
+        } else if url.ends_with('?') || url.ends_with('&') {
+            ""
+        } else {
+            "&"
+        };
+        self.url = format!(
+            "{url}{sep}{}={}",
+            encode(key.as_ref()),
+            encode(value.as_ref())
+        );
+        if let Some(fragment) = fragment {
+            self.url.push('#');
+            self.url.push_str(&fragment);
+        }
+        self
+    }
+
+    /// Set the request body
+    pub fn with_body(mut self, body: impl Into<String>) -> HttpRequest {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Set the request timeout
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> HttpRequest {
+        self.timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    /// Set whether redirects are followed
+    pub fn with_follow_redirects(mut self, follow: bool) -> HttpRequest {
+        self.follow_redirects = Some(follow);
+        self
+    }
+
+    /// Set the maximum number of redirects to follow
+    pub fn with_max_redirects(mut self, n: u32) -> HttpRequest {
+        self.max_redirects = Some(n);
+        self
+    }
+
+    /// The number of redirects that should be followed, taking `follow_redirects` into account
+    pub fn redirect_limit(&self) -> u32 {
+        if self.follow_redirects == Some(false) {
+            return 0;
+        }
+        self.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS)
+    }
 }
 
 /// Provides additional metadata about a Webassembly module
 #[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(deny_unknown_fields)]
 pub struct WasmMetadata {
     /// Module name, this is used by Extism to determine which is the `main` module
     pub name: Option<String>,
 
-    /// Module hash, if the data loaded from disk or via HTTP doesn't match an error will be raised
+    /// Module hash, if the data loaded from disk or via HTTP doesn't match an error will be raised. For encrypted
+    /// modules this is the hash of the encrypted data. This is a hex-encoded SHA-256 digest unless it's prefixed
+    /// with another algorithm, like `sha512:` or `blake3:`, see `HashAlgorithm`.
     pub hash: Option<String>,
+
+    /// Set when the module is encrypted, the key is requested from the host when the module is loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+
+    /// How the module data is compressed, when this isn't set the compression is detected from the data.
+    /// Modules are decompressed before the hash is checked, so `hash` always refers to the decompressed data.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Encoding>,
+
+    /// Signature over the module data, either a base64-encoded ed25519 signature or the contents of a minisign
+    /// signature file. Like `hash` this covers the decompressed data. Signatures are checked against
+    /// `Manifest::trusted_keys` before the module is compiled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+
+    /// Config values that are only visible to this module, these shadow the top-level `config` values with the
+    /// same key when the module calls `extism_config_get`
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "config_values::deserialize"
+    )]
+    #[cfg_attr(
+        feature = "json_schema",
+        schemars(with = "BTreeMap<String, serde_json::Value>")
+    )]
+    pub config: BTreeMap<String, String>,
+
+    /// Module config values that are read from files, see `Manifest::config_secrets`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_secrets: BTreeMap<String, PathBuf>,
+}
+
+/// Compression formats for module data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Uncompressed, this disables detection
+    Identity,
+
+    /// gzip
+    Gzip,
+
+    /// Zstandard
+    Zstd,
+}
+
+/// The largest module `WasmMetadata::decompress` will produce, 256MiB
+pub const MAX_DECOMPRESSED_BYTES: u64 = 1024 * 1024 * 256;
+
+impl WasmMetadata {
+    /// Decompress module data using `encoding`, or the detected compression format if `encoding` isn't set.
+    /// Encrypted modules are only decompressed when `encoding` is set, since the encrypted data could look
+    /// like a compressed stream. Decompression fails if the output is larger than `MAX_DECOMPRESSED_BYTES`.
+    pub fn decompress<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> Result<std::borrow::Cow<'a, [u8]>, std::io::Error> {
+        let encoding = match self.encoding {
+            Some(x) => x,
+            None if self.encryption.is_some() => Encoding::Identity,
+            None => Encoding::detect(data).unwrap_or(Encoding::Identity),
+        };
+
+        match encoding {
+            Encoding::Identity => Ok(std::borrow::Cow::Borrowed(data)),
+            #[cfg(feature = "compression")]
+            Encoding::Gzip => read_limited(flate2::read::GzDecoder::new(data)),
+            #[cfg(feature = "compression")]
+            Encoding::Zstd => read_limited(zstd::stream::read::Decoder::new(data)?),
+            #[cfg(not(feature = "compression"))]
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{encoding:?} compressed modules require the `compression` feature"),
+            )),
+        }
+    }
+}
+
+// Read decompressed data, failing once it's larger than `MAX_DECOMPRESSED_BYTES`
+#[cfg(feature = "compression")]
+fn read_limited<'a>(r: impl std::io::Read) -> Result<std::borrow::Cow<'a, [u8]>, std::io::Error> {
+    use std::io::Read;
+
+    let mut buf = Vec::new();
+    r.take(MAX_DECOMPRESSED_BYTES + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Decompressed module is larger than the limit of {MAX_DECOMPRESSED_BYTES} bytes"
+            ),
+        ));
+    }
+    Ok(std::borrow::Cow::Owned(buf))
+}
+
+impl Encoding {
+    /// Detect the compression format from the first bytes of `data`, `None` is returned for uncompressed data
+    pub fn detect(data: &[u8]) -> Option<Encoding> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Encoding::Gzip)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Encoding::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Algorithms that can be used to encrypt a module
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionAlgorithm {
+    /// AES-256 in GCM mode
+    Aes256Gcm,
+
+    /// ChaCha20-Poly1305
+    Chacha20Poly1305,
+}
+
+/// A public key trusted to sign modules, see `WasmMetadata::signature`
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TrustedKey {
+    /// Base64-encoded 32 byte ed25519 public key, used to verify raw base64-encoded signatures
+    Ed25519 { key: String },
+
+    /// minisign public key, as printed by `minisign -G`, used to verify minisign signature files
+    Minisign { key: String },
+}
+
+/// Describes how to decrypt an encrypted module, both supported algorithms use a 256-bit key and a 96-bit nonce
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(deny_unknown_fields)]
+pub struct Encryption {
+    /// Encryption algorithm
+    pub algorithm: EncryptionAlgorithm,
+
+    /// Identifies the key used to encrypt the module, this is passed to the host's key provider
+    pub key_id: String,
+
+    /// Base64 encoded nonce
+    pub nonce: String,
 }
 
 impl From<HttpRequest> for Wasm {
     fn from(req: HttpRequest) -> Self {
         Wasm::Url {
             req,
+            retry: None,
             meta: WasmMetadata::default(),
         }
     }
@@ -96,9 +523,11 @@ impl From<Vec<u8>> for Wasm {
 #[deprecated]
 pub type ManifestWasm = Wasm;
 
-/// The `Wasm` type specifies how to access a WebAssembly module
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+/// The `Wasm` type specifies how to access a WebAssembly module. Modules are deserialized using
+/// `Wasm::from_value`, so a `kind` field can be used to choose the variant explicitly.
+#[derive(Clone, serde::Serialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(untagged)]
 #[serde(deny_unknown_fields)]
 pub enum Wasm {
@@ -122,9 +551,83 @@ pub enum Wasm {
     Url {
         #[serde(flatten)]
         req: HttpRequest,
+        /// Retry failed downloads, by default the module is only requested once
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry: Option<RetryOptions>,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+
+    /// A module that was precompiled by the host's engine, the engine compatibility is checked before it's loaded.
+    /// Precompiled modules are loaded without being validated, so the runtime only loads them when the host
+    /// allows it.
+    Precompiled {
+        #[serde(rename = "precompiled")]
+        path: PathBuf,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+
+    /// From an OCI registry
+    Registry {
+        /// OCI reference, for example `oci://ghcr.io/org/plugin:1.2.0`. A digest can be used instead of a tag to
+        /// pin the artifact: `oci://ghcr.io/org/plugin@sha256:...`
+        registry: String,
+
+        /// Registry credentials
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auth: Option<RegistryAuth>,
+
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+
+    /// Every file in a directory that matches a pattern, the modules are named after their file names without
+    /// the extension and linked in sorted order. `meta.name` and `meta.hash` are ignored, the rest of the
+    /// metadata is used for every module.
+    Dir {
+        #[serde(rename = "dir")]
+        path: PathBuf,
+
+        /// Glob pattern matched against the file names, by default this is `*.wasm`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+
         #[serde(flatten)]
         meta: WasmMetadata,
     },
+
+    /// A `.tar`, `.tar.gz` or `.zip` archive containing one or more modules, entries are read into memory. When
+    /// the archive contains a `manifest.json` or `manifest.toml` at its root, the modules it lists are loaded
+    /// and it's included like an entry in `Manifest::include`, file paths in that manifest refer to archive
+    /// entries. Otherwise every entry that matches `pattern` is loaded and named like the modules in a
+    /// `Wasm::Dir`. `meta.hash` is checked against the archive itself and only `meta.config` is used for the
+    /// modules.
+    Archive {
+        #[serde(rename = "archive")]
+        path: PathBuf,
+
+        /// Glob pattern matched against the entry paths, by default this is `*.wasm`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+}
+
+/// Credentials used to pull modules from an OCI registry
+#[derive(Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(untagged)]
+#[serde(deny_unknown_fields)]
+pub enum RegistryAuth {
+    /// Username and password, these are exchanged for a token when the registry requires one
+    Basic { username: String, password: String },
+
+    /// A pre-issued bearer token
+    Bearer { token: String },
 }
 
 impl Wasm {
@@ -148,6 +651,51 @@ impl Wasm {
     pub fn url(req: HttpRequest) -> Self {
         Wasm::Url {
             req,
+            retry: None,
+            meta: Default::default(),
+        }
+    }
+
+    /// Load Wasm from a URL, retrying failed downloads
+    pub fn url_with_retry(req: HttpRequest, retry: RetryOptions) -> Self {
+        Wasm::Url {
+            req,
+            retry: Some(retry),
+            meta: Default::default(),
+        }
+    }
+
+    /// Load a precompiled module from a path
+    pub fn precompiled(path: impl AsRef<std::path::Path>) -> Self {
+        Wasm::Precompiled {
+            path: path.as_ref().to_path_buf(),
+            meta: Default::default(),
+        }
+    }
+
+    /// Load Wasm from an OCI registry, `reference` should start with `oci://`
+    pub fn registry(reference: impl Into<String>) -> Self {
+        Wasm::Registry {
+            registry: reference.into(),
+            auth: None,
+            meta: Default::default(),
+        }
+    }
+
+    /// Load every module in a directory, see `Wasm::Dir`
+    pub fn dir(path: impl AsRef<std::path::Path>, pattern: Option<&str>) -> Self {
+        Wasm::Dir {
+            path: path.as_ref().to_path_buf(),
+            pattern: pattern.map(|x| x.to_string()),
+            meta: Default::default(),
+        }
+    }
+
+    /// Load the modules in an archive, see `Wasm::Archive`
+    pub fn archive(path: impl AsRef<std::path::Path>, pattern: Option<&str>) -> Self {
+        Wasm::Archive {
+            path: path.as_ref().to_path_buf(),
+            pattern: pattern.map(|x| x.to_string()),
             meta: Default::default(),
         }
     }
@@ -157,7 +705,11 @@ impl Wasm {
         match self {
             Wasm::File { path: _, meta } => meta,
             Wasm::Data { data: _, meta } => meta,
-            Wasm::Url { req: _, meta } => meta,
+            Wasm::Url { meta, .. } => meta,
+            Wasm::Precompiled { path: _, meta } => meta,
+            Wasm::Registry { meta, .. } => meta,
+            Wasm::Dir { meta, .. } => meta,
+            Wasm::Archive { meta, .. } => meta,
         }
     }
 
@@ -166,7 +718,11 @@ impl Wasm {
         match self {
             Wasm::File { path: _, meta } => meta,
             Wasm::Data { data: _, meta } => meta,
-            Wasm::Url { req: _, meta } => meta,
+            Wasm::Url { meta, .. } => meta,
+            Wasm::Precompiled { path: _, meta } => meta,
+            Wasm::Registry { meta, .. } => meta,
+            Wasm::Dir { meta, .. } => meta,
+            Wasm::Archive { meta, .. } => meta,
         }
     }
 }
@@ -179,11 +735,116 @@ fn base64_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::
     schema.into()
 }
 
+/// A manifest included by another manifest, relative paths are resolved from the directory of the including
+/// manifest
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(untagged)]
+#[serde(deny_unknown_fields)]
+pub enum Include {
+    /// From disk
+    File { path: PathBuf },
+
+    /// Via HTTP
+    Url {
+        #[serde(flatten)]
+        req: HttpRequest,
+    },
+}
+
+impl Include {
+    // Returns `true` if both includes refer to the same manifest
+    fn same_source(&self, other: &Include) -> bool {
+        match (self, other) {
+            (Include::File { path: a }, Include::File { path: b }) => a == b,
+            (Include::Url { req: a }, Include::Url { req: b }) => a.url == b.url,
+            _ => false,
+        }
+    }
+}
+
+impl From<PathBuf> for Include {
+    fn from(path: PathBuf) -> Self {
+        Include::File { path }
+    }
+}
+
+impl From<HttpRequest> for Include {
+    fn from(req: HttpRequest) -> Self {
+        Include::Url { req }
+    }
+}
+
+/// A directory mounted using WASI, either the path inside the plugin or a mount with options:
+/// `{"path": "/data", "readonly": true}`
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(untagged)]
+pub enum AllowedPath {
+    /// A writable mount at the given path
+    Path(PathBuf),
+
+    /// A mount with options
+    Mount {
+        /// The path inside the plugin
+        path: PathBuf,
+
+        /// Disallow creating, modifying or removing files
+        #[serde(default)]
+        readonly: bool,
+    },
+}
+
+impl AllowedPath {
+    /// Create a read-only mount at `path`
+    pub fn readonly(path: impl AsRef<Path>) -> AllowedPath {
+        AllowedPath::Mount {
+            path: path.as_ref().to_path_buf(),
+            readonly: true,
+        }
+    }
+
+    /// The path inside the plugin
+    pub fn path(&self) -> &Path {
+        match self {
+            AllowedPath::Path(p) => p,
+            AllowedPath::Mount { path, .. } => path,
+        }
+    }
+
+    /// Returns `true` if the plugin can't write to the mount
+    pub fn is_readonly(&self) -> bool {
+        match self {
+            AllowedPath::Path(_) => false,
+            AllowedPath::Mount { readonly, .. } => *readonly,
+        }
+    }
+}
+
+impl From<PathBuf> for AllowedPath {
+    fn from(path: PathBuf) -> Self {
+        AllowedPath::Path(path)
+    }
+}
+
+impl From<&Path> for AllowedPath {
+    fn from(path: &Path) -> Self {
+        AllowedPath::Path(path.to_path_buf())
+    }
+}
+
 /// The `Manifest` type is used to configure the runtime and specify how to load modules.
 #[derive(Default, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(deny_unknown_fields)]
 pub struct Manifest {
+    /// The manifest format version, see `MANIFEST_VERSION`. This is set by `Manifest::migrate_from_value`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+
     /// WebAssembly modules, the `main` module should be named `main` or listed last
     #[serde(default)]
     pub wasm: Vec<Wasm>,
@@ -191,24 +852,98 @@ pub struct Manifest {
     #[serde(default)]
     pub memory: MemoryOptions,
 
-    /// Config values are made accessible using the PDK `extism_config_get` function
-    #[serde(default)]
+    /// Config values are made accessible using the PDK `extism_config_get` function. Values may be any JSON
+    /// value, anything other than a string is stored as JSON text, see `Manifest::config_get`.
+    #[serde(default, deserialize_with = "config_values::deserialize")]
+    #[cfg_attr(
+        feature = "json_schema",
+        schemars(with = "BTreeMap<String, serde_json::Value>")
+    )]
     pub config: BTreeMap<String, String>,
+
+    /// Config values that are read from files when a plugin is created, so secrets don't need to be stored in the
+    /// manifest. This maps config keys to file paths, relative paths are resolved like module paths. A `config`
+    /// value like `{"file": "/run/secrets/key"}` is moved here by `Manifest::migrate_from_value`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config_secrets: BTreeMap<String, PathBuf>,
+
     #[serde(default)]
 
     /// Specifies which hosts may be accessed via HTTP, if this is empty then
-    /// no hosts may be accessed. Wildcards may be used.
+    /// no hosts may be accessed. Wildcards may be used. Entries can also include a port (`example.com:8080`)
+    /// or be an origin (`https://example.com`) to restrict the scheme and port, an origin without a port only
+    /// allows the default port for its scheme. IP addresses (`10.1.2.3`) and CIDR ranges (`10.0.0.0/8`) may also
+    /// be used. Hosts matched by a wildcard pattern, like `*.example.com`, can only resolve to internal addresses
+    /// (loopback, private and other non-public ranges) that are allowed this way. A bare `*` allows every host,
+    /// including internal addresses, use `denied_hosts` to block address ranges.
     pub allowed_hosts: Option<Vec<String>>,
 
+    /// Hosts that may not be accessed via HTTP, even if they're matched by `allowed_hosts`. Entries use the same
+    /// format as `allowed_hosts` and a request is denied if it matches any of them, so `*.example.com` can be
+    /// allowed while `internal.example.com` is denied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_hosts: Vec<String>,
+
     /// Specifies which paths should be made available on disk when using WASI. This is a mapping from
     /// this is a mapping from the path on disk to the path it should be available inside the plugin.
-    /// For example, `".": "/tmp"` would mount the current directory as `/tmp` inside the module
+    /// For example, `".": "/tmp"` would mount the current directory as `/tmp` inside the module. A mount
+    /// can be made read-only using `".": {"path": "/tmp", "readonly": true}`
     #[serde(default)]
-    pub allowed_paths: Option<BTreeMap<PathBuf, PathBuf>>,
+    pub allowed_paths: Option<BTreeMap<PathBuf, AllowedPath>>,
 
     /// The plugin timeout, by default this is set to 30s
     #[serde(default = "default_timeout")]
     pub timeout_ms: Option<u64>,
+
+    /// Timeouts for specific functions, these replace `timeout_ms` when the named function is called
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub function_timeouts: BTreeMap<String, u64>,
+
+    /// The Cranelift optimization level used when compiling the modules, if this is not set then
+    /// the runtime default is used
+    #[serde(default)]
+    pub opt_level: Option<OptLevel>,
+
+    /// Other manifests to include, their modules are loaded before the modules listed in `wasm` and their
+    /// config values are used unless they're also set in this manifest. Included manifests can't allow any
+    /// hosts or paths that this manifest doesn't.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Include>,
+
+    /// A base manifest, this manifest is merged on top of it using `Manifest::merge`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<Include>,
+
+    /// Keys trusted to sign modules. When this isn't empty every module must have a `signature` made by one of
+    /// these keys, modules without a valid signature fail to load.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<TrustedKey>,
+
+    /// WASI capabilities, see `WasiOptions`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub wasi: WasiOptions,
+
+    /// The max number of calls that should run in parallel, each call uses its own plugin instance. Pools like
+    /// `WarmPool` and the `serve` module don't exceed this, by default parallelism is only limited by the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_calls: Option<u32>,
+
+    /// The max number of plugin instances a pool may create from this manifest. This is different from
+    /// `MemoryOptions::max_instances`, which limits the module instances inside a single plugin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_instances: Option<u32>,
+
+    /// Run the plugin deterministically so the same input always produces the same output: floating point NaNs
+    /// are canonicalized, WASI random data is generated from this seed, WASI clocks are virtual and HTTP requests
+    /// fail. Plugins that use threads or wasi-nn models can't be created in deterministic mode. Host functions
+    /// are called as usual, so they need to be deterministic themselves, and `poll_oneoff` still sleeps in real
+    /// time, when it waits on files as well as clocks the subscriptions that are ready can depend on the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deterministic_seed: Option<u64>,
+}
+
+fn is_default<T: Default + PartialEq>(x: &T) -> bool {
+    *x == T::default()
 }
 
 fn default_timeout() -> Option<u64> {
@@ -240,10 +975,16 @@ impl Manifest {
     /// Set MemoryOptions::memory_max
     pub fn with_memory_max(mut self, max: u32) -> Self {
         self.memory.max_pages = Some(max);
-        return self;
+        self
+    }
+
+    /// Set MemoryOptions::max_http_response_bytes
+    pub fn with_max_http_response_bytes(mut self, max: u64) -> Self {
+        self.memory.max_http_response_bytes = Some(max);
+        self
     }
 
-    /// Add a hostname to `allowed_hosts`
+    /// Add a hostname or origin to `allowed_hosts`
     pub fn with_allowed_host(mut self, host: impl Into<String>) -> Self {
         match &mut self.allowed_hosts {
             Some(h) => {
@@ -255,16 +996,31 @@ impl Manifest {
         self
     }
 
+    /// Add a hostname or origin to `denied_hosts`
+    pub fn with_denied_host(mut self, host: impl Into<String>) -> Self {
+        self.denied_hosts.push(host.into());
+        self
+    }
+
     /// Set `allowed_hosts`
     pub fn with_allowed_hosts(mut self, hosts: impl Iterator<Item = String>) -> Self {
         self.allowed_hosts = Some(hosts.collect());
         self
     }
 
-    /// Add a path to `allowed_paths`
-    pub fn with_allowed_path(mut self, src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Self {
+    /// Add a writable path to `allowed_paths`
+    pub fn with_allowed_path(self, src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Self {
+        self.with_mount(src, AllowedPath::from(dest.as_ref()))
+    }
+
+    /// Add a read-only path to `allowed_paths`, the plugin can read files in `src` but can't create, modify or
+    /// remove them
+    pub fn with_readonly_path(self, src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Self {
+        self.with_mount(src, AllowedPath::readonly(dest))
+    }
+
+    fn with_mount(mut self, src: impl AsRef<Path>, dest: AllowedPath) -> Self {
         let src = src.as_ref().to_path_buf();
-        let dest = dest.as_ref().to_path_buf();
         match &mut self.allowed_paths {
             Some(p) => {
                 p.insert(src, dest);
@@ -280,8 +1036,11 @@ impl Manifest {
     }
 
     /// Set `allowed_paths`
-    pub fn with_allowed_paths(mut self, paths: impl Iterator<Item = (PathBuf, PathBuf)>) -> Self {
-        self.allowed_paths = Some(paths.collect());
+    pub fn with_allowed_paths(
+        mut self,
+        paths: impl Iterator<Item = (PathBuf, impl Into<AllowedPath>)>,
+    ) -> Self {
+        self.allowed_paths = Some(paths.map(|(k, v)| (k, v.into())).collect());
         self
     }
 
@@ -296,12 +1055,217 @@ impl Manifest {
         self
     }
 
+    /// Set the base manifest, see `Manifest::merge`
+    pub fn with_extends(mut self, base: impl Into<Include>) -> Self {
+        self.extends = Some(base.into());
+        self
+    }
+
+    /// Merge two manifests, settings from `overlay` take precedence over settings from `base`:
+    ///
+    /// - `wasm`: the modules from `overlay` replace the modules from `base`, unless `overlay` has no modules
+    /// - `config`, `config_secrets`, `function_timeouts`, `allowed_paths` and `wasi.env`: both maps are combined, keys from `overlay` replace keys from `base`
+    /// - `allowed_hosts`, `denied_hosts` and `include`: both lists are combined and duplicates are removed. An empty
+    ///   `allowed_hosts` list in `overlay` (see `Manifest::disallow_all_hosts`) disallows all hosts.
+    /// - `memory`, `wasi`, `opt_level`, `extends`, `max_concurrent_calls`, `max_instances` and
+    ///   `deterministic_seed`: the value from `overlay` is used if it's set
+    /// - `timeout_ms`: the value from `overlay` is used unless it's unset or the default timeout
+    pub fn merge(base: Manifest, overlay: Manifest) -> Manifest {
+        let wasm = if overlay.wasm.is_empty() {
+            base.wasm
+        } else {
+            overlay.wasm
+        };
+
+        let mut config = base.config;
+        config.extend(overlay.config);
+
+        let mut config_secrets = base.config_secrets;
+        config_secrets.extend(overlay.config_secrets);
+
+        let mut function_timeouts = base.function_timeouts;
+        function_timeouts.extend(overlay.function_timeouts);
+
+        let allowed_paths = match (base.allowed_paths, overlay.allowed_paths) {
+            (Some(mut base), Some(overlay)) => {
+                base.extend(overlay);
+                Some(base)
+            }
+            (base, overlay) => overlay.or(base),
+        };
+
+        let allowed_hosts = match (base.allowed_hosts, overlay.allowed_hosts) {
+            (_, Some(overlay)) if overlay.is_empty() => Some(overlay),
+            (Some(mut base), Some(overlay)) => {
+                for host in overlay {
+                    if !base.contains(&host) {
+                        base.push(host);
+                    }
+                }
+                Some(base)
+            }
+            (base, overlay) => overlay.or(base),
+        };
+
+        let mut denied_hosts = base.denied_hosts;
+        for host in overlay.denied_hosts {
+            if !denied_hosts.contains(&host) {
+                denied_hosts.push(host);
+            }
+        }
+
+        let mut include = base.include;
+        for i in overlay.include {
+            if !include.iter().any(|x| x.same_source(&i)) {
+                include.push(i);
+            }
+        }
+
+        let mut trusted_keys = base.trusted_keys;
+        for key in overlay.trusted_keys {
+            if !trusted_keys.contains(&key) {
+                trusted_keys.push(key);
+            }
+        }
+
+        Manifest {
+            version: overlay.version.or(base.version),
+            wasm,
+            memory: MemoryOptions {
+                max_pages: overlay.memory.max_pages.or(base.memory.max_pages),
+                max_http_response_bytes: overlay
+                    .memory
+                    .max_http_response_bytes
+                    .or(base.memory.max_http_response_bytes),
+                max_stack_bytes: overlay
+                    .memory
+                    .max_stack_bytes
+                    .or(base.memory.max_stack_bytes),
+                max_table_elements: overlay
+                    .memory
+                    .max_table_elements
+                    .or(base.memory.max_table_elements),
+                max_instances: overlay.memory.max_instances.or(base.memory.max_instances),
+            },
+            config,
+            config_secrets,
+            allowed_hosts,
+            denied_hosts,
+            allowed_paths,
+            timeout_ms: if overlay.timeout_ms.is_none() || overlay.timeout_ms == default_timeout() {
+                base.timeout_ms
+            } else {
+                overlay.timeout_ms
+            },
+            function_timeouts,
+            opt_level: overlay.opt_level.or(base.opt_level),
+            include,
+            extends: overlay.extends.or(base.extends),
+            trusted_keys,
+            wasi: WasiOptions {
+                random: overlay.wasi.random.or(base.wasi.random),
+                clocks: overlay.wasi.clocks.or(base.wasi.clocks),
+                environment: overlay.wasi.environment.or(base.wasi.environment),
+                env: {
+                    let mut env = base.wasi.env;
+                    env.extend(overlay.wasi.env);
+                    env
+                },
+                stdout: overlay.wasi.stdout.or(base.wasi.stdout),
+                stderr: overlay.wasi.stderr.or(base.wasi.stderr),
+                args: if overlay.wasi.args.is_empty() {
+                    base.wasi.args
+                } else {
+                    overlay.wasi.args
+                },
+                inherit_args: overlay.wasi.inherit_args.or(base.wasi.inherit_args),
+                preview2: overlay.wasi.preview2.or(base.wasi.preview2),
+                nn: NnOptions {
+                    models: {
+                        let mut models = base.wasi.nn.models;
+                        models.extend(overlay.wasi.nn.models);
+                        models
+                    },
+                    hashes: if overlay.wasi.nn.hashes.is_empty() {
+                        base.wasi.nn.hashes
+                    } else {
+                        overlay.wasi.nn.hashes
+                    },
+                },
+            },
+            max_concurrent_calls: overlay.max_concurrent_calls.or(base.max_concurrent_calls),
+            max_instances: overlay.max_instances.or(base.max_instances),
+            deterministic_seed: overlay.deterministic_seed.or(base.deterministic_seed),
+        }
+    }
+
+    /// Parse a YAML manifest
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(s: &str) -> Result<Manifest, serde_yaml::Error> {
+        serde_yaml::from_str(s)
+    }
+
+    /// Encode the manifest as YAML
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
+    }
+
+    /// Parse a TOML manifest
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Manifest, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    /// Encode the manifest as TOML
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+
     /// Set a single `config` key
     pub fn with_config_key(mut self, k: impl Into<String>, v: impl Into<String>) -> Self {
         self.config.insert(k.into(), v.into());
         self
     }
 
+    /// Set a single `config` key to a JSON value, strings are stored as-is and anything else is stored as JSON
+    pub fn with_config_value(
+        mut self,
+        k: impl Into<String>,
+        v: impl Into<serde_json::Value>,
+    ) -> Self {
+        self.config
+            .insert(k.into(), config_values::to_string(v.into()));
+        self
+    }
+
+    /// Set a single `config` key to the contents of the file at `path`, the file is read when a plugin is created
+    pub fn with_config_secret_file(mut self, k: impl Into<String>, path: impl AsRef<Path>) -> Self {
+        self.config_secrets
+            .insert(k.into(), path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Get a `config` value as `T`. Values are parsed as JSON, if that fails the value is used as a JSON string
+    /// so plain strings can be read without quoting them.
+    pub fn config_get<T: serde::de::DeserializeOwned>(
+        &self,
+        key: impl AsRef<str>,
+    ) -> Result<Option<T>, serde_json::Error> {
+        let value = match self.config.get(key.as_ref()) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        match serde_json::from_str(value) {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => match serde_json::from_value(serde_json::Value::String(value.clone())) {
+                Ok(x) => Ok(Some(x)),
+                Err(_) => Err(e),
+            },
+        }
+    }
+
     /// Set `timeout_ms`, which will interrupt a plugin function's execution if it meets or
     /// exceeds this value. When an interrupt is made, the plugin will not be able to recover and
     /// continue execution.
@@ -309,6 +1273,116 @@ impl Manifest {
         self.timeout_ms = Some(timeout.as_millis() as u64);
         self
     }
+
+    /// Set the timeout for a single function, overriding `timeout_ms` when that function is called
+    pub fn with_function_timeout(
+        mut self,
+        name: impl Into<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        self.function_timeouts
+            .insert(name.into(), timeout.as_millis() as u64);
+        self
+    }
+
+    /// The timeout in milliseconds used when calling `function`
+    pub fn timeout_for(&self, function: &str) -> Option<u64> {
+        match self.function_timeouts.get(function) {
+            Some(x) => Some(*x),
+            None => self.timeout_ms,
+        }
+    }
+
+    /// Set `opt_level`
+    pub fn with_opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = Some(opt_level);
+        self
+    }
+
+    /// Add a manifest to `include`
+    pub fn with_include(mut self, include: impl Into<Include>) -> Self {
+        self.include.push(include.into());
+        self
+    }
+
+    /// Set `wasi`
+    pub fn with_wasi_options(mut self, wasi: WasiOptions) -> Self {
+        self.wasi = wasi;
+        self
+    }
+
+    /// Set `wasi.args`, the first argument is usually the program name
+    pub fn with_wasi_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.wasi.args = args.into_iter().map(|x| x.into()).collect();
+        self
+    }
+
+    /// Set an environment variable in `wasi.env`
+    pub fn with_wasi_env(mut self, k: impl Into<String>, v: impl Into<String>) -> Self {
+        self.wasi.env.insert(k.into(), v.into());
+        self
+    }
+
+    /// Add a wasi-nn model directory that the plugin can load by name, see `NnOptions::models`
+    pub fn with_nn_model(mut self, name: impl Into<String>, path: impl AsRef<Path>) -> Self {
+        self.wasi
+            .nn
+            .models
+            .insert(name.into(), path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Allow the plugin to load wasi-nn model files with the given hash, see `NnOptions::hashes`
+    pub fn with_nn_hash(mut self, hash: impl Into<String>) -> Self {
+        self.wasi.nn.hashes.push(hash.into());
+        self
+    }
+
+    /// Set `max_concurrent_calls`
+    pub fn with_max_concurrent_calls(mut self, n: u32) -> Self {
+        self.max_concurrent_calls = Some(n);
+        self
+    }
+
+    /// Set `max_instances`
+    pub fn with_max_instances(mut self, n: u32) -> Self {
+        self.max_instances = Some(n);
+        self
+    }
+
+    /// Run the plugin deterministically using the given random seed, see `deterministic_seed`
+    pub fn with_deterministic_seed(mut self, seed: u64) -> Self {
+        self.deterministic_seed = Some(seed);
+        self
+    }
+
+    /// Add a key to `trusted_keys`
+    pub fn with_trusted_key(mut self, key: TrustedKey) -> Self {
+        self.trusted_keys.push(key);
+        self
+    }
+}
+
+// Config values are stored as strings for compatibility with existing hosts and the PDK, any other JSON value
+// is converted to JSON text when the manifest is loaded
+mod config_values {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub fn to_string(v: serde_json::Value) -> String {
+        match v {
+            serde_json::Value::String(s) => s,
+            v => v.to_string(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<BTreeMap<String, String>, D::Error> {
+        let values = BTreeMap::<String, serde_json::Value>::deserialize(d)?;
+        Ok(values.into_iter().map(|(k, v)| (k, to_string(v))).collect())
+    }
 }
 
 mod base64 {
@@ -321,10 +1395,30 @@ mod base64 {
         String::serialize(&base64, s)
     }
 
+    // Accepts standard and URL-safe base64 with or without padding, optionally in a
+    // `data:application/wasm;base64,...` URI
     pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
         let base64 = String::deserialize(d)?;
-        general_purpose::STANDARD
-            .decode(base64.as_bytes())
+        let data = match base64.strip_prefix("data:") {
+            Some(uri) => match uri.split_once(',') {
+                Some((params, data)) if params.ends_with(";base64") => data,
+                Some(_) => {
+                    return Err(serde::de::Error::custom("data URIs must be base64-encoded"))
+                }
+                None => return Err(serde::de::Error::custom("invalid data URI")),
+            },
+            None => base64.as_str(),
+        };
+
+        let config = general_purpose::GeneralPurposeConfig::new()
+            .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent);
+        let alphabet = if data.contains(['-', '_']) {
+            &base64::alphabet::URL_SAFE
+        } else {
+            &base64::alphabet::STANDARD
+        };
+        general_purpose::GeneralPurpose::new(alphabet, config)
+            .decode(data.trim().as_bytes())
             .map_err(serde::de::Error::custom)
     }
 }
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use crate::{Manifest, Wasm};
+
+// Environment variables that programs commonly read, setting them from `config` changes how WASI programs behave
+const WELL_KNOWN_ENV: &[&str] = &[
+    "HOME", "LANG", "LC_ALL", "PATH", "PWD", "SHELL", "TERM", "TMPDIR", "TZ", "USER",
+];
+
+/// A non-fatal issue found by `Manifest::lint`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// `allowed_hosts` contains `*`, the plugin can make HTTP requests to any host
+    WildcardHost,
+
+    /// The root of the host filesystem is mounted without `readonly`
+    WritableRootMount { dest: PathBuf },
+
+    /// A module is downloaded without a `hash`, so its contents can change without the manifest changing
+    MissingHash { source: String },
+
+    /// A module is downloaded over plain HTTP
+    InsecureUrl { url: String },
+
+    /// `timeout_ms` isn't set, plugin functions can run forever
+    TimeoutDisabled,
+
+    /// A `config` key is exposed as a WASI environment variable that programs commonly read, or is replaced by a
+    /// `wasi.env` value with the same name
+    ConfigShadowsEnv { key: String },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::WildcardHost => write!(f, "allowed_hosts allows every host"),
+            Warning::WritableRootMount { dest } => {
+                write!(f, "/ is mounted read-write at {}", dest.display())
+            }
+            Warning::MissingHash { source } => write!(f, "{source} doesn't have a hash"),
+            Warning::InsecureUrl { url } => write!(f, "{url} is downloaded over plain HTTP"),
+            Warning::TimeoutDisabled => write!(f, "No timeout is set"),
+            Warning::ConfigShadowsEnv { key } => {
+                write!(f, "Config key {key} is also a WASI environment variable")
+            }
+        }
+    }
+}
+
+impl Manifest {
+    /// Check the manifest for settings that are allowed but usually unintended or unsafe, an empty list is
+    /// returned when nothing is found. Unlike `Manifest::validate` these don't prevent a plugin from being created.
+    pub fn lint(&self) -> Vec<Warning> {
+        let mut warnings = vec![];
+        if self.allowed_hosts.iter().flatten().any(|x| x == "*") {
+            warnings.push(Warning::WildcardHost);
+        }
+
+        for (src, dest) in self.allowed_paths.iter().flatten() {
+            if src.has_root() && src.parent().is_none() && !dest.is_readonly() {
+                warnings.push(Warning::WritableRootMount {
+                    dest: dest.path().to_path_buf(),
+                });
+            }
+        }
+
+        for wasm in self.wasm.iter() {
+            let pinned = wasm.meta().hash.is_some();
+            match wasm {
+                Wasm::Url { req, .. } => {
+                    if !pinned {
+                        warnings.push(Warning::MissingHash {
+                            source: req.redacted().url,
+                        });
+                    }
+                    if req.url.to_ascii_lowercase().starts_with("http://") {
+                        warnings.push(Warning::InsecureUrl {
+                            url: req.redacted().url,
+                        });
+                    }
+                }
+                Wasm::Registry { registry, .. } if !pinned && !registry.contains('@') => {
+                    warnings.push(Warning::MissingHash {
+                        source: registry.clone(),
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        if self.timeout_ms.is_none() {
+            warnings.push(Warning::TimeoutDisabled);
+        }
+
+        if self.wasi.environment.unwrap_or(true) {
+            for key in self.config.keys() {
+                if WELL_KNOWN_ENV.contains(&key.as_str()) || self.wasi.env.contains_key(key) {
+                    warnings.push(Warning::ConfigShadowsEnv { key: key.clone() });
+                }
+            }
+        }
+
+        warnings
+    }
+}
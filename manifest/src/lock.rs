@@ -0,0 +1,249 @@
+use crate::{HashAlgorithm, Manifest, Wasm};
+
+/// The current lockfile format version
+pub const LOCKFILE_VERSION: u32 = 1;
+
+/// A lockfile pins the exact content of every module referenced by a manifest
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Lockfile {
+    /// Lockfile format version
+    pub version: u32,
+
+    /// One entry for each module in the manifest, in the same order
+    pub modules: Vec<LockedModule>,
+}
+
+/// A single module in a `Lockfile`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct LockedModule {
+    /// Module name, if one is set in the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Where the module is loaded from: a file path, a URL or `<data>`
+    pub source: String,
+
+    /// The final URL the module was downloaded from, after following redirects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_url: Option<String>,
+
+    /// Hex encoded SHA-256 digest of the module
+    pub hash: String,
+
+    /// Module size in bytes
+    pub size: u64,
+}
+
+/// Errors returned when creating or applying a `Lockfile`
+#[derive(Debug)]
+pub enum LockError {
+    /// A module couldn't be read or downloaded
+    Fetch { source: String, message: String },
+
+    /// The manifest no longer matches the lockfile
+    Drift { source: String, message: String },
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::Fetch { source, message } => {
+                write!(f, "Unable to fetch {source}: {message}")
+            }
+            LockError::Drift { source, message } => {
+                write!(f, "{source} doesn't match lockfile: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+impl Wasm {
+    // Used to identify modules in a lockfile
+    pub(crate) fn source(&self) -> String {
+        match self {
+            Wasm::File { path, .. }
+            | Wasm::Precompiled { path, .. }
+            | Wasm::Dir { path, .. }
+            | Wasm::Archive { path, .. } => path.display().to_string(),
+            Wasm::Url { req, .. } => req.url.clone(),
+            Wasm::Data { .. } => "<data>".to_string(),
+            Wasm::Registry { registry, .. } => registry.clone(),
+        }
+    }
+
+    // Read or download the module
+    #[cfg(feature = "lock")]
+    fn fetch(&self) -> Result<(Vec<u8>, Option<String>), LockError> {
+        use std::io::Read;
+
+        let err = |e: &dyn std::fmt::Display| LockError::Fetch {
+            source: self.source(),
+            message: e.to_string(),
+        };
+
+        match self {
+            Wasm::File { path, meta } => {
+                let data = std::fs::read(path).map_err(|e| err(&e))?;
+                let data = meta.decompress(&data).map_err(|e| err(&e))?;
+                Ok((data.into_owned(), None))
+            }
+            Wasm::Precompiled { path, .. } => {
+                Ok((std::fs::read(path).map_err(|e| err(&e))?, None))
+            }
+            Wasm::Data { data, meta } => {
+                let data = meta.decompress(data).map_err(|e| err(&e))?;
+                Ok((data.into_owned(), None))
+            }
+            Wasm::Url { req, .. } => {
+                let mut agent = ureq::AgentBuilder::new().redirects(req.redirect_limit());
+                if let Some(ms) = req.timeout_ms {
+                    agent = agent.timeout(std::time::Duration::from_millis(ms));
+                }
+                let mut request = agent
+                    .build()
+                    .request(req.method.as_deref().unwrap_or("GET"), &req.url);
+                for (k, v) in req.headers.iter() {
+                    request = request.set(k, v);
+                }
+
+                let res = match &req.body {
+                    Some(body) => request.send_string(body),
+                    None => request.call(),
+                }
+                .map_err(|e| err(&e))?;
+                let resolved_url = res.get_url().to_string();
+                let mut data = Vec::new();
+                res.into_reader()
+                    .read_to_end(&mut data)
+                    .map_err(|e| err(&e))?;
+                let data = self.meta().decompress(&data).map_err(|e| err(&e))?;
+                Ok((data.into_owned(), Some(resolved_url)))
+            }
+            Wasm::Registry { .. } => Err(err(
+                &"OCI registry modules can't be locked, pin the module using a digest reference instead",
+            )),
+            Wasm::Dir { .. } => Err(err(
+                &"Directories can't be locked, list the modules individually instead",
+            )),
+            Wasm::Archive { .. } => Err(err(
+                &"Archives can't be locked, pin the archive using `hash` instead",
+            )),
+        }
+    }
+}
+
+impl Manifest {
+    /// Read or download every module and create a `Lockfile` containing their hashes and sizes
+    #[cfg(feature = "lock")]
+    pub fn lock(&self) -> Result<Lockfile, LockError> {
+        let mut modules = Vec::with_capacity(self.wasm.len());
+        for wasm in self.wasm.iter() {
+            let (data, resolved_url) = wasm.fetch()?;
+            let hash = HashAlgorithm::Sha256.digest(&data);
+            if let Some(expected) = &wasm.meta().hash {
+                // Hashes using other algorithms are checked with that algorithm, the lockfile always uses SHA-256
+                let found = match HashAlgorithm::split(expected) {
+                    Some((HashAlgorithm::Sha256, _)) => hash.clone(),
+                    Some((alg, _)) => format!("{}:{}", alg.name(), alg.digest(&data)),
+                    None => {
+                        return Err(LockError::Drift {
+                            source: wasm.source(),
+                            message: format!("unsupported hash algorithm {expected}"),
+                        })
+                    }
+                };
+                let expected = expected.strip_prefix("sha256:").unwrap_or(expected);
+                if !expected.eq_ignore_ascii_case(&found) {
+                    return Err(LockError::Drift {
+                        source: wasm.source(),
+                        message: format!("expected hash {expected} but found {found}"),
+                    });
+                }
+            }
+
+            modules.push(LockedModule {
+                name: wasm.meta().name.clone(),
+                source: wasm.source(),
+                resolved_url,
+                hash,
+                size: data.len() as u64,
+            });
+        }
+
+        Ok(Lockfile {
+            version: LOCKFILE_VERSION,
+            modules,
+        })
+    }
+
+    /// Read or download every module and check that they still match `lockfile`
+    #[cfg(feature = "lock")]
+    pub fn verify_lock(&self, lockfile: &Lockfile) -> Result<(), LockError> {
+        let current = self.clone().with_lockfile(lockfile)?.lock()?;
+        for (locked, found) in lockfile.modules.iter().zip(current.modules.iter()) {
+            if locked.size != found.size {
+                return Err(LockError::Drift {
+                    source: locked.source.clone(),
+                    message: format!("expected {} bytes but found {}", locked.size, found.size),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Pin the hash of every module to the value in `lockfile`, plugins created from the returned manifest
+    /// will fail to load if any of the sources have changed since the lockfile was created
+    pub fn with_lockfile(mut self, lockfile: &Lockfile) -> Result<Manifest, LockError> {
+        if lockfile.version != LOCKFILE_VERSION {
+            return Err(LockError::Drift {
+                source: "<lockfile>".to_string(),
+                message: format!("unsupported lockfile version {}", lockfile.version),
+            });
+        }
+
+        if lockfile.modules.len() != self.wasm.len() {
+            return Err(LockError::Drift {
+                source: "<manifest>".to_string(),
+                message: format!(
+                    "expected {} modules but found {}",
+                    lockfile.modules.len(),
+                    self.wasm.len()
+                ),
+            });
+        }
+
+        for (wasm, locked) in self.wasm.iter_mut().zip(lockfile.modules.iter()) {
+            let source = wasm.source();
+            if source != locked.source || wasm.meta().name != locked.name {
+                return Err(LockError::Drift {
+                    source,
+                    message: format!("expected module {}", locked.source),
+                });
+            }
+
+            let meta = wasm.meta_mut();
+            let sha256 = meta
+                .hash
+                .as_deref()
+                .and_then(HashAlgorithm::split)
+                .filter(|(alg, _)| *alg == HashAlgorithm::Sha256);
+            if let Some((_, hash)) = sha256 {
+                if !hash.eq_ignore_ascii_case(&locked.hash) {
+                    return Err(LockError::Drift {
+                        source,
+                        message: format!("expected hash {} but found {hash}", locked.hash),
+                    });
+                }
+            }
+            meta.hash = Some(locked.hash.clone());
+        }
+
+        Ok(self)
+    }
+}
@@ -0,0 +1,93 @@
+use crate::Manifest;
+
+/// The current manifest format version. Manifests without a `version` field are version 0.
+///
+/// Changes from version 0:
+/// - `memory.max` is now `memory.max_pages`
+pub const MANIFEST_VERSION: u32 = 1;
+
+fn error(msg: impl std::fmt::Display) -> serde_json::Error {
+    serde::de::Error::custom(msg)
+}
+
+// Version 0 manifests weren't versioned
+fn migrate_v0(manifest: &mut serde_json::Map<String, serde_json::Value>) {
+    if let Some(memory) = manifest.get_mut("memory").and_then(|x| x.as_object_mut()) {
+        if let Some(max) = memory.remove("max") {
+            memory.entry("max_pages").or_insert(max);
+        }
+    }
+}
+
+// Config values like `{"file": "/run/secrets/key"}` are moved to `config_secrets`, this is done for every version
+// since only the object form is a secret reference, strings that contain the same JSON text are left alone
+fn move_config_secrets(value: &mut serde_json::Map<String, serde_json::Value>) {
+    let config = match value.get_mut("config").and_then(|x| x.as_object_mut()) {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut secrets = serde_json::Map::new();
+    config.retain(|k, v| {
+        let path = match v.as_object() {
+            Some(obj) if obj.len() == 1 => obj.get("file").filter(|x| x.is_string()),
+            _ => None,
+        };
+        match path {
+            Some(path) => {
+                secrets.insert(k.clone(), path.clone());
+                false
+            }
+            None => true,
+        }
+    });
+
+    if secrets.is_empty() {
+        return;
+    }
+    // Invalid `config_secrets` values are left for the deserializer to report
+    if let serde_json::Value::Object(x) = value
+        .entry("config_secrets")
+        .or_insert_with(|| serde_json::Value::Object(Default::default()))
+    {
+        x.extend(secrets);
+    }
+}
+
+impl Manifest {
+    /// Parse a manifest from a JSON value, upgrading older layouts to `MANIFEST_VERSION` first. An error is
+    /// returned for manifests that are newer than `MANIFEST_VERSION`.
+    pub fn migrate_from_value(mut value: serde_json::Value) -> Result<Manifest, serde_json::Error> {
+        let manifest = match value.as_object_mut() {
+            Some(x) => x,
+            None => return serde_json::from_value(value),
+        };
+
+        let version = match manifest.get("version") {
+            None | Some(serde_json::Value::Null) => 0,
+            Some(v) => match v.as_u64() {
+                Some(v) => v,
+                None => return Err(error(format!("invalid manifest version {v}"))),
+            },
+        };
+        if version > MANIFEST_VERSION as u64 {
+            return Err(error(format!(
+                "unsupported manifest version {version}, the latest supported version is {MANIFEST_VERSION}"
+            )));
+        }
+
+        if version < 1 {
+            migrate_v0(manifest);
+        }
+
+        move_config_secrets(manifest);
+        if let Some(wasm) = manifest.get_mut("wasm").and_then(|x| x.as_array_mut()) {
+            for w in wasm.iter_mut().filter_map(|x| x.as_object_mut()) {
+                move_config_secrets(w);
+            }
+        }
+
+        manifest.insert("version".to_string(), MANIFEST_VERSION.into());
+        serde_json::from_value(value)
+    }
+}
@@ -0,0 +1,259 @@
+use std::path::PathBuf;
+
+use crate::{Manifest, Wasm};
+
+/// A problem found by `Manifest::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The manifest doesn't contain any modules
+    NoModules,
+
+    /// More than one module has the same name, only the last one would be linked
+    DuplicateModuleName { name: String },
+
+    /// A module hash doesn't use a supported algorithm or isn't a hex-encoded digest of the right length
+    InvalidHash { source: String, hash: String },
+
+    /// A registry reference doesn't start with `oci://`
+    InvalidRegistryReference { reference: String },
+
+    /// An `allowed_hosts` or `denied_hosts` entry can't be parsed
+    InvalidHost { host: String, reason: String },
+
+    /// An `allowed_paths` entry doesn't exist or isn't a directory
+    UnreachablePath { path: PathBuf },
+
+    /// The memory limit is larger than the 4GiB a 32-bit memory can address
+    MemoryLimitTooLarge { max_pages: u32 },
+
+    /// The timeout is zero, every call would be interrupted immediately
+    InvalidTimeout { timeout_ms: u64 },
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::NoModules => write!(f, "The manifest doesn't contain any modules"),
+            Problem::DuplicateModuleName { name } => {
+                write!(f, "More than one module is named {name}")
+            }
+            Problem::InvalidHash { source, hash } => {
+                write!(f, "Invalid hash for {source}: {hash}")
+            }
+            Problem::InvalidRegistryReference { reference } => {
+                write!(
+                    f,
+                    "Invalid registry reference, expected oci://: {reference}"
+                )
+            }
+            Problem::InvalidHost { host, reason } => {
+                write!(f, "Invalid host {host}: {reason}")
+            }
+            Problem::UnreachablePath { path } => {
+                write!(f, "Allowed path {} isn't a directory", path.display())
+            }
+            Problem::MemoryLimitTooLarge { max_pages } => {
+                write!(f, "Memory limit of {max_pages} pages is larger than 65536")
+            }
+            Problem::InvalidTimeout { timeout_ms } => {
+                write!(f, "Invalid timeout of {timeout_ms}ms")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Problem {}
+
+impl Wasm {
+    // The name the runtime uses for the module
+    fn module_name(&self) -> Option<String> {
+        if let Some(name) = &self.meta().name {
+            return Some(name.clone());
+        }
+
+        let stem = |path: &std::path::Path| {
+            path.with_extension("")
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+        };
+        match self {
+            Wasm::File { path, .. } | Wasm::Precompiled { path, .. } => stem(path),
+            Wasm::Data { .. } => Some("main".to_string()),
+            Wasm::Url { req, .. } => {
+                let file_name = req.url.split('/').next_back().unwrap_or_default();
+                let name = file_name
+                    .strip_suffix(".wasm")
+                    .or_else(|| file_name.strip_suffix(".wast"))
+                    .unwrap_or("main");
+                Some(name.to_string())
+            }
+            Wasm::Registry { registry, .. } => {
+                let (_, path) = registry.strip_prefix("oci://")?.split_once('/')?;
+                let repository = path.split('@').next().unwrap_or_default();
+                let name = repository.rsplit('/').next().unwrap_or_default();
+                Some(name.split(':').next().unwrap_or_default().to_string())
+            }
+
+            // Names for directory and archive modules depend on their contents
+            Wasm::Dir { .. } | Wasm::Archive { .. } => None,
+        }
+    }
+}
+
+fn is_valid_hash(hash: &str) -> bool {
+    match crate::HashAlgorithm::split(hash) {
+        Some((alg, digest)) => {
+            digest.len() == alg.hex_len() && digest.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+// Check an `allowed_hosts` entry, returning the reason it's invalid
+fn check_host(entry: &str) -> Result<(), String> {
+    let rest = match entry.split_once("://") {
+        Some((scheme, rest)) => {
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return Err(format!("unsupported scheme {scheme}"));
+            }
+            rest.trim_end_matches('/')
+        }
+        None => entry,
+    };
+
+    // CIDR ranges
+    if let Some((addr, prefix)) = rest.split_once('/') {
+        let addr: std::net::IpAddr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| format!("invalid network address {addr}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        return match prefix.parse::<u8>() {
+            Ok(x) if x <= max => Ok(()),
+            _ => Err(format!("invalid prefix length {prefix}")),
+        };
+    }
+
+    // IPv6 addresses, with or without brackets
+    let host = if let Some(x) = rest.strip_prefix('[') {
+        match x.split_once(']') {
+            Some((addr, port)) => {
+                if !port.is_empty() {
+                    check_port(port.strip_prefix(':').unwrap_or(port))?;
+                }
+                addr
+            }
+            None => return Err("unclosed bracket".to_string()),
+        }
+    } else if rest.matches(':').count() > 1 {
+        rest
+    } else {
+        match rest.split_once(':') {
+            Some((host, port)) => {
+                check_port(port)?;
+                host
+            }
+            None => rest,
+        }
+    };
+
+    if host.is_empty() {
+        return Err("empty host".to_string());
+    }
+    if host.contains(':') {
+        return match host.parse::<std::net::Ipv6Addr>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("invalid IPv6 address {host}")),
+        };
+    }
+    if let Some(c) = host
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !"-._*?[]!".contains(*c))
+    {
+        return Err(format!("invalid character {c:?}"));
+    }
+    Ok(())
+}
+
+fn check_port(port: &str) -> Result<(), String> {
+    match port.parse::<u16>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("invalid port {port}")),
+    }
+}
+
+impl Manifest {
+    /// Check the manifest for problems that would otherwise only be found when a plugin is created, an empty
+    /// list is returned when no problems are found. Paths in `allowed_paths` are checked against the filesystem.
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        if self.wasm.is_empty() && self.include.is_empty() && self.extends.is_none() {
+            problems.push(Problem::NoModules);
+        }
+
+        let mut names = std::collections::BTreeSet::new();
+        for wasm in self.wasm.iter() {
+            if let Some(name) = wasm.module_name() {
+                if !names.insert(name.clone()) {
+                    problems.push(Problem::DuplicateModuleName { name });
+                }
+            }
+
+            if let Some(hash) = &wasm.meta().hash {
+                if !is_valid_hash(hash) {
+                    problems.push(Problem::InvalidHash {
+                        source: wasm.source(),
+                        hash: hash.clone(),
+                    });
+                }
+            }
+
+            if let Wasm::Registry { registry, .. } = wasm {
+                if !registry.starts_with("oci://") {
+                    problems.push(Problem::InvalidRegistryReference {
+                        reference: registry.clone(),
+                    });
+                }
+            }
+        }
+
+        for host in self
+            .allowed_hosts
+            .iter()
+            .flatten()
+            .chain(&self.denied_hosts)
+        {
+            if let Err(reason) = check_host(host) {
+                problems.push(Problem::InvalidHost {
+                    host: host.clone(),
+                    reason,
+                });
+            }
+        }
+
+        for path in self.allowed_paths.iter().flat_map(|x| x.keys()) {
+            if !path.is_dir() {
+                problems.push(Problem::UnreachablePath { path: path.clone() });
+            }
+        }
+
+        if let Some(max_pages) = self.memory.max_pages {
+            if max_pages > 65536 {
+                problems.push(Problem::MemoryLimitTooLarge { max_pages });
+            }
+        }
+
+        let timeouts = self
+            .timeout_ms
+            .iter()
+            .chain(self.function_timeouts.values());
+        for &timeout_ms in timeouts {
+            if timeout_ms == 0 {
+                problems.push(Problem::InvalidTimeout { timeout_ms });
+            }
+        }
+
+        problems
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::{HttpRequest, RegistryAuth, RetryOptions, Wasm, WasmMetadata};
+
+// The key that identifies each kind of module when `kind` isn't set, in the same order as the `Wasm` variants
+const KINDS: &[(&str, &str)] = &[
+    ("file", "path"),
+    ("data", "data"),
+    ("url", "url"),
+    ("precompiled", "precompiled"),
+    ("registry", "registry"),
+    ("dir", "dir"),
+    ("archive", "archive"),
+];
+
+// Mirrors `Wasm` using an internally tagged representation, so errors are reported for a single variant instead of
+// "data did not match any variant"
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+enum Tagged {
+    File {
+        path: PathBuf,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+    Data {
+        #[serde(with = "crate::base64")]
+        data: Vec<u8>,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+    Url {
+        #[serde(flatten)]
+        req: HttpRequest,
+        #[serde(default)]
+        retry: Option<RetryOptions>,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+    Precompiled {
+        #[serde(rename = "precompiled")]
+        path: PathBuf,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+    Registry {
+        registry: String,
+        #[serde(default)]
+        auth: Option<RegistryAuth>,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+    Dir {
+        #[serde(rename = "dir")]
+        path: PathBuf,
+        #[serde(default)]
+        pattern: Option<String>,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+    Archive {
+        #[serde(rename = "archive")]
+        path: PathBuf,
+        #[serde(default)]
+        pattern: Option<String>,
+        #[serde(flatten)]
+        meta: WasmMetadata,
+    },
+}
+
+impl From<Tagged> for Wasm {
+    fn from(tagged: Tagged) -> Wasm {
+        match tagged {
+            Tagged::File { path, meta } => Wasm::File { path, meta },
+            Tagged::Data { data, meta } => Wasm::Data { data, meta },
+            Tagged::Url { req, retry, meta } => Wasm::Url { req, retry, meta },
+            Tagged::Precompiled { path, meta } => Wasm::Precompiled { path, meta },
+            Tagged::Registry {
+                registry,
+                auth,
+                meta,
+            } => Wasm::Registry {
+                registry,
+                auth,
+                meta,
+            },
+            Tagged::Dir {
+                path,
+                pattern,
+                meta,
+            } => Wasm::Dir {
+                path,
+                pattern,
+                meta,
+            },
+            Tagged::Archive {
+                path,
+                pattern,
+                meta,
+            } => Wasm::Archive {
+                path,
+                pattern,
+                meta,
+            },
+        }
+    }
+}
+
+impl Wasm {
+    /// Parse a module from a JSON value. The kind of module can be set explicitly using a `kind` field (`file`,
+    /// `data`, `url`, `precompiled`, `registry`, `dir` or `archive`), otherwise it's determined by the field that
+    /// names the source, like `path` or `url`. Errors name the kind of module and the field that caused them.
+    pub fn from_value(mut value: serde_json::Value) -> Result<Wasm, String> {
+        let object = match value.as_object_mut() {
+            Some(x) => x,
+            None => return Err(format!("expected a module object, found {value}")),
+        };
+
+        let kind = match object.get("kind") {
+            Some(serde_json::Value::String(kind)) => {
+                if !KINDS.iter().any(|(k, _)| k == kind) {
+                    return Err(format!(
+                        "unknown module kind {kind:?}, expected one of {}",
+                        KINDS.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(", ")
+                    ));
+                }
+                kind.clone()
+            }
+            Some(kind) => return Err(format!("module kind must be a string, found {kind}")),
+            None => {
+                let found: Vec<_> = KINDS
+                    .iter()
+                    .filter(|(_, key)| object.contains_key(*key))
+                    .collect();
+                match found.as_slice() {
+                    [(kind, _)] => {
+                        object.insert("kind".to_string(), kind.to_string().into());
+                        kind.to_string()
+                    }
+                    [] => {
+                        return Err(format!(
+                            "unable to determine the module kind, expected one of the fields {} or a `kind` field",
+                            KINDS
+                                .iter()
+                                .map(|(_, key)| format!("`{key}`"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "ambiguous module, found the fields {}, set `kind` to choose one",
+                            found
+                                .iter()
+                                .map(|(_, key)| format!("`{key}`"))
+                                .collect::<Vec<_>>()
+                                .join(" and ")
+                        ))
+                    }
+                }
+            }
+        };
+
+        match serde_json::from_value::<Tagged>(value) {
+            Ok(x) => Ok(x.into()),
+            Err(e) => Err(format!("invalid {kind} module: {e}")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Wasm {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(d)?;
+        Wasm::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
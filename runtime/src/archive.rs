@@ -0,0 +1,145 @@
+use extism_manifest::{Manifest, Wasm, WasmMetadata};
+
+use crate::*;
+
+/// The contents of a `Wasm::Archive`
+pub(crate) enum Archive {
+    /// Modules matching the archive's pattern
+    Modules(Vec<Wasm>),
+
+    /// The manifest at the root of the archive, its file paths have been replaced by the archive entries
+    Manifest(Box<Manifest>),
+}
+
+// The largest total size of the files read from an archive
+#[cfg(feature = "archive")]
+const MAX_EXTRACTED_BYTES: u64 = extism_manifest::MAX_DECOMPRESSED_BYTES;
+
+// Read an archive entry, sizes in the archive's headers aren't trusted. `remaining` is the number of bytes that can
+// still be extracted from the archive
+#[cfg(feature = "archive")]
+fn read_entry(r: impl std::io::Read, remaining: &mut u64) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+
+    let mut buf = Vec::new();
+    r.take(*remaining + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > *remaining {
+        anyhow::bail!(
+            "Archive is larger than the limit of {MAX_EXTRACTED_BYTES} bytes when extracted"
+        );
+    }
+    *remaining -= buf.len() as u64;
+    Ok(buf)
+}
+
+// Read every file in a `.tar`, `.tar.gz` or `.zip` archive into memory, keyed by the path inside the archive
+#[cfg(feature = "archive")]
+fn entries(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+    use std::io::Read;
+
+    let mut entries = BTreeMap::new();
+    let mut remaining = MAX_EXTRACTED_BYTES;
+    if data.starts_with(b"PK\x03\x04") {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+        for i in 0..zip.len() {
+            let file = zip.by_index(i)?;
+            let path = match file.enclosed_name() {
+                Some(path) if file.is_file() => path.to_string_lossy().to_string(),
+                _ => continue,
+            };
+            entries.insert(path, read_entry(file, &mut remaining)?);
+        }
+        return Ok(entries);
+    }
+
+    let reader: Box<dyn Read + '_> = if data.starts_with(&[0x1f, 0x8b]) {
+        Box::new(flate2::read::GzDecoder::new(data))
+    } else {
+        Box::new(data)
+    };
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().to_string();
+        entries.insert(path, read_entry(entry, &mut remaining)?);
+    }
+    Ok(entries)
+}
+
+#[cfg(not(feature = "archive"))]
+fn entries(_data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+    anyhow::bail!("Loading modules from archives requires the `archive` feature")
+}
+
+// Entry paths are compared without a leading `./`
+fn normalize(path: &str) -> &str {
+    path.trim_start_matches("./")
+}
+
+/// Read a `Wasm::Archive`, nothing is written to disk
+pub(crate) fn read(
+    path: &std::path::Path,
+    pattern: Option<&str>,
+    meta: &WasmMetadata,
+) -> Result<Archive, Error> {
+    let source = path.display().to_string();
+    let data =
+        std::fs::read(path).map_err(|e| anyhow::format_err!("Unable to read {source}: {e}"))?;
+    manifest::check_hash(&meta.hash, &data)?;
+    let mut entries: BTreeMap<String, Vec<u8>> = entries(&data)?
+        .into_iter()
+        .map(|(k, v)| (normalize(&k).to_string(), v))
+        .collect();
+
+    let nested = ["manifest.json", "manifest.toml"]
+        .into_iter()
+        .find_map(|name| entries.remove(name));
+    if let Some(nested) = nested {
+        let (mut manifest, module) = manifest::parse(&nested)?;
+        if module.is_some() {
+            anyhow::bail!("Manifest in archive {source} is a WebAssembly module");
+        }
+
+        for wasm in manifest.wasm.iter_mut() {
+            if let Wasm::File { path, meta } = wasm {
+                let name = path.to_string_lossy();
+                let data = match entries.get(normalize(&name)) {
+                    Some(x) => x.clone(),
+                    None => anyhow::bail!("Archive {source} doesn't contain {name}"),
+                };
+                *wasm = Wasm::Data {
+                    data,
+                    meta: std::mem::take(meta),
+                };
+            }
+        }
+        return Ok(Archive::Manifest(Box::new(manifest)));
+    }
+
+    let pattern = glob::Pattern::new(pattern.unwrap_or("*.wasm"))?;
+    let mut modules = vec![];
+    for (path, data) in entries {
+        if !pattern.matches(&path) {
+            continue;
+        }
+
+        // Compressed modules have two extensions, like `lib.wasm.gz`
+        let name = match std::path::Path::new(&path).file_stem() {
+            Some(name) => name.to_string_lossy(),
+            None => continue,
+        };
+        let name = name.strip_suffix(".wasm").unwrap_or(&name).to_string();
+        modules.push(Wasm::Data {
+            data,
+            meta: WasmMetadata {
+                name: Some(name),
+                config: meta.config.clone(),
+                ..Default::default()
+            },
+        });
+    }
+    Ok(Archive::Modules(modules))
+}
@@ -0,0 +1,165 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::*;
+
+// The state of a single call, guarded by a mutex so a dropped future can't cancel a call made by another caller
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CallState {
+    Waiting,
+    Running,
+    Abandoned,
+    Done,
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    match m.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
+
+// Cancels the call if the future is dropped before it completes
+struct CallGuard {
+    state: Arc<Mutex<CallState>>,
+    cancel_handle: CancelHandle,
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        let mut state = lock(&self.state);
+        match *state {
+            CallState::Waiting => *state = CallState::Abandoned,
+            CallState::Running => {
+                debug!(
+                    "Call future dropped, cancelling plugin {}",
+                    self.cancel_handle.id
+                );
+                let _ = self.cancel_handle.cancel();
+            }
+            CallState::Abandoned | CallState::Done => (),
+        }
+    }
+}
+
+/// `AsyncPlugin` allows plugins to be called from async code, calls are executed on tokio's blocking thread pool so
+/// a single runtime thread can drive many plugins. `AsyncPlugin` can be cloned, calls made using clones of the same
+/// plugin are executed one at a time.
+///
+/// Dropping the future returned by `AsyncPlugin::call` cancels the call, if it has already started the guest is
+/// interrupted the same way `CancelHandle::cancel` interrupts it.
+#[derive(Clone)]
+pub struct AsyncPlugin {
+    plugin: Arc<Mutex<Plugin>>,
+    cancel_handle: CancelHandle,
+}
+
+impl From<Plugin> for AsyncPlugin {
+    fn from(plugin: Plugin) -> AsyncPlugin {
+        AsyncPlugin::new(plugin)
+    }
+}
+
+impl AsyncPlugin {
+    /// Wrap an existing plugin
+    pub fn new(plugin: Plugin) -> AsyncPlugin {
+        let cancel_handle = plugin.cancel_handle();
+        AsyncPlugin {
+            plugin: Arc::new(Mutex::new(plugin)),
+            cancel_handle,
+        }
+    }
+
+    /// Get a `CancelHandle` for the underlying plugin
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel_handle.clone()
+    }
+
+    /// Call a function by name, this must be called from within a tokio runtime. The input is converted to bytes
+    /// before the call is scheduled, the output is converted on the blocking thread.
+    pub async fn call<'a, T: ToBytes<'a>, U: FromBytesOwned + Send + 'static>(
+        &self,
+        name: impl Into<String>,
+        input: T,
+    ) -> Result<U, Error> {
+        let input = input.to_bytes()?.as_ref().to_vec();
+        self.run(name.into(), move |plugin, name| {
+            plugin.call::<&[u8], U>(name, &input)
+        })
+        .await
+    }
+
+    /// Call a function by name, reading the input from `input` on the blocking thread, see `Plugin::call_reader`.
+    /// Async streams can be passed using an adapter like `tokio_util::io::SyncIoBridge`.
+    pub async fn call_reader<U: FromBytesOwned + Send + 'static>(
+        &self,
+        name: impl Into<String>,
+        input: impl std::io::Read + Send + 'static,
+    ) -> Result<U, Error> {
+        self.run(name.into(), move |plugin, name| {
+            plugin.call_reader::<U>(name, input)
+        })
+        .await
+    }
+
+    // Run `f` on tokio's blocking thread pool, cancelling the call if the returned future is dropped
+    async fn run<U: Send + 'static>(
+        &self,
+        name: String,
+        f: impl FnOnce(&mut Plugin, &str) -> Result<U, Error> + Send + 'static,
+    ) -> Result<U, Error> {
+        let state = Arc::new(Mutex::new(CallState::Waiting));
+        let guard = CallGuard {
+            state: state.clone(),
+            cancel_handle: self.cancel_handle.clone(),
+        };
+
+        let plugin = self.plugin.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let mut plugin = lock(&plugin);
+            {
+                let mut state = lock(&state);
+                if *state == CallState::Abandoned {
+                    anyhow::bail!("Call to {name} was cancelled before it started");
+                }
+                *state = CallState::Running;
+            }
+
+            let res = f(&mut plugin, &name);
+
+            // This is updated before the plugin is unlocked, so a late cancellation can't affect the next call
+            *lock(&state) = CallState::Done;
+            res
+        });
+
+        let res = match task.await {
+            Ok(x) => x,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(Error::msg(e.to_string())),
+        };
+        drop(guard);
+        res
+    }
+
+    /// Run `f` with exclusive access to the underlying plugin, this blocks the current thread until any running
+    /// call has finished
+    pub fn with_plugin<R>(&self, f: impl FnOnce(&mut Plugin) -> R) -> R {
+        f(&mut lock(&self.plugin))
+    }
+
+    /// Get the underlying plugin back, this returns `None` if there are other clones of the `AsyncPlugin` or a call
+    /// is still running
+    pub fn into_inner(self) -> Option<Plugin> {
+        let plugin = Arc::try_unwrap(self.plugin).ok()?;
+        Some(match plugin.into_inner() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        })
+    }
+}
+
+impl Plugin {
+    /// Convert the plugin into an `AsyncPlugin`, which can be called from async code
+    pub fn into_async(self) -> AsyncPlugin {
+        AsyncPlugin::new(self)
+    }
+}
@@ -0,0 +1,135 @@
+use crate::*;
+
+/// A `Backend` creates engines and compiles modules. Everything that depends on how modules are compiled goes
+/// through this trait, so an alternative backend (for example an interpreter for platforms that don't allow
+/// JIT compilation) can be added behind a feature flag by implementing it and changing `Active`.
+pub(crate) trait Backend {
+    type Engine: Clone;
+    type Module: Clone;
+
+    /// Backend name, used in logs and returned by `extism::backend_name`
+    const NAME: &'static str;
+
+    /// Create a new engine using the given settings
+    fn engine(config: &EngineConfig) -> Result<Self::Engine, Error>;
+
+    /// Compile a module, `data` may be WebAssembly or WAT
+    fn compile(engine: &Self::Engine, data: &[u8]) -> Result<Self::Module, Error>;
+
+    /// Serialize a compiled module so it can be loaded using `deserialize` without compiling it again
+    fn precompile(engine: &Self::Engine, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Load a module created using `precompile`, an error is returned if the module was precompiled by an
+    /// incompatible engine
+    fn deserialize(engine: &Self::Engine, data: &[u8]) -> Result<Self::Module, Error>;
+
+    /// Identifies the settings that determine whether a precompiled module can be loaded by `engine`
+    fn compatibility_hash(engine: &Self::Engine) -> String;
+
+    /// Returns `true` if `a` and `b` refer to the same engine
+    fn same_engine(a: &Self::Engine, b: &Self::Engine) -> bool;
+}
+
+/// Compiles modules to native code using wasmtime
+pub(crate) struct Wasmtime;
+
+impl Backend for Wasmtime {
+    type Engine = Engine;
+    type Module = Module;
+
+    const NAME: &'static str = "wasmtime";
+
+    fn engine(config: &EngineConfig) -> Result<Engine, Error> {
+        let mut c = Config::new();
+        if let Some(opt_level) = config.opt_level {
+            c.cranelift_opt_level(match opt_level {
+                OptLevel::None => wasmtime::OptLevel::None,
+                OptLevel::Speed => wasmtime::OptLevel::Speed,
+                OptLevel::SpeedAndSize => wasmtime::OptLevel::SpeedAndSize,
+            });
+        }
+
+        if let Some(n) = config.max_wasm_stack {
+            c.max_wasm_stack(n);
+        }
+
+        c.epoch_interruption(true)
+            .debug_info(config.debug_info)
+            .profiler(config.profiling)
+            .parallel_compilation(config.parallel_compilation)
+            .memory_init_cow(config.memory_init_cow)
+            .consume_fuel(config.consume_fuel)
+            .wasm_threads(config.threads)
+            .wasm_multi_memory(config.multi_memory)
+            .wasm_tail_call(config.tail_call)
+            .cranelift_nan_canonicalization(config.deterministic)
+            .relaxed_simd_deterministic(config.deterministic)
+            .wasm_component_model(config.component_model)
+            .strategy(match config.compiler {
+                Compiler::Cranelift => Strategy::Cranelift,
+                Compiler::Winch => Strategy::Winch,
+            });
+
+        if let Some(p) = &config.pooling {
+            let mut pool = PoolingAllocationConfig::default();
+            pool.total_core_instances(p.total_instances)
+                .total_memories(p.total_memories)
+                .total_tables(p.total_tables)
+                .max_memories_per_module(p.memories_per_module)
+                .memory_pages(p.memory_pages)
+                .table_elements(p.table_elements);
+            c.allocation_strategy(InstanceAllocationStrategy::Pooling(pool));
+        }
+
+        for f in config.configure.iter() {
+            (f.0)(&mut c);
+        }
+
+        Engine::new(&c)
+    }
+
+    fn compile(engine: &Engine, data: &[u8]) -> Result<Module, Error> {
+        Module::new(engine, data)
+    }
+
+    fn precompile(engine: &Engine, data: &[u8]) -> Result<Vec<u8>, Error> {
+        engine.precompile_module(data)
+    }
+
+    fn deserialize(engine: &Engine, data: &[u8]) -> Result<Module, Error> {
+        if engine.detect_precompiled(data) != Some(Precompiled::Module) {
+            anyhow::bail!("Not a precompiled {} module", Self::NAME);
+        }
+
+        // SAFETY: wasmtime checks that the module was compiled by a compatible engine. The data either comes from
+        // the disk cache, which must only be writable by trusted users, or from a `Wasm::Precompiled` module that
+        // the host allowed using `PluginBuilder::with_precompiled_modules`
+        unsafe { Module::deserialize(engine, data) }.map_err(|e| {
+            e.context(format!(
+                "Precompiled module isn't compatible with this engine (compatibility hash {}), recompile it using \
+                 `PluginBuilder::precompile`",
+                Self::compatibility_hash(engine)
+            ))
+        })
+    }
+
+    fn compatibility_hash(engine: &Engine) -> String {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn same_engine(a: &Engine, b: &Engine) -> bool {
+        Engine::same(a, b)
+    }
+}
+
+/// The backend used by the runtime
+pub(crate) type Active = Wasmtime;
+
+/// Returns the name of the backend used to compile and run plugins
+pub fn backend_name() -> &'static str {
+    Active::NAME
+}
@@ -0,0 +1,196 @@
+//! Helpers for measuring plugin performance, these can be used to compare different engine settings
+//! using your own plugins and inputs.
+//!
+//! ```rust,no_run
+//! # const WASM: &[u8] = include_bytes!("../../wasm/code.wasm");
+//! let manifest = extism::Manifest::new([extism_manifest::Wasm::data(WASM)]);
+//! let bench = extism::bench::Bench::new(manifest)
+//!     .with_wasi(true)
+//!     .with_input_sizes([16, 1024, 65536]);
+//! println!("{:?}", bench.instantiation().unwrap());
+//! println!("{:?}", bench.call_overhead("count_vowels").unwrap());
+//! for result in bench.throughput("count_vowels").unwrap() {
+//!     println!("{} bytes: {:.0} bytes/s", result.input_size, result.bytes_per_second());
+//! }
+//! ```
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// Summary of a set of measurements
+#[derive(Debug, Clone)]
+pub struct Timings {
+    /// Number of samples
+    pub iterations: usize,
+
+    /// Fastest sample
+    pub min: Duration,
+
+    /// Slowest sample
+    pub max: Duration,
+
+    /// Average of all samples
+    pub mean: Duration,
+
+    /// Median sample
+    pub median: Duration,
+}
+
+impl Timings {
+    fn new(mut samples: Vec<Duration>) -> Timings {
+        samples.sort();
+        let iterations = samples.len();
+        if iterations == 0 {
+            return Timings {
+                iterations,
+                min: Duration::ZERO,
+                max: Duration::ZERO,
+                mean: Duration::ZERO,
+                median: Duration::ZERO,
+            };
+        }
+
+        let total: Duration = samples.iter().sum();
+        Timings {
+            iterations,
+            min: samples[0],
+            max: samples[iterations - 1],
+            mean: total / iterations as u32,
+            median: samples[iterations / 2],
+        }
+    }
+}
+
+/// The result of calling a function with a specific input size
+#[derive(Debug, Clone)]
+pub struct Throughput {
+    /// Input size in bytes
+    pub input_size: usize,
+
+    /// Call timings
+    pub timings: Timings,
+}
+
+impl Throughput {
+    /// Input bytes processed per second, based on the mean call time
+    pub fn bytes_per_second(&self) -> f64 {
+        let secs = self.timings.mean.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.input_size as f64 / secs
+    }
+}
+
+type Configure = Box<dyn Fn(PluginBuilder) -> PluginBuilder>;
+
+/// Measures instantiation time, per-call overhead and throughput for a manifest
+pub struct Bench {
+    manifest: Manifest,
+    wasi: bool,
+    functions: Vec<Function>,
+    iterations: usize,
+    input_sizes: Vec<usize>,
+    configure: Option<Configure>,
+}
+
+impl Bench {
+    /// Create a new `Bench` for the given manifest
+    pub fn new(manifest: Manifest) -> Bench {
+        Bench {
+            manifest,
+            wasi: false,
+            functions: vec![],
+            iterations: 100,
+            input_sizes: vec![0, 1024, 1024 * 1024],
+            configure: None,
+        }
+    }
+
+    /// Enables WASI if the argument is set to `true`
+    pub fn with_wasi(mut self, wasi: bool) -> Self {
+        self.wasi = wasi;
+        self
+    }
+
+    /// Add host functions needed by the plugin
+    pub fn with_functions(mut self, f: impl IntoIterator<Item = Function>) -> Self {
+        self.functions.extend(f);
+        self
+    }
+
+    /// Set the number of times each measurement is repeated, the default is 100
+    pub fn with_iterations(mut self, n: usize) -> Self {
+        self.iterations = n;
+        self
+    }
+
+    /// Set the input sizes used by `Bench::throughput`
+    pub fn with_input_sizes(mut self, sizes: impl IntoIterator<Item = usize>) -> Self {
+        self.input_sizes = sizes.into_iter().collect();
+        self
+    }
+
+    /// Apply additional settings to the `PluginBuilder` used to create each plugin, this can be
+    /// used to compare engine settings
+    pub fn with_builder(mut self, f: impl 'static + Fn(PluginBuilder) -> PluginBuilder) -> Self {
+        self.configure = Some(Box::new(f));
+        self
+    }
+
+    fn plugin(&self) -> Result<Plugin, Error> {
+        let builder = PluginBuilder::new(self.manifest.clone())
+            .with_wasi(self.wasi)
+            .with_functions(self.functions.iter().cloned());
+        match &self.configure {
+            Some(f) => f(builder).build(),
+            None => builder.build(),
+        }
+    }
+
+    /// Measure the time it takes to create and instantiate a new plugin
+    pub fn instantiation(&self) -> Result<Timings, Error> {
+        let mut samples = Vec::with_capacity(self.iterations);
+        for _ in 0..self.iterations {
+            let start = Instant::now();
+            let mut plugin = self.plugin()?;
+            plugin.preinstantiate()?;
+            samples.push(start.elapsed());
+        }
+        Ok(Timings::new(samples))
+    }
+
+    /// Measure the time it takes to call `function` with an empty input
+    pub fn call_overhead(&self, function: impl AsRef<str>) -> Result<Timings, Error> {
+        let mut plugin = self.plugin()?;
+        self.measure(&mut plugin, function.as_ref(), &[])
+    }
+
+    /// Measure the time it takes to call `function` with each of the configured input sizes
+    pub fn throughput(&self, function: impl AsRef<str>) -> Result<Vec<Throughput>, Error> {
+        let mut plugin = self.plugin()?;
+        let mut results = Vec::with_capacity(self.input_sizes.len());
+        for size in self.input_sizes.iter() {
+            let input = vec![b'a'; *size];
+            let timings = self.measure(&mut plugin, function.as_ref(), &input)?;
+            results.push(Throughput {
+                input_size: *size,
+                timings,
+            });
+        }
+        Ok(results)
+    }
+
+    fn measure(&self, plugin: &mut Plugin, function: &str, input: &[u8]) -> Result<Timings, Error> {
+        // Make sure the plugin is instantiated before taking any measurements
+        plugin.call::<_, &[u8]>(function, input)?;
+
+        let mut samples = Vec::with_capacity(self.iterations);
+        for _ in 0..self.iterations {
+            let start = Instant::now();
+            plugin.call::<_, &[u8]>(function, input)?;
+            samples.push(start.elapsed());
+        }
+        Ok(Timings::new(samples))
+    }
+}
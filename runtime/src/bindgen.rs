@@ -0,0 +1,235 @@
+//! Generate typed Rust wrappers from the functions exported by a plugin.
+//!
+//! The generator is meant to be used from a build script, so the wrapper is regenerated whenever the plugin
+//! changes:
+//!
+//! ```rust,no_run
+//! // build.rs
+//! let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+//! let src = extism::bindgen::Bindgen::new("CountVowels")
+//!     .with_schema(&std::fs::read_to_string("count_vowels.json").unwrap())
+//!     .unwrap()
+//!     .generate(&std::fs::read("count_vowels.wasm").unwrap())
+//!     .unwrap();
+//! std::fs::write(out.join("count_vowels.rs"), src).unwrap();
+//! ```
+//!
+//! The generated file can then be included using `include!(concat!(env!("OUT_DIR"), "/count_vowels.rs"))`.
+//!
+//! A schema is optional, it is a JSON object that sets the input and output types for each function. Types
+//! must implement `ToBytes` and `FromBytesOwned` respectively, functions that aren't listed take `&[u8]` and
+//! return `Vec<u8>`:
+//!
+//! ```json
+//! {
+//!   "functions": {
+//!     "count_vowels": {
+//!       "input": "String",
+//!       "output": "extism::convert::Json<Count>",
+//!       "description": "Count the vowels in a string"
+//!     }
+//!   }
+//! }
+//! ```
+use std::fmt::Write;
+
+use crate::*;
+
+// Exports that are used to initialize the guest runtime, these are never wrapped
+const RUNTIME_EXPORTS: &[&str] = &[
+    "_start",
+    "_initialize",
+    "__wasm_call_ctors",
+    "hs_init",
+    "hs_exit",
+];
+
+// Keywords that must be escaped to be used as method names
+const RESERVED: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv",
+    "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Input and output types for a single function
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionSchema {
+    /// Input type, this must implement `ToBytes`
+    #[serde(default = "default_input")]
+    pub input: String,
+
+    /// Output type, this must implement `FromBytesOwned`
+    #[serde(default = "default_output")]
+    pub output: String,
+
+    /// Used as the doc comment for the generated method
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl Default for FunctionSchema {
+    fn default() -> Self {
+        FunctionSchema {
+            input: default_input(),
+            output: default_output(),
+            description: None,
+        }
+    }
+}
+
+fn default_input() -> String {
+    "&[u8]".to_string()
+}
+
+fn default_output() -> String {
+    "Vec<u8>".to_string()
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Schema {
+    #[serde(default)]
+    functions: BTreeMap<String, FunctionSchema>,
+}
+
+/// Generates a wrapper struct with one method for each function exported by a plugin
+#[derive(Debug, Clone)]
+pub struct Bindgen {
+    name: String,
+    functions: BTreeMap<String, FunctionSchema>,
+}
+
+impl Bindgen {
+    /// Create a new generator, `name` is used as the name of the generated struct
+    pub fn new(name: impl Into<String>) -> Bindgen {
+        Bindgen {
+            name: name.into(),
+            functions: BTreeMap::new(),
+        }
+    }
+
+    /// Load function types from a JSON schema
+    pub fn with_schema(mut self, schema: &str) -> Result<Self, Error> {
+        let schema: Schema = serde_json::from_str(schema)?;
+        self.functions.extend(schema.functions);
+        Ok(self)
+    }
+
+    /// Set the types for a single function
+    pub fn with_function(mut self, name: impl Into<String>, f: FunctionSchema) -> Self {
+        self.functions.insert(name.into(), f);
+        self
+    }
+
+    /// Generate Rust source code for the given module, which can be WebAssembly or WAT. Exports that don't
+    /// follow the Extism calling convention are skipped. An error is returned if the schema refers to a function
+    /// that isn't exported, or if two exports map to the same method name.
+    pub fn generate(&self, wasm: impl AsRef<[u8]>) -> Result<String, Error> {
+        if !is_ident(&self.name) {
+            anyhow::bail!("Invalid struct name: {}", self.name);
+        }
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm)?;
+
+        let mut exports = BTreeMap::new();
+        for export in module.exports() {
+            let name = export.name();
+            let ty = match export.ty().func() {
+                Some(ty) => ty.clone(),
+                None => continue,
+            };
+
+            if RUNTIME_EXPORTS.contains(&name) {
+                continue;
+            }
+
+            let results: Vec<_> = ty.results().collect();
+            if ty.params().len() != 0
+                || !matches!(results.as_slice(), [] | [wasmtime::ValType::I32])
+            {
+                if self.functions.contains_key(name) {
+                    anyhow::bail!("Function {name} doesn't use the Extism calling convention");
+                }
+                trace!("Skipping export {name} with type {ty:?}");
+                continue;
+            }
+
+            let method = method_name(name);
+            if let Some(other) = exports.insert(method.clone(), name) {
+                anyhow::bail!("Exports {other} and {name} both map to method {method}");
+            }
+        }
+
+        for name in self.functions.keys() {
+            if !exports.values().any(|x| x == name) {
+                anyhow::bail!("Function {name} is in the schema but isn't exported");
+            }
+        }
+
+        let default = FunctionSchema::default();
+        let mut s = String::new();
+        writeln!(s, "// Generated by extism::bindgen, do not edit")?;
+        writeln!(s)?;
+        writeln!(s, "pub struct {}(pub extism::Plugin);", self.name)?;
+        writeln!(s)?;
+        writeln!(s, "impl {} {{", self.name)?;
+        writeln!(s, "    pub fn new(plugin: extism::Plugin) -> Self {{")?;
+        writeln!(s, "        {}(plugin)", self.name)?;
+        writeln!(s, "    }}")?;
+        for (method, name) in exports {
+            let f = self.functions.get(name).unwrap_or(&default);
+            writeln!(s)?;
+            match &f.description {
+                Some(desc) => {
+                    for line in desc.lines() {
+                        writeln!(s, "    /// {line}")?;
+                    }
+                }
+                None => writeln!(s, "    /// Calls `{name}`")?,
+            }
+            writeln!(
+                s,
+                "    pub fn {method}(&mut self, input: {}) -> Result<{}, extism::Error> {{",
+                f.input, f.output
+            )?;
+            writeln!(s, "        self.0.call({name:?}, input)")?;
+            writeln!(s, "    }}")?;
+        }
+        writeln!(s, "}}")?;
+        Ok(s)
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && s != "_"
+}
+
+// Convert an export name to a valid method name
+fn method_name(export: &str) -> String {
+    let mut s: String = export
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if s.is_empty() {
+        s.push('_');
+    }
+
+    if s.starts_with(|c: char| c.is_ascii_digit()) || s == "_" {
+        s.insert(0, '_');
+    }
+
+    // `self` and `Self` can't be used as raw identifiers, `new` is already defined on the wrapper
+    match s.as_str() {
+        "self" | "Self" | "new" => format!("{s}_"),
+        _ if RESERVED.contains(&s.as_str()) => format!("r#{s}"),
+        _ => s,
+    }
+}
@@ -0,0 +1,366 @@
+// WebAssembly component support, see `ComponentPlugin`
+//
+// Components use the canonical ABI instead of the Extism kernel's memory for input and output. The kernel is still
+// instantiated in the same store so host functions written for `Plugin` can use the `CurrentPlugin` memory functions:
+// the input from the component is copied into kernel memory before each host function is called, and the output is
+// read back from the handle it returns
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use crate::manifest::Compile;
+use crate::plugin::{set_epoch_deadline_callback, set_fuel, Kernel, EXPORT_MODULE_NAME};
+use crate::*;
+
+/// The interface host functions are imported from when they don't have a namespace
+pub const COMPONENT_HOST_INTERFACE: &str = "extism:host/user";
+
+/// A plugin created from a WebAssembly component instead of a core module. Exported functions are called using the
+/// canonical ABI: they take their input as a `list<u8>` and return a `list<u8>`, or a `result<list<u8>, string>`
+/// where the error is returned from `ComponentPlugin::call`. Functions exported from an interface are called using
+/// `interface#function`.
+///
+/// Host functions are imported from their namespace, or `COMPONENT_HOST_INTERFACE` when it isn't set, as
+/// `func(input: list<u8>) -> list<u8>`. They must take a single memory handle and return at most one, so the same
+/// functions can be used by `Plugin` and `ComponentPlugin`. WASI always uses preview 2 for components.
+pub struct ComponentPlugin {
+    /// A unique ID for each plugin
+    pub id: uuid::Uuid,
+
+    store: Store<CurrentPlugin>,
+
+    /// Links the Extism kernel, which is used by host functions to access memory
+    linker: Linker<CurrentPlugin>,
+    kernel: Kernel,
+
+    instance_pre: component::InstancePre<CurrentPlugin>,
+
+    /// Components can't be called again after a trap, a new instance is created for the next call
+    instance: Option<component::Instance>,
+
+    timer_tx: std::sync::mpsc::Sender<TimerAction>,
+    cancel_handle: CancelHandle,
+    interrupted: Arc<AtomicBool>,
+    pub(crate) fuel_limit: Option<u64>,
+    _functions: Vec<Function>,
+}
+
+impl std::fmt::Debug for ComponentPlugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ComponentPlugin({})", self.id)
+    }
+}
+
+impl ComponentPlugin {
+    /// Create a new plugin from the given manifest and host functions, the manifest must contain a single component
+    pub fn new_with_manifest(
+        manifest: &Manifest,
+        functions: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> Result<ComponentPlugin, Error> {
+        let data = serde_json::to_vec(manifest)?;
+        Self::new(data, functions, with_wasi)
+    }
+
+    /// Create a new plugin from the given WebAssembly component or JSON encoded manifest, and host functions
+    pub fn new(
+        wasm: impl AsRef<[u8]>,
+        functions: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> Result<ComponentPlugin, Error> {
+        Self::new_with_options(PluginOptions::default(), wasm, functions, with_wasi)
+    }
+
+    pub(crate) fn new_with_options(
+        options: PluginOptions,
+        wasm: impl AsRef<[u8]>,
+        functions: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> Result<ComponentPlugin, Error> {
+        let PluginOptions {
+            mut config,
+            shared,
+            policy,
+            keys,
+            max_threads,
+            deterministic_seed,
+            precompiled,
+        } = options;
+        if max_threads.is_some() {
+            anyhow::bail!("Threads aren't supported by component plugins");
+        }
+
+        let (manifest, component) = manifest::parse(wasm.as_ref())?;
+        let mut manifest = manifest::resolve_includes(manifest, None)?;
+        manifest::check_precompiled(&manifest, precompiled)?;
+        if deterministic_seed.is_some() {
+            manifest.deterministic_seed = deterministic_seed;
+        }
+        if with_wasi {
+            manifest.wasi.preview2 = Some(true);
+        }
+        let policy = policy.unwrap_or_else(|| Policy::from_manifest(&manifest));
+        config.update(&manifest);
+        config.component_model = true;
+        let engine = if shared {
+            module_cache::engine(&config)?
+        } else {
+            config.engine()?
+        };
+
+        let component = match component {
+            Some(data) => component::Component::compile(&engine, data)?,
+            None if manifest.wasm.len() == 1 => {
+                let (mut components, _) =
+                    manifest::modules::<component::Component>(&manifest, &engine, keys.as_ref())?;
+                components.remove("main").unwrap()
+            }
+            None => anyhow::bail!("Component plugins must have exactly one component"),
+        };
+
+        let available_pages = manifest.memory.max_pages;
+        let mut store = Store::new(
+            &engine,
+            CurrentPlugin::new(manifest, policy, with_wasi, available_pages)?,
+        );
+
+        let interrupted = Arc::new(AtomicBool::new(false));
+        set_epoch_deadline_callback(&mut store, interrupted.clone());
+        set_fuel(&mut store, None);
+
+        let mut linker = Linker::new(&engine);
+        linker.module(&mut store, EXPORT_MODULE_NAME, &manifest::kernel(&engine)?)?;
+        let kernel = Kernel::new(&linker, &mut store)?;
+
+        let functions: Vec<Function> = functions.into_iter().collect();
+        let mut component_linker = component::Linker::new(&engine);
+        if with_wasi {
+            wasmtime_wasi::preview2::command::sync::add_to_linker(&mut component_linker)?;
+        }
+        define_functions(&mut component_linker, &functions)?;
+        let instance_pre = component_linker.instantiate_pre(&component)?;
+
+        let id = uuid::Uuid::new_v4();
+        store.data_mut().id = id;
+        if store.data().memory_limiter.is_some() {
+            store.limiter(|internal| internal.memory_limiter.as_mut().unwrap());
+        }
+
+        let timer_tx = Timer::tx();
+        Ok(ComponentPlugin {
+            id,
+            store,
+            linker,
+            kernel,
+            instance_pre,
+            instance: None,
+            timer_tx: timer_tx.clone(),
+            cancel_handle: CancelHandle {
+                id,
+                timer_tx,
+                interrupted: None,
+            },
+            interrupted,
+            fuel_limit: None,
+            _functions: functions,
+        })
+    }
+
+    /// Get the plugin's ID
+    pub fn id(&self) -> uuid::Uuid {
+        self.id
+    }
+
+    /// Get a `CancelHandle`, which can be used from another thread to cancel a running plugin
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel_handle.clone()
+    }
+
+    /// Returns `true` if the given function exists, use `interface#function` for functions exported from an
+    /// interface
+    pub fn function_exists(&mut self, function: impl AsRef<str>) -> bool {
+        self.get_func(function.as_ref()).is_ok_and(|x| x.is_some())
+    }
+
+    /// Call a function by name with the given input, the output is decoded using `FromBytesOwned` since it's
+    /// copied out of the component's memory
+    pub fn call<'a, T: ToBytes<'a>, U: FromBytesOwned>(
+        &mut self,
+        name: impl AsRef<str>,
+        input: T,
+    ) -> Result<U, Error> {
+        let input = input.to_bytes()?;
+        let output = self.call_bytes(name, input.as_ref())?;
+        U::from_bytes_owned(&output)
+    }
+
+    /// Call a function by name with the given input and return the output bytes
+    pub fn call_bytes(&mut self, name: impl AsRef<str>, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let name = name.as_ref();
+        let start = std::time::Instant::now();
+        self.call_inner(name, input).map_err(|e| {
+            let ctx = ErrorContext {
+                plugin_id: self.id,
+                function: name.to_string(),
+                source: error::manifest_source(&self.store.data().manifest),
+                elapsed: start.elapsed(),
+            };
+            e.context(ctx)
+        })
+    }
+
+    fn call_inner(&mut self, name: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
+        self.update_internal_pointers();
+        let func = match self.get_func(name)? {
+            Some(f) => f,
+            None => anyhow::bail!("Function not found: {name}"),
+        };
+
+        // Free any memory used by host functions during the last call
+        self.kernel.reset.call(&mut self.store, &[], &mut [])?;
+
+        let timeout = self.store.data().manifest.timeout_for(name);
+        self.interrupted.store(false, Ordering::SeqCst);
+        self.timer_tx
+            .send(TimerAction::Start {
+                id: self.id,
+                engine: self.store.engine().clone(),
+                duration: timeout.map(std::time::Duration::from_millis),
+                interrupted: self.interrupted.clone(),
+            })
+            .unwrap();
+        set_fuel(&mut self.store, self.fuel_limit);
+
+        let res = call_func(&mut self.store, func, input);
+
+        self.timer_tx
+            .send(TimerAction::Stop { id: self.id })
+            .unwrap();
+        self.interrupted.store(false, Ordering::SeqCst);
+        set_fuel(&mut self.store, self.fuel_limit.map(|_| u32::MAX as u64));
+
+        match res {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(e)) => Err(Error::msg(e)),
+            Err(e) => {
+                self.instance = None;
+                if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+                    return Err(Error::msg("out of fuel"));
+                }
+
+                let cause = e.root_cause().to_string();
+                if cause == "timeout" || cause == "oom" {
+                    return Err(Error::msg(cause));
+                }
+
+                error!("Plugin {}: call to {name} failed: {e:?}", self.id);
+                Err(e.context("Call failed"))
+            }
+        }
+    }
+
+    // Find an exported function, instantiating the component if needed
+    fn get_func(&mut self, name: &str) -> Result<Option<component::Func>, Error> {
+        let instance = match self.instance {
+            Some(x) => x,
+            None => {
+                self.update_internal_pointers();
+                let instance = self.instance_pre.instantiate(&mut self.store)?;
+                self.instance = Some(instance);
+                instance
+            }
+        };
+
+        let mut exports = instance.exports(&mut self.store);
+        let func = match name.split_once('#') {
+            Some((interface, name)) => exports.instance(interface).and_then(|mut x| x.func(name)),
+            None => exports.root().func(name),
+        };
+        Ok(func)
+    }
+
+    // Point `CurrentPlugin` at this plugin's store and linker, these need to be updated after the plugin has been
+    // moved
+    fn update_internal_pointers(&mut self) {
+        let store = &mut self.store as *mut _;
+        let linker = &mut self.linker as *mut _;
+        let current_plugin = self.store.data_mut();
+        current_plugin.store = store;
+        current_plugin.linker = linker;
+    }
+}
+
+// Call an exported function, the outer error is a trap and the inner error is returned by the function
+fn call_func(
+    store: &mut Store<CurrentPlugin>,
+    func: component::Func,
+    input: &[u8],
+) -> Result<Result<Vec<u8>, String>, Error> {
+    if let Ok(f) = func.typed::<(&[u8],), (Result<Vec<u8>, String>,)>(&*store) {
+        let (output,) = f.call(&mut *store, (input,))?;
+        f.post_return(&mut *store)?;
+        return Ok(output);
+    }
+
+    let f = func
+        .typed::<(&[u8],), (Vec<u8>,)>(&*store)
+        .context("Exported functions must take a `list<u8>` and return a `list<u8>` or `result<list<u8>, string>`")?;
+    let (output,) = f.call(&mut *store, (input,))?;
+    f.post_return(&mut *store)?;
+    Ok(Ok(output))
+}
+
+// Define host functions as `func(input: list<u8>) -> list<u8>`, grouped by the interface they're imported from
+fn define_functions(
+    linker: &mut component::Linker<CurrentPlugin>,
+    functions: &[Function],
+) -> Result<(), Error> {
+    let mut interfaces: BTreeMap<&str, Vec<&Function>> = BTreeMap::new();
+    for f in functions {
+        let ty = f.ty();
+        if !ty.params().eq([wasmtime::ValType::I64])
+            || !ty.results().all(|x| x == wasmtime::ValType::I64)
+            || ty.results().len() > 1
+        {
+            anyhow::bail!(
+                "Host function {} can't be used by a component, it must take one memory handle and return at most one",
+                f.name()
+            );
+        }
+        let interface = f.namespace().unwrap_or(COMPONENT_HOST_INTERFACE);
+        interfaces.entry(interface).or_default().push(f);
+    }
+
+    for (interface, functions) in interfaces {
+        let mut instance = linker.instance(interface)?;
+        for f in functions {
+            let func = f.f.clone();
+            let n_results = f.ty().results().len();
+            instance.func_wrap(
+                f.name(),
+                move |mut store: StoreContextMut<CurrentPlugin>, (input,): (Vec<u8>,)| {
+                    let plugin = store.data_mut();
+                    let input = plugin.memory_new(&input)?;
+                    let params = [plugin.memory_to_val(input)];
+                    let mut results = vec![Val::I64(0); n_results];
+                    func(plugin, &params, &mut results)?;
+
+                    let output = match results.first().and_then(|x| plugin.memory_from_val(x)) {
+                        Some(handle) => {
+                            let output = plugin.memory_bytes(handle)?.to_vec();
+                            if handle.offset() != input.offset() {
+                                plugin.memory_free(handle)?;
+                            }
+                            output
+                        }
+                        None => vec![],
+                    };
+                    plugin.memory_free(input)?;
+                    Ok((output,))
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
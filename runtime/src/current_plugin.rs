@@ -3,30 +3,166 @@ use crate::*;
 /// CurrentPlugin stores data that is available to the caller in PDK functions, this should
 /// only be accessed from inside a host function
 pub struct CurrentPlugin {
-    /// Plugin variables
-    pub(crate) vars: std::collections::BTreeMap<String, Vec<u8>>,
+    /// The ID of the plugin this belongs to, see `Plugin::id`
+    pub(crate) id: uuid::Uuid,
+
+    /// Plugin variables, values are reference counted so they can be cloned cheaply
+    pub(crate) vars: std::collections::BTreeMap<String, Bytes>,
 
     /// Extism manifest
     pub(crate) manifest: extism_manifest::Manifest,
+
+    /// Capabilities granted to the plugin
+    pub(crate) policy: Policy,
     pub(crate) store: *mut Store<CurrentPlugin>,
     pub(crate) linker: *mut wasmtime::Linker<CurrentPlugin>,
     pub(crate) wasi: Option<Wasi>,
+    pub(crate) wasi_preview2: Option<WasiPreview2>,
+    #[cfg(feature = "wasi-nn")]
+    pub(crate) wasi_nn: Option<crate::wasi_nn::WasiNn>,
     pub(crate) http_status: u16,
     pub(crate) available_pages: Option<u32>,
     pub(crate) memory_limiter: Option<MemoryLimiter>,
+
+    /// When the current call times out, only used by plugins that use the epoch ticker
+    pub(crate) deadline: Option<std::time::Instant>,
+
+    /// The number of times each host function was called during the current call, in the order the functions were
+    /// passed to the plugin
+    pub(crate) host_calls: Vec<u64>,
+
+    /// Receives chunks written using `extism_output_write`, only set while a function called with
+    /// `Plugin::call_streaming` is running
+    pub(crate) output_sink: Option<*mut OutputSink<'static>>,
+
+    /// The kernel's memory, this is looked up along with the kernel functions since a plugin using threads may
+    /// shadow `env::memory` with its shared memory
+    pub(crate) kernel_memory: Option<Memory>,
+
+    /// The instance of the main module, used to find memories by name in `CurrentPlugin::memory_index`
+    pub(crate) instance: Option<Instance>,
+
+    /// Memories returned by `CurrentPlugin::memory_index` along with their names, a handle's memory is an index into
+    /// this list plus one since `0` is Extism memory
+    pub(crate) memories: Vec<(String, Memory)>,
+
+    /// Used by `wasi::thread-spawn`, set when the plugin is created using `PluginBuilder::with_threads`
+    pub(crate) threads: Option<std::sync::Arc<Threads>>,
 }
 
+/// The callback passed to `Plugin::call_streaming`
+pub(crate) type OutputSink<'a> = dyn FnMut(&[u8]) -> Result<(), Error> + 'a;
+
 unsafe impl Send for CurrentPlugin {}
 
+// Only used when the linker was created with WASI preview 2, see `WasiOptions::preview2`
+impl wasmtime_wasi::preview2::WasiView for CurrentPlugin {
+    fn table(&self) -> &wasmtime_wasi::preview2::Table {
+        &self.wasi_preview2.as_ref().unwrap().table
+    }
+
+    fn table_mut(&mut self) -> &mut wasmtime_wasi::preview2::Table {
+        &mut self.wasi_preview2.as_mut().unwrap().table
+    }
+
+    fn ctx(&self) -> &wasmtime_wasi::preview2::WasiCtx {
+        &self.wasi_preview2.as_ref().unwrap().ctx
+    }
+
+    fn ctx_mut(&mut self) -> &mut wasmtime_wasi::preview2::WasiCtx {
+        &mut self.wasi_preview2.as_mut().unwrap().ctx
+    }
+}
+
+impl wasmtime_wasi::preview2::preview1::WasiPreview1View for CurrentPlugin {
+    fn adapter(&self) -> &wasmtime_wasi::preview2::preview1::WasiPreview1Adapter {
+        &self.wasi_preview2.as_ref().unwrap().adapter
+    }
+
+    fn adapter_mut(&mut self) -> &mut wasmtime_wasi::preview2::preview1::WasiPreview1Adapter {
+        &mut self.wasi_preview2.as_mut().unwrap().adapter
+    }
+}
+
+// Create a WASI preview 2 context with the same environment, arguments, stdio and directories as the preview 1
+// context created in `CurrentPlugin::new`
+fn wasi_preview2(manifest: &Manifest, policy: &Policy) -> Result<WasiPreview2, Error> {
+    use wasmtime_wasi::preview2::{DirPerms, FilePerms, IsATTY};
+
+    let opts = &manifest.wasi;
+    if opts.random == Some(false) || opts.clocks == Some(false) {
+        anyhow::bail!("WASI random and clocks can't be disabled when using WASI preview 2");
+    }
+
+    let mut builder = wasmtime_wasi::preview2::WasiCtxBuilder::new();
+    if let Some(seed) = manifest.deterministic_seed {
+        builder
+            .secure_random(crate::wasi::SeededRandom::new(seed))
+            .insecure_random(crate::wasi::SeededRandom::new(!seed))
+            .insecure_random_seed(seed as u128)
+            .wall_clock(crate::wasi::VirtualClock::new())
+            .monotonic_clock(crate::wasi::VirtualClock::new());
+    }
+
+    let mut env = BTreeMap::new();
+    if opts.environment.unwrap_or(true) {
+        env.extend(manifest.config.iter());
+    }
+    env.extend(opts.env.iter());
+    for (k, v) in env {
+        builder.env(k, v);
+    }
+
+    if !opts.args.is_empty() {
+        builder.args(&opts.args);
+    } else if opts.inherit_args.unwrap_or(false) {
+        builder.args(&std::env::args().collect::<Vec<_>>());
+    }
+
+    let output = std::env::var("EXTISM_ENABLE_WASI_OUTPUT").is_ok();
+    if opts.stdout.unwrap_or(output) {
+        builder.stdout(wasmtime_wasi::preview2::stdout(), IsATTY::No);
+    }
+    if opts.stderr.unwrap_or(output) {
+        builder.stderr(wasmtime_wasi::preview2::stderr(), IsATTY::No);
+    }
+
+    let auth = wasmtime_wasi::ambient_authority();
+    for (k, v) in policy.fs_write_paths() {
+        let d = wasmtime_wasi::Dir::open_ambient_dir(k, auth)?;
+        builder.preopened_dir(d, DirPerms::all(), FilePerms::all(), v.to_string_lossy());
+    }
+    for (k, v) in policy.fs_read_paths() {
+        let d = wasmtime_wasi::Dir::open_ambient_dir(k, auth)?;
+        builder.preopened_dir(d, DirPerms::READ, FilePerms::READ, v.to_string_lossy());
+    }
+
+    let mut table = wasmtime_wasi::preview2::Table::new();
+    let ctx = builder.build(&mut table)?;
+    Ok(WasiPreview2 {
+        ctx,
+        table,
+        adapter: wasmtime_wasi::preview2::preview1::WasiPreview1Adapter::new(),
+        #[cfg(feature = "wasi-http")]
+        http: wasmtime_wasi_http::WasiHttpCtx::new(),
+    })
+}
+
 pub(crate) struct MemoryLimiter {
     bytes_left: usize,
     max_bytes: usize,
+    max_table_elements: Option<u32>,
+    max_instances: Option<usize>,
 }
 
 impl MemoryLimiter {
     pub(crate) fn reset(&mut self) {
         self.bytes_left = self.max_bytes;
     }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes_left
+    }
 }
 
 impl wasmtime::ResourceLimiter for MemoryLimiter {
@@ -52,15 +188,31 @@ impl wasmtime::ResourceLimiter for MemoryLimiter {
     }
 
     fn table_growing(&mut self, _current: u32, desired: u32, maximum: Option<u32>) -> Result<bool> {
+        if let Some(max) = self.max_table_elements {
+            if desired > max {
+                return Ok(false);
+            }
+        }
+
         if let Some(max) = maximum {
             return Ok(desired <= max);
         }
 
         Ok(true)
     }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+            .unwrap_or(wasmtime::DEFAULT_INSTANCE_LIMIT)
+    }
 }
 
 impl CurrentPlugin {
+    /// The ID of the plugin that's being called, this can be used to correlate host function calls with a plugin
+    pub fn plugin_id(&self) -> uuid::Uuid {
+        self.id
+    }
+
     /// Get a `MemoryHandle` from a memory offset
     pub fn memory_handle(&mut self, offs: u64) -> Option<MemoryHandle> {
         let len = self.memory_length(offs);
@@ -71,6 +223,7 @@ impl CurrentPlugin {
         Some(MemoryHandle {
             offset: offs,
             length: len,
+            memory: 0,
         })
     }
 
@@ -110,14 +263,27 @@ impl CurrentPlugin {
         }
     }
 
+    /// Access the bytes of a memory handle, handles in memories other than Extism memory are checked against the
+    /// size of their memory
     pub fn memory_bytes(&mut self, handle: MemoryHandle) -> Result<&mut [u8], Error> {
-        let (linker, mut store) = self.linker_and_store();
-        let mem = linker
-            .get(&mut store, "env", "memory")
-            .unwrap()
-            .into_memory()
-            .unwrap();
-        let ptr = unsafe { mem.data_ptr(&store).add(handle.offset() as usize) };
+        if handle.memory() != 0 {
+            let mem = match self.memories.get(handle.memory() as usize - 1) {
+                Some((_, mem)) => *mem,
+                None => anyhow::bail!("Invalid memory index: {}", handle.memory()),
+            };
+            let (_, store) = self.linker_and_store();
+            let start = handle.offset() as usize;
+            return mem
+                .data_mut(store)
+                .get_mut(start..start + handle.len())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Memory region {start}+{} is out of bounds", handle.len())
+                });
+        }
+
+        let mem = self.kernel_memory.unwrap();
+        let (_, store) = self.linker_and_store();
+        let ptr = unsafe { mem.data_ptr(store).add(handle.offset() as usize) };
         if ptr.is_null() {
             return Ok(&mut []);
         }
@@ -126,10 +292,7 @@ impl CurrentPlugin {
 
     pub fn memory_alloc(&mut self, n: u64) -> Result<MemoryHandle, Error> {
         if n == 0 {
-            return Ok(MemoryHandle {
-                offset: 0,
-                length: 0,
-            });
+            return Ok(MemoryHandle::null());
         }
         let (linker, mut store) = self.linker_and_store();
         let output = &mut [Val::I64(0)];
@@ -144,15 +307,19 @@ impl CurrentPlugin {
         if offs == 0 {
             anyhow::bail!("out of memory")
         }
-        trace!("memory_alloc: {}, {}", offs, n);
+        trace!("Plugin {}: memory_alloc: {}, {}", self.id, offs, n);
         Ok(MemoryHandle {
             offset: offs,
             length: n,
+            memory: 0,
         })
     }
 
     /// Free a block of Extism plugin memory
     pub fn memory_free(&mut self, handle: MemoryHandle) -> Result<(), Error> {
+        if handle.memory() != 0 {
+            anyhow::bail!("Only handles in Extism memory can be freed");
+        }
         let (linker, mut store) = self.linker_and_store();
         linker
             .get(&mut store, "env", "extism_free")
@@ -163,6 +330,44 @@ impl CurrentPlugin {
         Ok(())
     }
 
+    /// Find a memory exported by the plugin and return an index that can be used with `CurrentPlugin::memory_region`,
+    /// this can be used to access memories other than Extism memory in plugins that use multiple memories, see
+    /// `PluginBuilder::with_multi_memory`. Memories exported by the main module are found by name, other modules in
+    /// the manifest are searched using `module::name`. Indexes are valid until the plugin is instantiated again.
+    pub fn memory_index(&mut self, name: &str) -> Option<u32> {
+        if let Some(index) = self.memories.iter().position(|(x, _)| x == name) {
+            return Some(index as u32 + 1);
+        }
+
+        let instance = self.instance;
+        let (linker, store) = self.linker_and_store();
+        let memory = match name.split_once("::") {
+            Some((module, name)) => linker
+                .get(&mut *store, module, name)
+                .and_then(|x| x.into_memory()),
+            None => instance.and_then(|instance| instance.get_memory(&mut *store, name)),
+        }?;
+        self.memories.push((name.to_string(), memory));
+        Some(self.memories.len() as u32)
+    }
+
+    /// Get a `MemoryHandle` for a region of a memory returned by `CurrentPlugin::memory_index`, `None` is returned
+    /// when the region is out of bounds. These handles can be read using `CurrentPlugin::memory_bytes` but can't be
+    /// freed or passed back to the plugin using `CurrentPlugin::memory_to_val`.
+    pub fn memory_region(&mut self, memory: u32, offset: u64, length: u64) -> Option<MemoryHandle> {
+        let (_, mem) = *self.memories.get((memory as usize).checked_sub(1)?)?;
+        let (_, store) = self.linker_and_store();
+        if offset.checked_add(length)? > mem.data_size(&*store) as u64 {
+            return None;
+        }
+
+        Some(MemoryHandle {
+            offset,
+            length,
+            memory,
+        })
+    }
+
     pub fn memory_length(&mut self, offs: u64) -> u64 {
         let (linker, mut store) = self.linker_and_store();
         let output = &mut [Val::I64(0)];
@@ -174,17 +379,18 @@ impl CurrentPlugin {
             .call(&mut store, &[Val::I64(offs as i64)], output)
             .unwrap();
         let len = output[0].unwrap_i64() as u64;
-        trace!("memory_length: {}, {}", offs, len);
+        trace!("Plugin {}: memory_length: {}, {}", self.id, offs, len);
         len
     }
 
-    /// Access a plugin's variables
-    pub fn vars(&self) -> &std::collections::BTreeMap<String, Vec<u8>> {
+    /// Access a plugin's variables, cloning a value doesn't copy the underlying data
+    pub fn vars(&self) -> &std::collections::BTreeMap<String, Bytes> {
         &self.vars
     }
 
-    /// Mutable access to a plugin's variables
-    pub fn vars_mut(&mut self) -> &mut std::collections::BTreeMap<String, Vec<u8>> {
+    /// Mutable access to a plugin's variables, a `Vec<u8>` can be converted to `Bytes` without copying
+    /// using `Bytes::from`
+    pub fn vars_mut(&mut self) -> &mut std::collections::BTreeMap<String, Bytes> {
         &mut self.vars
     }
 
@@ -193,67 +399,162 @@ impl CurrentPlugin {
         &self.manifest
     }
 
+    /// Get a config value as `T`, see `Manifest::config_get`
+    pub fn config_get<T: serde::de::DeserializeOwned>(
+        &self,
+        key: impl AsRef<str>,
+    ) -> Result<Option<T>, Error> {
+        Ok(self.manifest.config_get(key)?)
+    }
+
+    /// The policy used to check HTTP requests, WASI access and plugin variables
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
     pub(crate) fn new(
         manifest: extism_manifest::Manifest,
+        policy: Policy,
         wasi: bool,
         available_pages: Option<u32>,
     ) -> Result<Self, Error> {
-        let wasi = if wasi {
+        let preview2 = wasi && manifest.wasi.preview2.unwrap_or(false);
+        let wasi_preview2 = if preview2 {
+            Some(wasi_preview2(&manifest, &policy)?)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "wasi-nn")]
+        let wasi_nn = if wasi {
+            Some(crate::wasi_nn::WasiNn::new(&manifest.wasi.nn)?)
+        } else {
+            None
+        };
+
+        let wasi = if wasi && !preview2 {
             let auth = wasmtime_wasi::ambient_authority();
-            let mut ctx = wasmtime_wasi::WasiCtxBuilder::new();
-            for (k, v) in manifest.config.iter() {
-                ctx.env(k, v)?;
+            let opts = &manifest.wasi;
+            let random: Box<dyn wasi_common::RngCore + Send + Sync> =
+                match (opts.random.unwrap_or(true), manifest.deterministic_seed) {
+                    (false, _) => Box::new(crate::wasi::NoRandom),
+                    (true, Some(seed)) => Box::new(crate::wasi::SeededRandom::new(seed)),
+                    (true, None) => wasmtime_wasi::sync::random_ctx(),
+                };
+            let clocks = match (opts.clocks.unwrap_or(true), manifest.deterministic_seed) {
+                (false, _) => wasi_common::WasiClocks::new(),
+                (true, Some(_)) => wasi_common::WasiClocks::new()
+                    .with_system(crate::wasi::VirtualClock::new())
+                    .with_monotonic(crate::wasi::VirtualClock::new()),
+                (true, None) => wasmtime_wasi::sync::clocks_ctx(),
+            };
+            let mut ctx = wasmtime_wasi::WasiCtx::new(
+                random,
+                clocks,
+                wasmtime_wasi::sync::sched_ctx(),
+                wasi_common::Table::new(),
+            );
+
+            let mut env = BTreeMap::new();
+            if opts.environment.unwrap_or(true) {
+                env.extend(manifest.config.iter());
+            }
+            env.extend(opts.env.iter());
+            for (k, v) in env {
+                ctx.push_env(k, v)?;
             }
 
-            if let Some(a) = &manifest.allowed_paths {
-                for (k, v) in a.iter() {
-                    let d = wasmtime_wasi::Dir::open_ambient_dir(k, auth)?;
-                    ctx.preopened_dir(d, v)?;
+            if !opts.args.is_empty() {
+                for arg in opts.args.iter() {
+                    ctx.push_arg(arg)?;
                 }
+            } else if opts.inherit_args.unwrap_or(false) {
+                for arg in std::env::args() {
+                    ctx.push_arg(&arg)?;
+                }
+            }
+
+            for (k, v) in policy.fs_write_paths() {
+                let d = wasmtime_wasi::Dir::open_ambient_dir(k, auth)?;
+                let d = wasmtime_wasi::sync::dir::Dir::from_cap_std(d);
+                ctx.push_preopened_dir(Box::new(d), v)?;
+            }
+
+            // WASI output is typically used for debugging purposes, it can be enabled for every plugin using
+            // `EXTISM_ENABLE_WASI_OUTPUT`
+            let output = std::env::var("EXTISM_ENABLE_WASI_OUTPUT").is_ok();
+            if opts.stdout.unwrap_or(output) {
+                ctx.set_stdout(Box::new(wasmtime_wasi::sync::stdio::stdout()));
+            }
+            if opts.stderr.unwrap_or(output) {
+                ctx.set_stderr(Box::new(wasmtime_wasi::sync::stdio::stderr()));
             }
 
-            // Enable WASI output, typically used for debugging purposes
-            if std::env::var("EXTISM_ENABLE_WASI_OUTPUT").is_ok() {
-                ctx.inherit_stdout().inherit_stderr();
+            for (k, v) in policy.fs_read_paths() {
+                ctx.push_preopened_dir(Box::new(crate::wasi::ReadOnlyDir::open(k)?), v)?;
             }
 
-            Some(Wasi { ctx: ctx.build() })
+            Some(Wasi { ctx })
         } else {
             None
         };
 
-        let memory_limiter = if let Some(pgs) = available_pages {
-            let n = pgs as usize * 65536;
+        let memory = &manifest.memory;
+        let memory_limiter = if available_pages.is_some()
+            || memory.max_table_elements.is_some()
+            || memory.max_instances.is_some()
+        {
+            let n = available_pages.map_or(usize::MAX, |pgs| pgs as usize * 65536);
             Some(crate::current_plugin::MemoryLimiter {
                 max_bytes: n,
                 bytes_left: n,
+                max_table_elements: memory.max_table_elements,
+                max_instances: memory.max_instances.map(|x| x as usize),
             })
         } else {
             None
         };
 
         Ok(CurrentPlugin {
+            id: uuid::Uuid::nil(),
             wasi,
+            wasi_preview2,
+            #[cfg(feature = "wasi-nn")]
+            wasi_nn,
             manifest,
+            policy,
             http_status: 0,
             vars: BTreeMap::new(),
             linker: std::ptr::null_mut(),
             store: std::ptr::null_mut(),
             available_pages,
             memory_limiter,
+            deadline: None,
+            host_calls: vec![],
+            output_sink: None,
+            kernel_memory: None,
+            instance: None,
+            memories: vec![],
+            threads: None,
         })
     }
 
+    pub(crate) fn count_host_call(&mut self, index: usize) {
+        if self.host_calls.len() <= index {
+            self.host_calls.resize(index + 1, 0);
+        }
+        self.host_calls[index] += 1;
+    }
+
     /// Get a pointer to the plugin memory
     pub(crate) fn memory_ptr(&mut self) -> *mut u8 {
-        let (linker, mut store) = self.linker_and_store();
-        if let Some(mem) = linker.get(&mut store, "env", "memory") {
-            if let Some(mem) = mem.into_memory() {
-                return mem.data_ptr(&mut store);
+        match self.kernel_memory {
+            Some(mem) => {
+                let (_, store) = self.linker_and_store();
+                mem.data_ptr(store)
             }
+            None => std::ptr::null_mut(),
         }
-
-        std::ptr::null_mut()
     }
 
     /// Get a `MemoryHandle` from a `Val` reference - this can be used to convert a host function's
@@ -267,6 +568,7 @@ impl CurrentPlugin {
         Some(MemoryHandle {
             offset: offs,
             length,
+            memory: 0,
         })
     }
 
@@ -278,7 +580,7 @@ impl CurrentPlugin {
 
     /// Clear the current plugin error
     pub fn clear_error(&mut self) {
-        trace!("CurrentPlugin::clear_error");
+        trace!("Plugin {}: CurrentPlugin::clear_error", self.id);
         let (linker, mut store) = self.linker_and_store();
         if let Some(f) = linker.get(&mut store, "env", "extism_error_set") {
             f.into_func()
@@ -312,6 +614,7 @@ impl CurrentPlugin {
         let s = self.memory_str(MemoryHandle {
             offset: offs,
             length,
+            memory: 0,
         });
         match s {
             Ok(s) => Some(s),
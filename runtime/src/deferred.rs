@@ -0,0 +1,215 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::*;
+
+/// Determines what happens when a `DeferredPlugin` is called before it's ready
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeferredCallPolicy {
+    /// Block until the plugin is ready, then make the call
+    #[default]
+    Queue,
+
+    /// Return an error immediately
+    Reject,
+}
+
+type ReadyCallback = Box<dyn FnOnce(Result<(), Error>) + Send>;
+
+struct State {
+    // `None` until the background thread has finished, errors are stored as strings since they are
+    // reported to every waiter
+    plugin: Option<Result<Plugin, String>>,
+    callbacks: Vec<ReadyCallback>,
+    wakers: Vec<std::task::Waker>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+}
+
+fn status(plugin: &Result<Plugin, String>) -> Result<(), Error> {
+    match plugin {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::msg(e.clone())),
+    }
+}
+
+/// A plugin that is compiled and instantiated on a background thread, returned by
+/// `PluginBuilder::build_deferred` and `Plugin::new_deferred`
+#[derive(Clone)]
+pub struct DeferredPlugin {
+    shared: Arc<Shared>,
+    policy: DeferredCallPolicy,
+}
+
+impl DeferredPlugin {
+    pub(crate) fn new(builder: PluginBuilder) -> DeferredPlugin {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                plugin: None,
+                callbacks: vec![],
+                wakers: vec![],
+            }),
+            ready: Condvar::new(),
+        });
+
+        let s = shared.clone();
+        std::thread::spawn(move || {
+            let plugin = builder.build().and_then(|mut plugin| {
+                plugin.preinstantiate()?;
+                Ok(plugin)
+            });
+
+            let plugin = plugin.map_err(|e| format!("{e:?}"));
+            if let Err(e) = &plugin {
+                error!("Deferred plugin failed to load: {e}");
+            }
+
+            let (callbacks, wakers) = {
+                let mut state = s.lock();
+                state.plugin = Some(plugin);
+                (
+                    std::mem::take(&mut state.callbacks),
+                    std::mem::take(&mut state.wakers),
+                )
+            };
+            s.ready.notify_all();
+
+            for waker in wakers {
+                waker.wake();
+            }
+
+            for callback in callbacks {
+                let res = status(s.lock().plugin.as_ref().unwrap());
+                callback(res);
+            }
+        });
+
+        DeferredPlugin {
+            shared,
+            policy: DeferredCallPolicy::default(),
+        }
+    }
+
+    /// Set the policy used for calls made before the plugin is ready
+    pub fn with_policy(mut self, policy: DeferredCallPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns `true` once the plugin has finished loading, successfully or not
+    pub fn is_ready(&self) -> bool {
+        self.shared.lock().plugin.is_some()
+    }
+
+    /// Register a callback that will be called once the plugin has finished loading, if the plugin is
+    /// already loaded the callback is called immediately
+    pub fn on_ready(&self, f: impl FnOnce(Result<(), Error>) + Send + 'static) {
+        let mut state = self.shared.lock();
+        match &state.plugin {
+            Some(plugin) => {
+                let res = status(plugin);
+                drop(state);
+                f(res)
+            }
+            None => state.callbacks.push(Box::new(f)),
+        }
+    }
+
+    /// Returns a future that resolves once the plugin has finished loading
+    pub fn ready(&self) -> Ready {
+        Ready {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Block until the plugin has finished loading
+    pub fn wait(&self) -> Result<(), Error> {
+        let state = self.wait_lock();
+        status(state.plugin.as_ref().unwrap())
+    }
+
+    fn wait_lock(&self) -> MutexGuard<'_, State> {
+        let mut state = self.shared.lock();
+        while state.plugin.is_none() {
+            state = match self.shared.ready.wait(state) {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+        }
+        state
+    }
+
+    /// Call a function by name, if the plugin isn't ready yet then the call will be queued or rejected
+    /// depending on the `DeferredCallPolicy`
+    pub fn call<'a, T: ToBytes<'a>, U: FromBytesOwned>(
+        &self,
+        name: impl AsRef<str>,
+        input: T,
+    ) -> Result<U, Error> {
+        let mut state = self.shared.lock();
+        if state.plugin.is_none() {
+            match self.policy {
+                DeferredCallPolicy::Reject => anyhow::bail!("Plugin is not ready"),
+                DeferredCallPolicy::Queue => {
+                    drop(state);
+                    state = self.wait_lock();
+                }
+            }
+        }
+
+        match state.plugin.as_mut().unwrap() {
+            Ok(plugin) => plugin.call(name, input),
+            Err(e) => Err(Error::msg(e.clone())),
+        }
+    }
+
+    /// Wait for the plugin to finish loading and take ownership of it. This will return an error if
+    /// the plugin has already been taken by another clone of the `DeferredPlugin`.
+    pub fn into_plugin(self) -> Result<Plugin, Error> {
+        let mut state = self.wait_lock();
+        match state.plugin.take().unwrap() {
+            Ok(plugin) => {
+                state.plugin = Some(Err("Plugin has already been taken".to_string()));
+                Ok(plugin)
+            }
+            Err(e) => {
+                state.plugin = Some(Err(e.clone()));
+                Err(Error::msg(e))
+            }
+        }
+    }
+}
+
+/// A future that resolves once a `DeferredPlugin` has finished loading
+pub struct Ready {
+    shared: Arc<Shared>,
+}
+
+impl std::future::Future for Ready {
+    type Output = Result<(), Error>;
+
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let mut state = self.shared.lock();
+        match &state.plugin {
+            Some(plugin) => std::task::Poll::Ready(status(plugin)),
+            None => {
+                state.wakers.push(cx.waker().clone());
+                std::task::Poll::Pending
+            }
+        }
+    }
+}
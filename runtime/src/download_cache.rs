@@ -0,0 +1,185 @@
+// On-disk cache for modules downloaded using `Wasm::Url`
+//
+// Entries are keyed by the URL and the expected hash. Modules with a hash are loaded from the cache without making
+// a request, modules without a hash are revalidated using the `ETag` from the response they were cached from. Since
+// nothing verifies those entries, modules without a hash are only cached in a directory set by the host or in the
+// default directory when it's private to the current user
+#[cfg(feature = "register-http")]
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(feature = "register-http")]
+use crate::*;
+
+// `None` until `set_download_cache_dir` is called
+static CACHE_DIR: std::sync::Mutex<Option<Option<PathBuf>>> = std::sync::Mutex::new(None);
+
+/// Set the directory used to cache modules downloaded from a URL or OCI registry, `None` disables the cache. By
+/// default modules are cached in the directory named by the `EXTISM_CACHE_DIR` environment variable, or
+/// `extism-cache` in the user's cache directory. The directory should only be writable by the current user, modules
+/// without a hash are loaded from it after the server confirms they haven't changed
+pub fn set_download_cache_dir(dir: Option<PathBuf>) {
+    let mut cache_dir = match CACHE_DIR.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    *cache_dir = Some(dir);
+}
+
+// The cache directory, `None` if the cache is disabled
+pub(crate) fn dir() -> Option<PathBuf> {
+    configured_dir().unwrap_or_else(|| Some(user_cache_dir("extism-cache")))
+}
+
+// The cache directory set by the host, `None` if the default is used
+fn configured_dir() -> Option<Option<PathBuf>> {
+    let cache_dir = match CACHE_DIR.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    match &*cache_dir {
+        Some(dir) => Some(dir.clone()),
+        None => std::env::var_os("EXTISM_CACHE_DIR").map(|x| Some(PathBuf::from(x))),
+    }
+}
+
+// The default directory for a cache that shouldn't be shared with other users: `$XDG_CACHE_HOME/{name}`,
+// `$HOME/.cache/{name}`, or a directory in the system temporary directory that includes the user ID
+pub(crate) fn user_cache_dir(name: &str) -> PathBuf {
+    let var = |k| {
+        std::env::var_os(k)
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+    };
+    if let Some(dir) = var("XDG_CACHE_HOME") {
+        return dir.join(name);
+    }
+    if let Some(home) = var("HOME") {
+        return home.join(".cache").join(name);
+    }
+
+    #[cfg(unix)]
+    let name = format!("{name}-{}", unsafe { libc::geteuid() });
+    std::env::temp_dir().join(name)
+}
+
+// Create a directory only the current user can access, fails if it already exists and is owned by another user or
+// is accessible to other users
+#[cfg(any(feature = "registry", feature = "register-http"))]
+pub(crate) fn create_private_dir(dir: &std::path::Path) -> Result<(), crate::Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        let meta = std::fs::symlink_metadata(dir)?;
+        if !meta.is_dir()
+            || meta.uid() != unsafe { libc::geteuid() }
+            || meta.permissions().mode() & 0o077 != 0
+        {
+            anyhow::bail!(
+                "Cache directory {} is not private to the current user",
+                dir.display()
+            );
+        }
+    }
+
+    #[cfg(not(unix))]
+    std::fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(feature = "register-http")]
+fn key(url: &str, hash: Option<&str>) -> String {
+    let key = format!("{url}\n{}", hash.unwrap_or_default());
+    extism_manifest::HashAlgorithm::Sha256.digest(key.as_bytes())
+}
+
+// Write to a temporary file first so other processes never read a partially written entry
+#[cfg(feature = "register-http")]
+fn write(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&tmp, data)?;
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "register-http")]
+fn store(dir: &Path, key: &str, data: &[u8], etag: Option<&str>) -> Result<(), Error> {
+    std::fs::create_dir_all(dir)?;
+    write(&dir.join(format!("{key}.wasm")), data)?;
+    let etag_path = dir.join(format!("{key}.etag"));
+    match etag {
+        Some(etag) => write(&etag_path, etag.as_bytes())?,
+        None => {
+            let _ = std::fs::remove_file(etag_path);
+        }
+    }
+    Ok(())
+}
+
+// Fetch a module using the cache and decode it using `decode`, which also checks the data. Cached data is only used
+// if it can be decoded, downloaded data is only cached if it can be decoded
+#[cfg(feature = "register-http")]
+pub(crate) fn fetch<T>(
+    req: &extism_manifest::HttpRequest,
+    retry: Option<&extism_manifest::RetryOptions>,
+    hash: Option<&str>,
+    decode: impl Fn(&[u8]) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let dir = match dir() {
+        Some(x) => x,
+        None => return decode(&manifest::fetch_with_retry(req, retry)?),
+    };
+
+    // Other users could replace entries in a shared directory
+    if hash.is_none() && configured_dir().is_none() {
+        if let Err(e) = create_private_dir(&dir) {
+            debug!("Not caching module without a hash: {e:?}");
+            return decode(&manifest::fetch_with_retry(req, retry)?);
+        }
+    }
+
+    let key = key(&req.url, hash);
+    let cached = std::fs::read(dir.join(format!("{key}.wasm")))
+        .ok()
+        .and_then(|data| decode(&data).ok());
+    let cached = match (cached, hash) {
+        (Some(x), Some(_)) => return Ok(x),
+        (cached, _) => cached,
+    };
+
+    let mut req = req.clone();
+    if cached.is_some() {
+        if let Ok(etag) = std::fs::read_to_string(dir.join(format!("{key}.etag"))) {
+            req.headers.insert("If-None-Match".to_string(), etag);
+        }
+    }
+
+    let (data, etag) = match (
+        manifest::with_retry(&req, retry, || manifest::fetch_response(&req))?,
+        cached,
+    ) {
+        (Some(x), _) => x,
+        (None, Some(data)) => {
+            debug!("Using cached module for {}", req.redacted().url);
+            return Ok(data);
+        }
+        (None, None) => anyhow::bail!(
+            "Unexpected 304 Not Modified response from {}",
+            req.redacted().url
+        ),
+    };
+
+    let decoded = decode(&data)?;
+    if let Err(e) = store(&dir, &key, &data, etag.as_deref()) {
+        error!("Unable to cache module from {}: {e:?}", req.redacted().url);
+    }
+    Ok(decoded)
+}
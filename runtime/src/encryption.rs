@@ -0,0 +1,59 @@
+use std::borrow::Cow;
+
+use extism_manifest::WasmMetadata;
+
+use crate::*;
+
+/// Returns the key for an encrypted module given its `key_id`, see `PluginBuilder::with_key_provider`
+pub type KeyProvider = std::sync::Arc<dyn Fn(&str) -> Result<Vec<u8>, Error> + Send + Sync>;
+
+/// Decrypt a module if its metadata includes an `encryption` block, otherwise the data is returned unchanged.
+/// This is called after the hash has been checked, so the hash always refers to the encrypted data.
+pub(crate) fn decrypt<'a>(
+    meta: &WasmMetadata,
+    data: &'a [u8],
+    keys: Option<&KeyProvider>,
+) -> Result<Cow<'a, [u8]>, Error> {
+    let encryption = match &meta.encryption {
+        Some(x) => x,
+        None => return Ok(Cow::Borrowed(data)),
+    };
+
+    let name = meta.name.as_deref().unwrap_or("main");
+    let keys = match keys {
+        Some(x) => x,
+        None => anyhow::bail!("Module {name} is encrypted but no key provider was set"),
+    };
+
+    #[cfg(not(feature = "encryption"))]
+    {
+        let _ = (encryption, keys);
+        anyhow::bail!("Module {name} is encrypted, this requires the `encryption` feature");
+    }
+
+    #[cfg(feature = "encryption")]
+    {
+        use base64::Engine;
+        use extism_manifest::EncryptionAlgorithm;
+        use ring::aead;
+
+        let key = keys(&encryption.key_id)?;
+        let algorithm = match encryption.algorithm {
+            EncryptionAlgorithm::Aes256Gcm => &aead::AES_256_GCM,
+            EncryptionAlgorithm::Chacha20Poly1305 => &aead::CHACHA20_POLY1305,
+        };
+        let key = aead::UnboundKey::new(algorithm, &key)
+            .map_err(|_| anyhow::format_err!("Invalid key for module {name}"))?;
+        let nonce = base64::engine::general_purpose::STANDARD.decode(&encryption.nonce)?;
+        let nonce = aead::Nonce::try_assume_unique_for_key(&nonce)
+            .map_err(|_| anyhow::format_err!("Invalid nonce for module {name}"))?;
+
+        let mut buf = data.to_vec();
+        let len = aead::LessSafeKey::new(key)
+            .open_in_place(nonce, aead::Aad::empty(), &mut buf)
+            .map_err(|_| anyhow::format_err!("Unable to decrypt module {name}"))?
+            .len();
+        buf.truncate(len);
+        Ok(Cow::Owned(buf))
+    }
+}
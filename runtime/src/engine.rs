@@ -0,0 +1,208 @@
+use crate::backend::Backend;
+use crate::*;
+
+fn profiling_strategy() -> ProfilingStrategy {
+    match std::env::var("EXTISM_PROFILE").as_deref() {
+        Ok("perf") => ProfilingStrategy::PerfMap,
+        Ok(x) => {
+            log::warn!("Invalid value for EXTISM_PROFILE: {x}");
+            ProfilingStrategy::None
+        }
+        Err(_) => ProfilingStrategy::None,
+    }
+}
+
+/// The compiler used to generate native code from WebAssembly modules
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Compiler {
+    /// Cranelift generates optimized code, this is the default
+    #[default]
+    Cranelift,
+
+    /// Winch is a baseline compiler that compiles much faster than Cranelift but generates slower
+    /// code, this is useful for short-lived or rarely called plugins. This requires the `winch`
+    /// feature.
+    Winch,
+}
+
+/// Settings for wasmtime's pooling instance allocator, see `PluginBuilder::with_pooling_allocator`. Memory and
+/// tables are reserved up front for each slot, so instantiation only needs to reuse a free slot instead of
+/// allocating. Every plugin uses one instance and memory for the Extism kernel in addition to its own modules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolingConfig {
+    /// The maximum number of instances that can exist at the same time
+    pub total_instances: u32,
+
+    /// The maximum number of linear memories that can exist at the same time
+    pub total_memories: u32,
+
+    /// The maximum number of tables that can exist at the same time
+    pub total_tables: u32,
+
+    /// The maximum size of each linear memory in 64KiB pages, modules that need more memory can't be instantiated
+    /// and `memory.grow` fails past this size. `MemoryOptions::max_pages` is still enforced when it's lower.
+    pub memory_pages: u64,
+
+    /// The maximum number of elements in each table
+    pub table_elements: u32,
+
+    /// The maximum number of memories defined by a single module, this needs to be raised for modules that use
+    /// multiple memories
+    pub memories_per_module: u32,
+}
+
+impl Default for PoolingConfig {
+    fn default() -> Self {
+        PoolingConfig {
+            total_instances: 1000,
+            total_memories: 1000,
+            total_tables: 1000,
+            memory_pages: 160,
+            table_elements: 10_000,
+            memories_per_module: 1,
+        }
+    }
+}
+
+impl PoolingConfig {
+    /// Create a pool with room for `n` plugins that each have a single module
+    pub fn new(n: u32) -> PoolingConfig {
+        PoolingConfig {
+            total_instances: n * 2,
+            total_memories: n * 2,
+            total_tables: n * 2,
+            ..Default::default()
+        }
+    }
+
+    /// Set the maximum size of each linear memory in 64KiB pages
+    pub fn with_memory_pages(mut self, pages: u64) -> Self {
+        self.memory_pages = pages;
+        self
+    }
+
+    /// Set the maximum number of elements in each table
+    pub fn with_table_elements(mut self, elements: u32) -> Self {
+        self.table_elements = elements;
+        self
+    }
+
+    /// Set the maximum number of memories defined by a single module
+    pub fn with_memories_per_module(mut self, memories: u32) -> Self {
+        self.memories_per_module = memories;
+        self
+    }
+}
+
+/// A function that changes the wasmtime `Config` after the runtime's settings are applied, see
+/// `PluginBuilder::with_wasmtime_config`
+#[derive(Clone)]
+pub(crate) struct ConfigureFn(pub(crate) std::sync::Arc<dyn Fn(&mut Config) + Send + Sync>);
+
+// Functions are compared by identity, so plugins only share an engine when they use the same function
+impl PartialEq for ConfigureFn {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(
+            std::sync::Arc::as_ptr(&self.0),
+            std::sync::Arc::as_ptr(&other.0),
+        )
+    }
+}
+
+impl std::fmt::Debug for ConfigureFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ConfigureFn").finish()
+    }
+}
+
+/// The settings used to create a wasmtime `Engine`, plugins created with the same settings are able
+/// to share an `Engine` (and the modules compiled with it) when the module cache is enabled
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct EngineConfig {
+    pub(crate) debug_info: bool,
+    pub(crate) profiling: ProfilingStrategy,
+    pub(crate) parallel_compilation: bool,
+    pub(crate) compiler: Compiler,
+    pub(crate) opt_level: Option<OptLevel>,
+    pub(crate) memory_init_cow: bool,
+    pub(crate) max_wasm_stack: Option<usize>,
+    pub(crate) consume_fuel: bool,
+    pub(crate) threads: bool,
+    pub(crate) component_model: bool,
+    pub(crate) multi_memory: bool,
+    pub(crate) tail_call: bool,
+    pub(crate) deterministic: bool,
+    pub(crate) cache: Option<CacheConfig>,
+    pub(crate) pooling: Option<PoolingConfig>,
+    pub(crate) configure: Vec<ConfigureFn>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        // If the `EXTISM_DEBUG` environment variable is set then we enable debug info
+        EngineConfig {
+            debug_info: std::env::var("EXTISM_DEBUG").is_ok(),
+            profiling: profiling_strategy(),
+            parallel_compilation: true,
+            compiler: Compiler::default(),
+            opt_level: None,
+            memory_init_cow: true,
+            max_wasm_stack: None,
+            consume_fuel: false,
+            threads: false,
+            component_model: false,
+            multi_memory: false,
+            tail_call: false,
+            deterministic: false,
+            cache: None,
+            pooling: None,
+            configure: vec![],
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Apply engine settings from the manifest, settings made using `PluginBuilder` take precedence
+    pub(crate) fn update(&mut self, manifest: &Manifest) {
+        if self.opt_level.is_none() {
+            self.opt_level = manifest.opt_level;
+        }
+
+        if let Some(n) = manifest.memory.max_stack_bytes {
+            self.max_wasm_stack = Some(n as usize);
+        }
+
+        if manifest.deterministic_seed.is_some() {
+            self.deterministic = true;
+        }
+    }
+
+    /// Create a new `Engine` using the current settings
+    pub(crate) fn engine(&self) -> Result<Engine, Error> {
+        backend::Active::engine(self)
+    }
+}
+
+static COMPILATION_POOLS: std::sync::Mutex<BTreeMap<usize, std::sync::Arc<rayon::ThreadPool>>> =
+    std::sync::Mutex::new(BTreeMap::new());
+
+/// Get a thread pool with `n` threads that can be used to limit the number of threads used for
+/// parallel compilation, wasmtime compiles functions using the current rayon thread pool
+pub(crate) fn compilation_pool(n: usize) -> Result<std::sync::Arc<rayon::ThreadPool>, Error> {
+    let mut pools = match COMPILATION_POOLS.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+
+    if let Some(pool) = pools.get(&n) {
+        return Ok(pool.clone());
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(n)
+        .thread_name(|i| format!("extism-compile-{i}"))
+        .build()?;
+    let pool = std::sync::Arc::new(pool);
+    pools.insert(n, pool.clone());
+    Ok(pool)
+}
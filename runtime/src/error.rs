@@ -0,0 +1,140 @@
+use crate::*;
+
+/// `ErrorContext` is attached to every error returned from a plugin call, it can be accessed using
+/// `ErrorContext::of` or `Error::downcast_ref::<ErrorContext>()`
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    pub(crate) plugin_id: uuid::Uuid,
+    pub(crate) function: String,
+    pub(crate) source: String,
+    pub(crate) elapsed: std::time::Duration,
+}
+
+impl ErrorContext {
+    /// Get the `ErrorContext` attached to an error, if there is one
+    pub fn of(e: &Error) -> Option<&ErrorContext> {
+        e.downcast_ref::<ErrorContext>()
+    }
+
+    /// The ID of the plugin that returned the error
+    pub fn plugin_id(&self) -> uuid::Uuid {
+        self.plugin_id
+    }
+
+    /// The name of the function that was called
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// Where the plugin's main module was loaded from: a file path, a URL or `<data>` when it
+    /// was passed in directly
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// How long the call ran before failing
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.elapsed
+    }
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Plugin {} ({}): call to {} failed after {:?}",
+            self.plugin_id, self.source, self.function, self.elapsed
+        )
+    }
+}
+
+/// Returned when a plugin is created and some of its imports aren't provided by the host, it lists every missing
+/// import across all modules
+#[derive(Debug, Clone)]
+pub struct MissingImportsError {
+    /// The imports that couldn't be resolved
+    pub imports: Vec<ImportInfo>,
+}
+
+impl std::fmt::Display for MissingImportsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Missing imports:")?;
+        for (i, import) in self.imports.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(
+                f,
+                "{sep}{}::{} (required by {})",
+                import.namespace, import.name, import.module
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MissingImportsError {}
+
+/// Returned by `Plugin::call_typed`, separates failures converting the input and output from failures in the plugin
+#[derive(Debug)]
+pub enum CallError {
+    /// The input couldn't be encoded, the plugin wasn't called
+    Encode(Error),
+
+    /// The call failed, this includes traps, timeouts, cancellation and errors returned by the plugin
+    Call(Error),
+
+    /// The call succeeded but the output couldn't be decoded
+    Decode(Error),
+}
+
+impl CallError {
+    /// The underlying error
+    pub fn error(&self) -> &Error {
+        match self {
+            CallError::Encode(e) | CallError::Call(e) | CallError::Decode(e) => e,
+        }
+    }
+
+    /// Convert into the underlying error
+    pub fn into_error(self) -> Error {
+        match self {
+            CallError::Encode(e) | CallError::Call(e) | CallError::Decode(e) => e,
+        }
+    }
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::Encode(e) => write!(f, "Unable to encode input: {e}"),
+            CallError::Call(e) => write!(f, "{e}"),
+            CallError::Decode(e) => write!(f, "Unable to decode output: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error().as_ref())
+    }
+}
+
+// Describe where the main module in a manifest was loaded from
+pub(crate) fn manifest_source(manifest: &Manifest) -> String {
+    let wasm = manifest
+        .wasm
+        .iter()
+        .find(|w| w.meta().name.as_deref() == Some("main"))
+        .or_else(|| manifest.wasm.last());
+
+    match wasm {
+        Some(
+            extism_manifest::Wasm::File { path, .. }
+            | extism_manifest::Wasm::Precompiled { path, .. }
+            | extism_manifest::Wasm::Dir { path, .. }
+            | extism_manifest::Wasm::Archive { path, .. },
+        ) => path.display().to_string(),
+        Some(extism_manifest::Wasm::Url { req, .. }) => req.url.clone(),
+        Some(extism_manifest::Wasm::Registry { registry, .. }) => registry.clone(),
+        Some(extism_manifest::Wasm::Data { .. }) | None => "<data>".to_string(),
+    }
+}
@@ -158,7 +158,7 @@ impl Drop for UserData {
 unsafe impl Send for UserData {}
 unsafe impl Sync for UserData {}
 
-type FunctionInner = dyn Fn(wasmtime::Caller<CurrentPlugin>, &[wasmtime::Val], &mut [wasmtime::Val]) -> Result<(), Error>
+pub(crate) type FunctionInner = dyn Fn(&mut CurrentPlugin, &[wasmtime::Val], &mut [wasmtime::Val]) -> Result<(), Error>
     + Sync
     + Send;
 
@@ -204,9 +204,7 @@ impl Function {
                 args.into_iter().map(wasmtime::ValType::from),
                 returns.into_iter().map(wasmtime::ValType::from),
             ),
-            f: std::sync::Arc::new(move |mut caller, inp, outp| {
-                f(caller.data_mut(), inp, outp, data.make_copy())
-            }),
+            f: std::sync::Arc::new(move |plugin, inp, outp| f(plugin, inp, outp, data.make_copy())),
             namespace: None,
             _user_data: std::sync::Arc::new(user_data),
         }
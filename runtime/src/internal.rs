@@ -6,6 +6,15 @@ pub struct Wasi {
     pub ctx: wasmtime_wasi::WasiCtx,
 }
 
+/// WASI context used when `WasiOptions::preview2` is enabled
+pub(crate) struct WasiPreview2 {
+    pub(crate) ctx: wasmtime_wasi::preview2::WasiCtx,
+    pub(crate) table: wasmtime_wasi::preview2::Table,
+    pub(crate) adapter: wasmtime_wasi::preview2::preview1::WasiPreview1Adapter,
+    #[cfg(feature = "wasi-http")]
+    pub(crate) http: wasmtime_wasi_http::WasiHttpCtx,
+}
+
 /// InternalExt provides a unified way of acessing `memory`, `store` and `internal` values
 pub(crate) trait Internal {
     fn store(&self) -> &Store<CurrentPlugin>;
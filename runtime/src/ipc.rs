@@ -0,0 +1,294 @@
+//! Forward host function calls to another process.
+//!
+//! An `IpcBridge` creates host functions that send their arguments over a stream, such as a unix socket or the
+//! stdin/stdout of a child process, and wait for a response. This allows host capabilities to be implemented in
+//! any language without linking them into the host binary.
+//!
+//! ```rust,no_run
+//! let bridge = extism::ipc::IpcBridge::spawn(std::process::Command::new("./host-functions")).unwrap();
+//! let mut plugin = extism::PluginBuilder::new_with_module(std::fs::read("plugin.wasm").unwrap())
+//!     .with_functions([bridge.function("kv_read", 1)])
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! ## Protocol
+//!
+//! Calls are made one at a time, each request is followed by exactly one response. All integers are
+//! little-endian and a field is a `u32` length followed by that many bytes.
+//!
+//! - Request: a `u32` field count followed by the fields. The first field is the function name and the
+//!   remaining fields are the arguments, read from plugin memory.
+//! - Response: a `u8` status followed by a single field. When the status is `0` the field is the output, which
+//!   is copied into plugin memory and returned to the plugin. Any other status is an error and the field
+//!   contains the error message.
+//!
+//! The response must arrive within the bridge's timeout. After a timeout, an I/O error or a malformed response
+//! the stream can't be trusted to be at the start of a response, so every later call fails.
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::*;
+
+/// The largest field that will be read from a response, 64MiB
+pub const MAX_FIELD_SIZE: usize = 64 * 1024 * 1024;
+
+/// How long to wait for a response by default
+pub const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Reads the stream on a background thread so reads can time out, whatever the stream is. The thread exits when the
+// stream is closed
+struct Reader {
+    rx: std::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+    deadline: Option<std::time::Instant>,
+}
+
+impl Reader {
+    fn new(mut reader: impl Read + Send + 'static) -> Reader {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        std::thread::spawn(move || loop {
+            let mut buf = vec![0; 64 * 1024];
+            let res = match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Ok(buf)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = res.is_err();
+            if tx.send(res).is_err() || failed {
+                return;
+            }
+        });
+        Reader {
+            rx,
+            buf: vec![],
+            pos: 0,
+            deadline: None,
+        }
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        use std::sync::mpsc::RecvTimeoutError;
+
+        if self.pos == self.buf.len() {
+            let res = match self.deadline {
+                Some(deadline) => self
+                    .rx
+                    .recv_timeout(deadline.saturating_duration_since(std::time::Instant::now())),
+                None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            self.buf = match res {
+                Ok(x) => x?,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "Timed out waiting for IPC response",
+                    ))
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.pos = 0;
+        }
+
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+struct Channel {
+    reader: Reader,
+    writer: Box<dyn Write + Send>,
+    child: Option<std::process::Child>,
+    #[cfg(unix)]
+    socket: Option<std::os::unix::net::UnixStream>,
+    timeout: Option<std::time::Duration>,
+    broken: bool,
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        // Wake up the reader thread
+        #[cfg(unix)]
+        if let Some(socket) = &self.socket {
+            let _ = socket.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+impl Channel {
+    fn new(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Channel {
+        Channel {
+            reader: Reader::new(reader),
+            writer: Box::new(writer),
+            child: None,
+            #[cfg(unix)]
+            socket: None,
+            timeout: Some(DEFAULT_TIMEOUT),
+            broken: false,
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[&[u8]]) -> Result<(u8, Vec<u8>), Error> {
+        let mut req = Vec::new();
+        req.extend_from_slice(&(args.len() as u32 + 1).to_le_bytes());
+        write_field(&mut req, name.as_bytes())?;
+        for arg in args {
+            write_field(&mut req, arg)?;
+        }
+        self.writer.write_all(&req)?;
+        self.writer.flush()?;
+
+        self.reader.deadline = self.timeout.map(|x| std::time::Instant::now() + x);
+        let mut status = [0u8];
+        self.reader.read_exact(&mut status)?;
+        let data = read_field(&mut self.reader)?;
+        Ok((status[0], data))
+    }
+}
+
+/// A connection to a process that implements host functions
+#[derive(Clone)]
+pub struct IpcBridge {
+    channel: Arc<Mutex<Channel>>,
+}
+
+impl IpcBridge {
+    /// Create a bridge from any pair of streams
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> IpcBridge {
+        IpcBridge::from_channel(Channel::new(reader, writer))
+    }
+
+    /// Connect to a unix socket
+    #[cfg(unix)]
+    pub fn connect(path: impl AsRef<std::path::Path>) -> Result<IpcBridge, Error> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        let mut channel = Channel::new(stream.try_clone()?, stream.try_clone()?);
+        channel.socket = Some(stream);
+        Ok(IpcBridge::from_channel(channel))
+    }
+
+    /// Start a process and communicate with it using stdin and stdout, the process is killed when the last clone
+    /// of the bridge is dropped
+    pub fn spawn(mut command: std::process::Command) -> Result<IpcBridge, Error> {
+        let mut child = command
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => anyhow::bail!("Unable to open stdin/stdout for IPC process"),
+        };
+        let mut channel = Channel::new(stdout, stdin);
+        channel.child = Some(child);
+        Ok(IpcBridge::from_channel(channel))
+    }
+
+    fn from_channel(channel: Channel) -> IpcBridge {
+        IpcBridge {
+            channel: Arc::new(Mutex::new(channel)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Channel> {
+        match self.channel.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Set how long to wait for a response, `None` waits forever. By default this is `DEFAULT_TIMEOUT`. This
+    /// applies to every clone of the bridge
+    pub fn with_timeout(self, timeout: Option<std::time::Duration>) -> Self {
+        self.lock().timeout = timeout;
+        self
+    }
+
+    /// Send a request and wait for the response
+    pub fn call(&self, name: &str, args: &[&[u8]]) -> Result<Vec<u8>, Error> {
+        let mut channel = self.lock();
+        if channel.broken {
+            anyhow::bail!(
+                "IPC call to {name} failed: the connection is broken by an earlier error"
+            );
+        }
+
+        let (status, data) = match channel.call(name, args) {
+            Ok(x) => x,
+            Err(e) => {
+                channel.broken = true;
+                return Err(e.context(format!("IPC call to {name} failed")));
+            }
+        };
+        if status != 0 {
+            anyhow::bail!(
+                "IPC call to {name} failed: {}",
+                String::from_utf8_lossy(&data)
+            );
+        }
+        Ok(data)
+    }
+
+    /// Create a host function named `name` that forwards calls over the bridge. The function follows the Extism
+    /// convention: it takes `params` memory offsets and returns the offset of the output.
+    pub fn function(&self, name: impl Into<String>, params: usize) -> Function {
+        let name = name.into();
+        let bridge = self.clone();
+        let function = name.clone();
+        Function::new(
+            name,
+            vec![ValType::I64; params],
+            [ValType::I64],
+            None,
+            move |plugin, inputs, outputs, _user_data| {
+                let mut args = Vec::with_capacity(inputs.len());
+                for input in inputs {
+                    args.push(plugin.memory_get_val::<&[u8]>(input)?.to_vec());
+                }
+                let args: Vec<&[u8]> = args.iter().map(|x| x.as_slice()).collect();
+
+                trace!("Forwarding call to {function} over IPC");
+                let output = bridge.call(&function, &args)?;
+                let handle = plugin.memory_new(&output)?;
+                outputs[0] = plugin.memory_to_val(handle);
+                Ok(())
+            },
+        )
+    }
+}
+
+fn write_field(buf: &mut Vec<u8>, data: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(data.len())?;
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+fn read_field(reader: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FIELD_SIZE {
+        anyhow::bail!("IPC response field is too large: {len} bytes");
+    }
+
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
@@ -3,31 +3,115 @@ pub(crate) use std::collections::BTreeMap;
 pub(crate) use wasmtime::*;
 
 pub use extism_convert as convert;
+pub use wasmtime;
 
 pub use anyhow::Error;
+pub use bytes::Bytes;
 
+mod archive;
+#[cfg(feature = "async")]
+mod async_plugin;
+pub(crate) mod backend;
+mod component_plugin;
 mod current_plugin;
+mod deferred;
+mod download_cache;
+mod encryption;
+pub(crate) mod engine;
+mod error;
 mod function;
+mod http_client;
 mod internal;
 pub(crate) mod manifest;
+mod module_cache;
+mod oci;
 pub(crate) mod pdk;
 mod plugin;
 mod plugin_builder;
+mod policy;
+mod pool;
+mod secrets;
+mod signature;
+mod snapshot;
+mod state;
+mod threads;
 mod timer;
+mod warm_pool;
+mod wasi;
+#[cfg(feature = "wasi-http")]
+mod wasi_http;
+#[cfg(feature = "wasi-nn")]
+mod wasi_nn;
 
 /// Extism C API
 pub mod sdk;
 
+/// Generate typed wrappers for plugins
+pub mod bindgen;
+
+/// Plugin registry client
+#[cfg(feature = "registry")]
+pub mod registry;
+
+/// Test utilities for host authors
+#[cfg(feature = "testing")]
+pub mod testing;
+
+/// HTTP server for plugins
+#[cfg(feature = "serve")]
+pub mod serve;
+
+/// Host functions implemented by other processes
+#[cfg(feature = "ipc")]
+pub mod ipc;
+
+/// Host functions for plugins that load other plugins
+#[cfg(feature = "nested")]
+pub mod nested;
+
+/// Run plugin functions on a schedule
+#[cfg(feature = "schedule")]
+pub mod schedule;
+
+/// Benchmarking helpers
+#[cfg(feature = "bench")]
+pub mod bench;
+
+#[cfg(feature = "async")]
+pub use async_plugin::AsyncPlugin;
+pub use backend::backend_name;
+pub use component_plugin::{ComponentPlugin, COMPONENT_HOST_INTERFACE};
 pub use current_plugin::CurrentPlugin;
+pub use deferred::{DeferredCallPolicy, DeferredPlugin, Ready};
+pub use download_cache::set_download_cache_dir;
+pub use encryption::KeyProvider;
+pub use engine::{Compiler, PoolingConfig};
+pub use error::{CallError, ErrorContext, MissingImportsError};
 pub use extism_convert::{FromBytes, FromBytesOwned, ToBytes};
-pub use extism_manifest::Manifest;
+pub use extism_manifest::{Manifest, OptLevel};
 pub use function::{Function, UserData, Val, ValType};
-pub use plugin::{CancelHandle, Plugin};
+pub use http_client::{set_http_client_config, HttpClientConfig};
+pub use manifest::is_component;
+pub use module_cache::{clear_module_cache, CacheConfig};
+pub use plugin::{
+    CallStats, CancelHandle, CommandOutput, FunctionInfo, ImportInfo, MemoryStats, OutputRef,
+    Plugin, WarmUpStats, HEALTH_CHECK_FUNCTION, HEALTH_CHECK_TIMEOUT,
+};
 pub use plugin_builder::PluginBuilder;
+pub use policy::{Capability, Policy, DEFAULT_KV_MAX_BYTES};
+pub use pool::{PluginPool, PooledPlugin};
+pub use secrets::ConfigSecretError;
+pub use snapshot::Snapshot;
+pub use state::{MemoryRegion, PluginState, RegionData, MIGRATE_FUNCTION, STATE_FORMAT_VERSION};
+pub use warm_pool::WarmPool;
 
-pub(crate) use internal::{Internal, Wasi};
+pub(crate) use current_plugin::OutputSink;
+pub(crate) use engine::EngineConfig;
+pub(crate) use internal::{Internal, Wasi, WasiPreview2};
 pub(crate) use log::{debug, error, trace};
-pub(crate) use timer::{Timer, TimerAction};
+pub(crate) use plugin_builder::PluginOptions;
+pub(crate) use threads::Threads;
+pub(crate) use timer::{TickerGuard, Timer, TimerAction};
 
 #[cfg(test)]
 mod tests;
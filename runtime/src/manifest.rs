@@ -2,11 +2,11 @@ use std::collections::BTreeMap;
 use std::fmt::Write as FmtWrite;
 use std::io::Read;
 
-use sha2::Digest;
+use crate::backend::Backend;
 
 use crate::*;
 
-fn hex(data: &[u8]) -> String {
+pub(crate) fn hex(data: &[u8]) -> String {
     let mut s = String::new();
     for &byte in data {
         write!(&mut s, "{:02x}", byte).unwrap();
@@ -14,11 +14,23 @@ fn hex(data: &[u8]) -> String {
     s
 }
 
+// Cached files are named using the hex digest, prefixed with the algorithm name for anything other than SHA-256
+fn cache_file_name(hash: &str) -> String {
+    match extism_manifest::HashAlgorithm::split(hash) {
+        Some((extism_manifest::HashAlgorithm::Sha256, digest)) => digest.to_ascii_lowercase(),
+        Some((alg, digest)) => format!("{}-{}", alg.name(), digest.to_ascii_lowercase()),
+        None => hash.replace(|c: char| !c.is_ascii_alphanumeric(), "-"),
+    }
+}
+
 #[allow(unused)]
 fn cache_add_file(hash: &str, data: &[u8]) -> Result<(), Error> {
-    let cache_dir = std::env::temp_dir().join("exitsm-cache");
-    let _ = std::fs::create_dir(&cache_dir);
-    let file = cache_dir.join(hash);
+    let cache_dir = match download_cache::dir() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    std::fs::create_dir_all(&cache_dir)?;
+    let file = cache_dir.join(cache_file_name(hash));
     if file.exists() {
         return Ok(());
     }
@@ -27,8 +39,11 @@ fn cache_add_file(hash: &str, data: &[u8]) -> Result<(), Error> {
 }
 
 fn cache_get_file(hash: &str) -> Result<Option<Vec<u8>>, Error> {
-    let cache_dir = std::env::temp_dir().join("exitsm-cache");
-    let file = cache_dir.join(hash);
+    let cache_dir = match download_cache::dir() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let file = cache_dir.join(cache_file_name(hash));
     if file.exists() {
         let r = std::fs::read(file)?;
         return Ok(Some(r));
@@ -37,17 +52,20 @@ fn cache_get_file(hash: &str) -> Result<Option<Vec<u8>>, Error> {
     Ok(None)
 }
 
-fn check_hash(hash: &Option<String>, data: &[u8]) -> Result<(), Error> {
+pub(crate) fn check_hash(hash: &Option<String>, data: &[u8]) -> Result<(), Error> {
     match hash {
         None => Ok(()),
         Some(hash) => {
-            let digest = sha2::Sha256::digest(data);
-            let hex = hex(&digest);
-            if &hex != hash {
+            let (alg, expected) = match extism_manifest::HashAlgorithm::split(hash) {
+                Some(x) => x,
+                None => anyhow::bail!("Unsupported hash algorithm: {hash}"),
+            };
+            let hex = alg.digest(data);
+            if !hex.eq_ignore_ascii_case(expected) {
                 return Err(anyhow::format_err!(
                     "Hash mismatch, found {} but expected {}",
                     hex,
-                    hash
+                    expected
                 ));
             }
             Ok(())
@@ -55,10 +73,236 @@ fn check_hash(hash: &Option<String>, data: &[u8]) -> Result<(), Error> {
     }
 }
 
+// A response body and its `ETag` header
+#[cfg(any(feature = "register-http", feature = "registry"))]
+pub(crate) type Response = (Vec<u8>, Option<String>);
+
+// Headers that are only sent to the origin of the original URL
+#[cfg(any(feature = "register-http", feature = "registry"))]
+const CREDENTIAL_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+// Send a request described by an `HttpRequest` and read the response, `None` is returned for `304 Not Modified`
+// responses. Redirects are followed here instead of by `ureq`, which doesn't follow `307` and `308` redirects for
+// requests with a body. Credentials aren't sent once a redirect leads to a different scheme, host or port
+#[cfg(any(feature = "register-http", feature = "registry"))]
+pub(crate) fn fetch_response(
+    req: &extism_manifest::HttpRequest,
+) -> Result<Option<Response>, Error> {
+    let mut url = req.url.clone();
+    let mut method = req.method.as_deref().unwrap_or("GET").to_string();
+    let mut body = req.body.as_deref();
+    let mut redirects = 0;
+    let origin = url::Url::parse(&url)?.origin();
+    let mut cross_origin = false;
+
+    let res = loop {
+        let mut agent = http_client::agent(&url)?.redirects(0);
+        if let Some(ms) = req.timeout_ms {
+            agent = agent.timeout(std::time::Duration::from_millis(ms));
+        }
+
+        cross_origin |= url::Url::parse(&url)?.origin() != origin;
+        let mut r = agent.build().request(&method, &url);
+        for (k, v) in req.headers.iter() {
+            if cross_origin && CREDENTIAL_HEADERS.contains(&k.to_ascii_lowercase().as_str()) {
+                continue;
+            }
+            r = r.set(k, v);
+        }
+
+        let res = match body {
+            Some(body) => r.send_string(body)?,
+            None => r.call()?,
+        };
+
+        let status = res.status();
+        if status == 304 || !(300..400).contains(&status) {
+            break res;
+        }
+
+        let location = match res.header("location") {
+            Some(x) if redirects < req.redirect_limit() => x.to_string(),
+            _ => anyhow::bail!(
+                "Redirect ({status}) from {} wasn't followed",
+                req.redacted().url
+            ),
+        };
+
+        redirects += 1;
+        url = url::Url::parse(&url)?.join(&location)?.to_string();
+        if status == 303 || ((status == 301 || status == 302) && method != "HEAD") {
+            method = "GET".to_string();
+            body = None;
+        }
+    };
+
+    if res.status() == 304 {
+        return Ok(None);
+    }
+
+    let etag = res.header("etag").map(|x| x.to_string());
+    let mut data = Vec::new();
+    res.into_reader().read_to_end(&mut data)?;
+    Ok(Some((data, etag)))
+}
+
+// Send a request described by an `HttpRequest` and read the response body
+#[cfg(any(feature = "register-http", feature = "registry"))]
+pub(crate) fn fetch(req: &extism_manifest::HttpRequest) -> Result<Vec<u8>, Error> {
+    match fetch_response(req)? {
+        Some((data, _)) => Ok(data),
+        None => anyhow::bail!(
+            "Unexpected 304 Not Modified response from {}",
+            req.redacted().url
+        ),
+    }
+}
+
+// Call `f` until it succeeds, retrying connection errors and the status codes listed in `retry`. When every attempt
+// fails the number of attempts is added to the error
+#[cfg(any(feature = "register-http", feature = "registry"))]
+pub(crate) fn with_retry<T>(
+    req: &extism_manifest::HttpRequest,
+    retry: Option<&extism_manifest::RetryOptions>,
+    mut f: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let retry = match retry {
+        Some(x) => x,
+        None => return f(),
+    };
+
+    let attempts = retry
+        .attempts
+        .unwrap_or(extism_manifest::DEFAULT_RETRY_ATTEMPTS)
+        .max(1);
+    let status_codes = retry
+        .status_codes
+        .as_deref()
+        .unwrap_or(extism_manifest::DEFAULT_RETRY_STATUS_CODES);
+    let mut delay = std::time::Duration::from_millis(
+        retry
+            .backoff_ms
+            .unwrap_or(extism_manifest::DEFAULT_RETRY_BACKOFF_MS),
+    );
+    let mut attempt = 1;
+    loop {
+        let e = match f() {
+            Ok(x) => return Ok(x),
+            Err(e) => e,
+        };
+
+        let retryable = match e.downcast_ref::<ureq::Error>() {
+            Some(ureq::Error::Status(code, _)) => status_codes.contains(code),
+            _ => true,
+        };
+        let url = req.redacted().url;
+        if !retryable || attempt >= attempts {
+            return Err(e.context(format!("Unable to fetch {url} after {attempt} attempt(s)")));
+        }
+
+        debug!("Fetching {url} failed, retrying in {delay:?}: {e}");
+        std::thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+// Fetch a module, see `with_retry`
+#[cfg(any(feature = "register-http", feature = "registry"))]
+pub(crate) fn fetch_with_retry(
+    req: &extism_manifest::HttpRequest,
+    retry: Option<&extism_manifest::RetryOptions>,
+) -> Result<Vec<u8>, Error> {
+    with_retry(req, retry, || fetch(req))
+}
+
+// Read a module from `reader`, the hash is computed while reading so the data is only traversed once
+pub(crate) fn read_verified(mut reader: impl Read, hash: Option<&str>) -> Result<Vec<u8>, Error> {
+    let hash = match hash {
+        Some(hash) => match extism_manifest::HashAlgorithm::split(hash) {
+            Some(x) => Some(x),
+            None => anyhow::bail!("Unsupported hash algorithm: {hash}"),
+        },
+        None => None,
+    };
+    let mut hasher = hash.map(|(alg, _)| extism_manifest::Hasher::new(alg));
+    let mut data = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buf[..n]);
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+
+    if let (Some(hasher), Some((_, hash))) = (hasher, hash) {
+        let found = hasher.finalize();
+        if !found.eq_ignore_ascii_case(hash) {
+            anyhow::bail!("Hash mismatch, found {found} but expected {hash}");
+        }
+    }
+    Ok(data)
+}
+
 const WASM: &[u8] = include_bytes!("extism-runtime.wasm");
 
-/// Convert from manifest to a wasmtime Module
-fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, Module), Error> {
+// Compile the Extism kernel
+pub(crate) fn kernel(engine: &Engine) -> Result<Module, Error> {
+    module_cache::compile(engine, WASM)
+}
+
+// Core modules and components are loaded from a manifest the same way, only compilation differs
+pub(crate) trait Compile: Sized {
+    fn compile(engine: &Engine, data: &[u8]) -> Result<Self, Error>;
+    fn deserialize(engine: &Engine, data: &[u8]) -> Result<Self, Error>;
+}
+
+impl Compile for Module {
+    fn compile(engine: &Engine, data: &[u8]) -> Result<Self, Error> {
+        if is_component(data) {
+            anyhow::bail!("Expected a WebAssembly module but found a component, use `ComponentPlugin` to load it");
+        }
+        module_cache::compile(engine, data)
+    }
+
+    fn deserialize(engine: &Engine, data: &[u8]) -> Result<Self, Error> {
+        backend::Active::deserialize(engine, data)
+    }
+}
+
+impl Compile for component::Component {
+    fn compile(engine: &Engine, data: &[u8]) -> Result<Self, Error> {
+        if !is_component(data) {
+            anyhow::bail!(
+                "Expected a WebAssembly component but found a module, use `Plugin` to load it"
+            );
+        }
+        component::Component::new(engine, data)
+    }
+
+    fn deserialize(engine: &Engine, data: &[u8]) -> Result<Self, Error> {
+        if engine.detect_precompiled(data) != Some(Precompiled::Component) {
+            anyhow::bail!("Not a precompiled component");
+        }
+
+        // SAFETY: see `Backend::deserialize`
+        unsafe { component::Component::deserialize(engine, data) }
+    }
+}
+
+/// Convert from manifest to a wasmtime Module, or a component when loading a `ComponentPlugin`
+fn to_module<M: Compile>(
+    engine: &Engine,
+    wasm: &extism_manifest::Wasm,
+    keys: Option<&KeyProvider>,
+    trusted_keys: &[extism_manifest::TrustedKey],
+) -> Result<(String, M), Error> {
     match wasm {
         extism_manifest::Wasm::File { path, meta } => {
             if cfg!(not(feature = "register-filesystem")) {
@@ -79,29 +323,52 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
             let mut file = std::fs::File::open(path)?;
             file.read_to_end(&mut buf)?;
 
+            let buf = meta.decompress(&buf)?;
             check_hash(&meta.hash, &buf)?;
+            signature::verify(meta, &buf, trusted_keys)?;
+            let buf = encryption::decrypt(meta, &buf, keys)?;
 
-            Ok((name, Module::new(engine, buf)?))
+            Ok((name, M::compile(engine, &buf)?))
+        }
+        extism_manifest::Wasm::Precompiled { path, meta } => {
+            if cfg!(not(feature = "register-filesystem")) {
+                return Err(anyhow::format_err!("File-based registration is disabled"));
+            }
+
+            let name = match &meta.name {
+                None => {
+                    let name = path.with_extension("");
+                    name.file_name().unwrap().to_string_lossy().to_string()
+                }
+                Some(n) => n.clone(),
+            };
+
+            let buf = std::fs::read(path)?;
+            check_hash(&meta.hash, &buf)?;
+            signature::verify(meta, &buf, trusted_keys)?;
+            let buf = encryption::decrypt(meta, &buf, keys)?;
+            let module = M::deserialize(engine, &buf).map_err(|e| {
+                e.context(format!(
+                    "Unable to load precompiled module {}",
+                    path.display()
+                ))
+            })?;
+            Ok((name, module))
         }
         extism_manifest::Wasm::Data { meta, data } => {
-            check_hash(&meta.hash, data)?;
+            let data = meta.decompress(data)?;
+            check_hash(&meta.hash, &data)?;
+            signature::verify(meta, &data, trusted_keys)?;
+            let data = encryption::decrypt(meta, &data, keys)?;
             Ok((
                 meta.name.as_deref().unwrap_or("main").to_string(),
-                Module::new(engine, data)?,
+                M::compile(engine, &data)?,
             ))
         }
         #[allow(unused)]
-        extism_manifest::Wasm::Url {
-            req:
-                extism_manifest::HttpRequest {
-                    url,
-                    headers,
-                    method,
-                },
-            meta,
-        } => {
+        extism_manifest::Wasm::Url { req, retry, meta } => {
             // Get the file name
-            let file_name = url.split('/').last().unwrap_or_default();
+            let file_name = req.url.split('/').last().unwrap_or_default();
             let name = match &meta.name {
                 Some(name) => name.as_str(),
                 None => {
@@ -117,11 +384,45 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
                 }
             };
 
+            #[cfg(not(feature = "register-http"))]
+            {
+                return Err(anyhow::format_err!("HTTP registration is disabled"));
+            }
+
+            #[cfg(feature = "register-http")]
+            {
+                // Fetch WASM code, cached modules are only used if they still match the hash
+                let data =
+                    download_cache::fetch(req, retry.as_ref(), meta.hash.as_deref(), |data| {
+                        let data = meta.decompress(data)?;
+                        check_hash(&meta.hash, &data)?;
+                        Ok(data.into_owned())
+                    })?;
+
+                signature::verify(meta, &data, trusted_keys)?;
+                let data = encryption::decrypt(meta, &data, keys)?;
+
+                // Convert fetched data to module
+                let module = M::compile(engine, &data)?;
+                Ok((name.to_string(), module))
+            }
+        }
+        #[allow(unused)]
+        extism_manifest::Wasm::Registry {
+            registry,
+            auth,
+            meta,
+        } => {
+            let r = oci::Reference::parse(registry)?;
+            let name = meta.name.as_deref().unwrap_or(r.name()).to_string();
+
             if let Some(h) = &meta.hash {
                 if let Ok(Some(data)) = cache_get_file(h) {
                     check_hash(&meta.hash, &data)?;
-                    let module = Module::new(engine, data)?;
-                    return Ok((name.to_string(), module));
+                    signature::verify(meta, &data, trusted_keys)?;
+                    let data = encryption::decrypt(meta, &data, keys)?;
+                    let module = M::compile(engine, &data)?;
+                    return Ok((name, module));
                 }
             }
 
@@ -132,85 +433,388 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
 
             #[cfg(feature = "register-http")]
             {
-                // Setup request
-                let mut req = ureq::request(method.as_deref().unwrap_or("GET"), url);
-
-                for (k, v) in headers.iter() {
-                    req = req.set(k, v);
-                }
-
-                // Fetch WASM code
-                let mut r = req.call()?.into_reader();
-                let mut data = Vec::new();
-                r.read_to_end(&mut data)?;
+                let (data, digest) = oci::pull(&r, auth.as_ref())?;
 
-                // Try to cache file
-                if let Some(hash) = &meta.hash {
-                    cache_add_file(hash, &data);
-                }
+                // Cache using the layer digest, which is the SHA-256 hash of the module
+                cache_add_file(&digest, &data);
 
                 check_hash(&meta.hash, &data)?;
-
-                // Convert fetched data to module
-                let module = Module::new(engine, data)?;
-                Ok((name.to_string(), module))
+                signature::verify(meta, &data, trusted_keys)?;
+                let data = encryption::decrypt(meta, &data, keys)?;
+                let module = M::compile(engine, &data)?;
+                Ok((name, module))
             }
         }
+        // Directories and archives are expanded when the manifest is resolved
+        extism_manifest::Wasm::Dir { path, .. } => {
+            anyhow::bail!("Directory {} wasn't expanded", path.display())
+        }
+        extism_manifest::Wasm::Archive { path, .. } => {
+            anyhow::bail!("Archive {} wasn't expanded", path.display())
+        }
     }
 }
 
 const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
 
-pub(crate) fn load(
-    engine: &Engine,
-    data: &[u8],
-) -> Result<(extism_manifest::Manifest, BTreeMap<String, Module>), Error> {
-    let extism_module = Module::new(engine, WASM)?;
+/// Returns `true` when `data` is a WebAssembly component instead of a core module, in either the binary or text
+/// format. Components can be loaded using `ComponentPlugin`.
+pub fn is_component(data: &[u8]) -> bool {
+    // Binary components use the same magic number as modules, the layer that follows the version is 1 instead of 0
+    if data.len() >= 8 && data[0..4] == WASM_MAGIC {
+        return data[6..8] == [0x01, 0x00];
+    }
+    data.starts_with(b"(component")
+}
+
+// Parse the data passed to `Plugin::new`, which may be a JSON or TOML manifest or a WebAssembly module. When a
+// module is passed directly it is returned along with an empty manifest.
+pub(crate) fn parse(data: &[u8]) -> Result<(extism_manifest::Manifest, Option<&[u8]>), Error> {
     let has_magic = data.len() >= 4 && data[0..4] == WASM_MAGIC;
-    let is_wast = data.starts_with(b"(module") || data.starts_with(b";;");
+    let is_wast =
+        data.starts_with(b"(module") || data.starts_with(b"(component") || data.starts_with(b";;");
     if !has_magic && !is_wast {
+        // Both formats are read into a JSON value so older layouts can be migrated
         if let Ok(s) = std::str::from_utf8(data) {
-            if let Ok(t) = toml::from_str::<extism_manifest::Manifest>(s) {
-                let mut m = modules(&t, engine)?;
-                m.insert("env".to_string(), extism_module);
-                return Ok((t, m));
+            if let Ok(t) = toml::from_str::<serde_json::Value>(s) {
+                let t = extism_manifest::Manifest::migrate_from_value(t)?;
+                return Ok((t, None));
             }
         }
 
-        let t = serde_json::from_slice::<extism_manifest::Manifest>(data)?;
-        let mut m = modules(&t, engine)?;
-        m.insert("env".to_string(), extism_module);
-        return Ok((t, m));
+        let t = serde_json::from_slice::<serde_json::Value>(data)?;
+        let t = extism_manifest::Manifest::migrate_from_value(t)?;
+        return Ok((t, None));
     }
 
-    let m = Module::new(engine, data)?;
-    let mut modules = BTreeMap::new();
-    modules.insert("env".to_string(), extism_module);
-    modules.insert("main".to_string(), m);
-    Ok((Default::default(), modules))
+    Ok((Default::default(), Some(data)))
+}
+
+// Config values for modules that set `config` in their metadata, keyed by module name
+pub(crate) type ModuleConfig = BTreeMap<String, std::sync::Arc<BTreeMap<String, String>>>;
+
+// Compile the Extism kernel along with the modules returned by `parse`
+pub(crate) fn load(
+    engine: &Engine,
+    manifest: &extism_manifest::Manifest,
+    module: Option<&[u8]>,
+    keys: Option<&KeyProvider>,
+) -> Result<(BTreeMap<String, Module>, ModuleConfig), Error> {
+    let extism_module = kernel(engine)?;
+    let (mut m, config) = match module {
+        Some(data) => {
+            let mut m = BTreeMap::new();
+            m.insert("main".to_string(), Module::compile(engine, data)?);
+            (m, BTreeMap::new())
+        }
+        None => modules(manifest, engine, keys)?,
+    };
+    m.insert("env".to_string(), extism_module);
+    Ok((m, config))
 }
 
-pub(crate) fn modules(
+pub(crate) fn modules<M: Compile>(
     manifest: &extism_manifest::Manifest,
     engine: &Engine,
-) -> Result<BTreeMap<String, Module>, Error> {
+    keys: Option<&KeyProvider>,
+) -> Result<(BTreeMap<String, M>, ModuleConfig), Error> {
     if manifest.wasm.is_empty() {
         return Err(anyhow::format_err!("No wasm files specified"));
     }
 
     let mut modules = BTreeMap::new();
+    let mut config = BTreeMap::new();
 
     // If there's only one module, it should be called `main`
     if manifest.wasm.len() == 1 {
-        let (_, m) = to_module(engine, &manifest.wasm[0])?;
+        let wasm = &manifest.wasm[0];
+        let (_, m) = to_module(engine, wasm, keys, &manifest.trusted_keys)?;
         modules.insert("main".to_string(), m);
-        return Ok(modules);
+        if !wasm.meta().config.is_empty() {
+            config.insert(
+                "main".to_string(),
+                std::sync::Arc::new(wasm.meta().config.clone()),
+            );
+        }
+        return Ok((modules, config));
     }
 
     for f in &manifest.wasm {
-        let (name, m) = to_module(engine, f)?;
+        let (name, m) = to_module(engine, f, keys, &manifest.trusted_keys)?;
+        if !f.meta().config.is_empty() {
+            config.insert(name.clone(), std::sync::Arc::new(f.meta().config.clone()));
+        }
         modules.insert(name, m);
     }
 
+    Ok((modules, config))
+}
+
+/// Returns an error if `manifest` loads a `Wasm::Precompiled` module and `allow` is false
+pub(crate) fn check_precompiled(
+    manifest: &extism_manifest::Manifest,
+    allow: bool,
+) -> Result<(), Error> {
+    if allow {
+        return Ok(());
+    }
+    for wasm in manifest.wasm.iter() {
+        if let extism_manifest::Wasm::Precompiled { path, .. } = wasm {
+            anyhow::bail!(
+                "Precompiled modules aren't allowed, use `PluginBuilder::with_precompiled_modules` to load {}",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Resolve the `extends` and `include` fields of a manifest, the modules and config from included manifests are
+/// merged into the returned manifest. Config values that reference secret files are replaced by their contents.
+/// `dir` is used to resolve relative paths, when it's `None` the current directory is used.
+pub(crate) fn resolve_includes(
+    manifest: extism_manifest::Manifest,
+    dir: Option<&std::path::Path>,
+) -> Result<extism_manifest::Manifest, Error> {
+    resolve(manifest, dir, false, &mut vec![])
+}
+
+// `stack` contains the sources of the manifests that are currently being resolved, it's used to detect cycles
+fn resolve(
+    mut manifest: extism_manifest::Manifest,
+    dir: Option<&std::path::Path>,
+    remote: bool,
+    stack: &mut Vec<String>,
+) -> Result<extism_manifest::Manifest, Error> {
+    for wasm in manifest.wasm.iter_mut() {
+        if let extism_manifest::Wasm::File { path, .. }
+        | extism_manifest::Wasm::Precompiled { path, .. }
+        | extism_manifest::Wasm::Dir { path, .. }
+        | extism_manifest::Wasm::Archive { path, .. } = wasm
+        {
+            if remote {
+                anyhow::bail!(
+                    "Remote manifest {} references a local file: {}",
+                    stack.last().map(|x| x.as_str()).unwrap_or_default(),
+                    path.display()
+                );
+            }
+            if let Some(dir) = dir {
+                *path = dir.join(&path);
+            }
+        }
+    }
+
+    let source = stack.last().map(|x| x.as_str()).unwrap_or_default();
+    let remote_source = if remote { Some(source) } else { None };
+    secrets::resolve(
+        &mut manifest.config,
+        &mut manifest.config_secrets,
+        dir,
+        remote_source,
+    )?;
+    for wasm in manifest.wasm.iter_mut() {
+        let meta = wasm.meta_mut();
+        secrets::resolve(
+            &mut meta.config,
+            &mut meta.config_secrets,
+            dir,
+            remote_source,
+        )?;
+    }
+
+    // Manifests found in archives are included like manifests in `include`
+    let mut archived = vec![];
+    if manifest.wasm.iter().any(|x| {
+        matches!(
+            x,
+            extism_manifest::Wasm::Dir { .. } | extism_manifest::Wasm::Archive { .. }
+        )
+    }) {
+        let mut wasm = vec![];
+        for w in std::mem::take(&mut manifest.wasm) {
+            match w {
+                extism_manifest::Wasm::Dir {
+                    path,
+                    pattern,
+                    meta,
+                } => wasm.extend(expand_dir(&path, pattern.as_deref(), &meta)?),
+                extism_manifest::Wasm::Archive {
+                    path,
+                    pattern,
+                    meta,
+                } => match archive::read(&path, pattern.as_deref(), &meta)? {
+                    archive::Archive::Modules(modules) => wasm.extend(modules),
+                    archive::Archive::Manifest(child) => {
+                        archived.push((path.display().to_string(), *child))
+                    }
+                },
+                w => wasm.push(w),
+            }
+        }
+        manifest.wasm = wasm;
+    }
+
+    // Resolve the base manifest first, then merge this manifest on top of it
+    if let Some(extends) = manifest.extends.take() {
+        let (source, data, base_dir) = fetch_include(&extends, dir, remote)?;
+        if stack.contains(&source) {
+            anyhow::bail!("Extends cycle: {} -> {source}", stack.join(" -> "));
+        }
+
+        let (base, module) = parse(&data)?;
+        if module.is_some() {
+            anyhow::bail!("Base manifest {source} is a WebAssembly module");
+        }
+
+        stack.push(source);
+        let is_remote = remote || matches!(extends, extism_manifest::Include::Url { .. });
+        let base = resolve(base, base_dir.as_deref(), is_remote, stack)?;
+        stack.pop();
+        manifest = extism_manifest::Manifest::merge(base, manifest);
+    }
+
+    let includes = std::mem::take(&mut manifest.include);
+    let mut wasm = vec![];
+    let mut config = BTreeMap::new();
+    for include in includes {
+        let (source, data, child_dir) = fetch_include(&include, dir, remote)?;
+        if stack.contains(&source) {
+            anyhow::bail!("Include cycle: {} -> {source}", stack.join(" -> "));
+        }
+
+        let (child, module) = parse(&data)?;
+        if module.is_some() {
+            anyhow::bail!("Included manifest {source} is a WebAssembly module");
+        }
+
+        stack.push(source.clone());
+        let is_remote = remote || matches!(include, extism_manifest::Include::Url { .. });
+        let child = resolve(child, child_dir.as_deref(), is_remote, stack)?;
+        stack.pop();
+
+        check_narrowed(&manifest, &child, &source)?;
+        wasm.extend(child.wasm);
+        config.extend(child.config);
+    }
+
+    // Archived manifests can't reference anything outside of the archive, so they're resolved like remote
+    // manifests
+    for (source, child) in archived {
+        stack.push(source.clone());
+        let child = resolve(child, None, true, stack)?;
+        stack.pop();
+
+        check_narrowed(&manifest, &child, &source)?;
+        wasm.extend(child.wasm);
+        config.extend(child.config);
+    }
+
+    wasm.append(&mut manifest.wasm);
+    config.append(&mut manifest.config);
+    manifest.wasm = wasm;
+    manifest.config = config;
+    Ok(manifest)
+}
+
+// List the modules in a `Wasm::Dir` directory, sorted by file name
+fn expand_dir(
+    path: &std::path::Path,
+    pattern: Option<&str>,
+    meta: &extism_manifest::WasmMetadata,
+) -> Result<Vec<extism_manifest::Wasm>, Error> {
+    let pattern = glob::Pattern::new(pattern.unwrap_or("*.wasm"))?;
+    let mut files = vec![];
+    let entries = std::fs::read_dir(path)
+        .map_err(|e| anyhow::format_err!("Unable to read {}: {e}", path.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if entry.file_type()?.is_file() && pattern.matches(&file_name) {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    let mut modules = Vec::with_capacity(files.len());
+    for file in files {
+        // Compressed modules have two extensions, like `lib.wasm.gz`
+        let name = match file.file_stem().map(|x| x.to_string_lossy()) {
+            Some(name) => name.strip_suffix(".wasm").unwrap_or(&name).to_string(),
+            None => continue,
+        };
+        let mut wasm = extism_manifest::Wasm::file(file);
+        *wasm.meta_mut() = extism_manifest::WasmMetadata {
+            name: Some(name),
+            hash: None,
+            ..meta.clone()
+        };
+        modules.push(wasm);
+    }
     Ok(modules)
 }
+
+// Returns the source used to identify the manifest, its contents and the directory used to resolve relative
+// paths inside of it
+fn fetch_include(
+    include: &extism_manifest::Include,
+    dir: Option<&std::path::Path>,
+    remote: bool,
+) -> Result<(String, Vec<u8>, Option<std::path::PathBuf>), Error> {
+    match include {
+        extism_manifest::Include::File { path } => {
+            if remote {
+                anyhow::bail!("Remote manifest includes a local file: {}", path.display());
+            }
+
+            let path = match dir {
+                Some(dir) => dir.join(path),
+                None => path.clone(),
+            };
+            let path = std::fs::canonicalize(&path)
+                .map_err(|e| anyhow::format_err!("Unable to include {}: {e}", path.display()))?;
+            let data = std::fs::read(&path)?;
+            let dir = path.parent().map(|x| x.to_path_buf());
+            Ok((path.display().to_string(), data, dir))
+        }
+        #[allow(unused)]
+        extism_manifest::Include::Url { req } => {
+            #[cfg(not(feature = "register-http"))]
+            {
+                anyhow::bail!("HTTP registration is disabled");
+            }
+
+            #[cfg(feature = "register-http")]
+            {
+                let data = fetch(req)?;
+                Ok((req.url.clone(), data, None))
+            }
+        }
+    }
+}
+
+// Included manifests can't allow anything that the including manifest doesn't
+fn check_narrowed(
+    parent: &extism_manifest::Manifest,
+    child: &extism_manifest::Manifest,
+    source: &str,
+) -> Result<(), Error> {
+    let policy = Policy::from_manifest(parent);
+    for host in child.allowed_hosts.iter().flatten() {
+        if !policy.allows_host_pattern(host) {
+            anyhow::bail!("Included manifest {source} allows host {host}, which isn't allowed by the including manifest");
+        }
+    }
+
+    for (src, dest) in child.allowed_paths.iter().flatten() {
+        if !policy.allows_path(src, dest.path(), dest.is_readonly()) {
+            anyhow::bail!(
+                "Included manifest {source} allows path {}, which isn't allowed by the including manifest",
+                src.display()
+            );
+        }
+    }
+
+    Ok(())
+}
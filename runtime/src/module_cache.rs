@@ -0,0 +1,300 @@
+use sha2::Digest;
+
+use crate::backend::Backend;
+
+use crate::*;
+
+// The max number of linkers cached for each engine, linkers keep their host functions and user data alive so the
+// least recently used linker is removed when a new one is added
+const MAX_LINKERS: usize = 16;
+
+// A shared engine and all of the modules that have been compiled with it, keyed by the hex encoded
+// SHA-256 digest of the module source. `linkers` is ordered from least to most recently used.
+struct CachedEngine {
+    config: EngineConfig,
+    engine: Engine,
+    modules: BTreeMap<String, Module>,
+    linkers: Vec<(LinkerKey, Linker<CurrentPlugin>)>,
+}
+
+// Identifies the definitions in a base linker: whether or not WASI is enabled, which WASI
+// implementation is used and the host functions that were added to it. The key holds a reference to each
+// function, so the address of a function can't be reused by another one while the linker is cached.
+struct LinkerKey {
+    wasi: bool,
+    preview2: bool,
+    functions: Vec<(
+        Option<String>,
+        String,
+        std::sync::Arc<function::FunctionInner>,
+    )>,
+}
+
+impl LinkerKey {
+    fn new(wasi: bool, preview2: bool, functions: &[Function]) -> LinkerKey {
+        LinkerKey {
+            wasi,
+            preview2,
+            functions: functions
+                .iter()
+                .map(|f| (f.namespace.clone(), f.name.clone(), f.f.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl PartialEq for LinkerKey {
+    fn eq(&self, other: &LinkerKey) -> bool {
+        self.wasi == other.wasi
+            && self.preview2 == other.preview2
+            && self.functions.len() == other.functions.len()
+            && self.functions.iter().zip(other.functions.iter()).all(
+                |((ns, name, f), (ns1, name1, f1))| {
+                    ns == ns1
+                        && name == name1
+                        && std::sync::Arc::as_ptr(f) as *const ()
+                            == std::sync::Arc::as_ptr(f1) as *const ()
+                },
+            )
+    }
+}
+
+/// Settings for the on-disk cache of compiled modules, see `PluginBuilder::with_cache_config`. Modules are stored
+/// in `dir` using the digest of the module and the compatibility hash of the engine as the file name, so a cached
+/// module is only used by engines that are able to load it.
+///
+/// Cached modules are loaded without being validated, so `dir` must only be writable by trusted users.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The directory used to store compiled modules, it's created if it doesn't exist
+    pub dir: std::path::PathBuf,
+
+    /// The maximum total size of the cached modules in bytes, the least recently used modules are removed when the
+    /// cache grows past this size. The cache isn't limited when this isn't set.
+    pub max_size: Option<u64>,
+}
+
+impl CacheConfig {
+    /// Cache compiled modules in `dir` without a size limit
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> CacheConfig {
+        CacheConfig {
+            dir: dir.into(),
+            max_size: None,
+        }
+    }
+
+    /// Limit the total size of the cache
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    fn path(&self, engine: &Engine, digest: &str) -> std::path::PathBuf {
+        self.dir.join(format!(
+            "{digest}-{}.cwasm",
+            backend::Active::compatibility_hash(engine)
+        ))
+    }
+
+    // Load a module from the cache, or compile it and add it to the cache
+    fn compile(&self, engine: &Engine, digest: &str, data: &[u8]) -> Result<Module, Error> {
+        let module = self.load_or_compile(engine, digest, data);
+        self.evict();
+        module
+    }
+
+    // Failing to write to the cache isn't an error, since the module is still usable
+    fn load_or_compile(&self, engine: &Engine, digest: &str, data: &[u8]) -> Result<Module, Error> {
+        let path = self.path(engine, digest);
+        if let Ok(buf) = std::fs::read(&path) {
+            match backend::Active::deserialize(engine, &buf) {
+                Ok(module) => {
+                    trace!("Disk cache hit: {}", path.display());
+
+                    // The modification time is used to find the least recently used modules
+                    let _ = std::fs::File::options()
+                        .write(true)
+                        .open(&path)
+                        .and_then(|f| f.set_modified(std::time::SystemTime::now()));
+                    return Ok(module);
+                }
+                Err(e) => {
+                    debug!("Removing invalid cached module {}: {e:?}", path.display());
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+
+        trace!("Disk cache miss: {}", path.display());
+        let buf = backend::Active::precompile(engine, data)?;
+        let module = backend::Active::deserialize(engine, &buf)?;
+        if let Err(e) = self.write(&path, &buf) {
+            error!(
+                "Unable to write {} to the module cache: {e}",
+                path.display()
+            );
+        }
+        Ok(module)
+    }
+
+    // Write to a temporary file first so other processes never load a partially written module
+    fn write(&self, path: &std::path::Path, data: &[u8]) -> Result<(), std::io::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+    }
+
+    // Remove the least recently used modules until the cache is below `max_size`
+    fn evict(&self) {
+        let max_size = match self.max_size {
+            Some(x) => x,
+            None => return,
+        };
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let path = entry.path();
+                if path.extension()? != "cwasm" {
+                    return None;
+                }
+                let meta = entry.metadata().ok()?;
+                Some((meta.modified().ok()?, meta.len(), path))
+            })
+            .collect();
+        let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+        files.sort();
+        for (_, size, path) in files {
+            if total <= max_size {
+                break;
+            }
+            debug!("Removing {} from the module cache", path.display());
+            if std::fs::remove_file(&path).is_ok() {
+                total -= size;
+            }
+        }
+    }
+}
+
+static MODULE_CACHE: std::sync::Mutex<Vec<CachedEngine>> = std::sync::Mutex::new(Vec::new());
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<CachedEngine>> {
+    match MODULE_CACHE.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
+
+/// Get the shared `Engine` for the given settings, creating it if needed. Modules compiled using
+/// `compile` with a shared engine are cached for the lifetime of the process.
+pub(crate) fn engine(config: &EngineConfig) -> Result<Engine, Error> {
+    let mut cache = lock();
+    if let Some(entry) = cache.iter().find(|x| &x.config == config) {
+        return Ok(entry.engine.clone());
+    }
+
+    let engine = config.engine()?;
+    cache.push(CachedEngine {
+        config: config.clone(),
+        engine: engine.clone(),
+        modules: BTreeMap::new(),
+        linkers: vec![],
+    });
+    Ok(engine)
+}
+
+/// Compile a module, if `engine` is a shared engine then the result is cached and re-used
+/// for any other plugin loading the same module.
+pub(crate) fn compile(engine: &Engine, data: impl AsRef<[u8]>) -> Result<Module, Error> {
+    let data = data.as_ref();
+    let is_shared = lock()
+        .iter()
+        .any(|x| backend::Active::same_engine(&x.engine, engine));
+    if !is_shared {
+        return backend::Active::compile(engine, data);
+    }
+
+    let digest = manifest::hex(&sha2::Sha256::digest(data));
+    let (cached, disk_cache) = lock()
+        .iter()
+        .find(|x| backend::Active::same_engine(&x.engine, engine))
+        .map(|x| (x.modules.get(&digest).cloned(), x.config.cache.clone()))
+        .unwrap_or_default();
+    if let Some(module) = cached {
+        trace!("Module cache hit: {digest}");
+        return Ok(module);
+    }
+
+    // Compile without holding the lock so multiple modules can be compiled in parallel
+    trace!("Module cache miss: {digest}");
+    let module = match disk_cache {
+        Some(cache) => cache.compile(engine, &digest, data)?,
+        None => backend::Active::compile(engine, data)?,
+    };
+    if let Some(entry) = lock()
+        .iter_mut()
+        .find(|x| backend::Active::same_engine(&x.engine, engine))
+    {
+        entry.modules.insert(digest, module.clone());
+    }
+    Ok(module)
+}
+
+/// Get a linker with WASI and the given host functions defined, if `engine` is a shared engine then
+/// a clone of the cached linker is returned, otherwise `build` is used to create a new one. At most `MAX_LINKERS`
+/// linkers are cached for each engine.
+pub(crate) fn linker(
+    engine: &Engine,
+    wasi: bool,
+    preview2: bool,
+    functions: &[Function],
+    build: impl FnOnce() -> Result<Linker<CurrentPlugin>, Error>,
+) -> Result<Linker<CurrentPlugin>, Error> {
+    let is_shared = lock()
+        .iter()
+        .any(|x| backend::Active::same_engine(&x.engine, engine));
+    if !is_shared {
+        return build();
+    }
+
+    let key = LinkerKey::new(wasi, preview2, functions);
+    if let Some(entry) = lock()
+        .iter_mut()
+        .find(|x| backend::Active::same_engine(&x.engine, engine))
+    {
+        if let Some(index) = entry.linkers.iter().position(|(k, _)| k == &key) {
+            let cached = entry.linkers.remove(index);
+            let linker = cached.1.clone();
+            entry.linkers.push(cached);
+            return Ok(linker);
+        }
+    }
+
+    let linker = build()?;
+    if let Some(entry) = lock()
+        .iter_mut()
+        .find(|x| backend::Active::same_engine(&x.engine, engine))
+    {
+        if entry.linkers.len() >= MAX_LINKERS {
+            entry.linkers.remove(0);
+        }
+        entry.linkers.push((key, linker.clone()));
+    }
+    Ok(linker)
+}
+
+/// Remove all modules and linkers from the process-wide module cache, plugins that have already been created
+/// will continue to work
+pub fn clear_module_cache() {
+    for entry in lock().iter_mut() {
+        entry.modules.clear();
+        entry.linkers.clear();
+    }
+}
@@ -0,0 +1,232 @@
+//! Host functions that allow a trusted plugin to create and call other plugins.
+//!
+//! `NestedPlugins` provides three host functions in the `env` namespace:
+//!
+//! - `nested_plugin_new(manifest: i64) -> i64`: create a plugin from a JSON manifest and return its handle
+//! - `nested_plugin_call(plugin: i64, name: i64, input: i64) -> i64`: call a function and return the output
+//! - `nested_plugin_free(plugin: i64)`: drop a plugin
+//!
+//! Child manifests are restricted before they're loaded: memory and timeouts are capped by `NestedLimits`,
+//! allowed hosts and paths are narrowed to those allowed by the parent's `Policy`, modules can only be
+//! loaded from memory or from URLs the parent is allowed to access, and config values can't be read from files.
+//!
+//! Each `NestedPlugins` should only be used by a single parent plugin, since handles are shared between all
+//! plugins the functions are linked into.
+use std::sync::{Arc, Mutex};
+
+use crate::*;
+
+/// Limits applied to every child plugin
+#[derive(Debug, Clone)]
+pub struct NestedLimits {
+    /// The maximum number of child plugins that can exist at once
+    pub max_plugins: usize,
+
+    /// The maximum number of memory pages for each child
+    pub max_memory_pages: u32,
+
+    /// The maximum timeout for each call to a child
+    pub max_timeout: std::time::Duration,
+
+    /// Enable WASI for child plugins
+    pub wasi: bool,
+}
+
+impl Default for NestedLimits {
+    fn default() -> Self {
+        NestedLimits {
+            max_plugins: 16,
+            max_memory_pages: 256,
+            max_timeout: std::time::Duration::from_secs(5),
+            wasi: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    plugins: BTreeMap<i64, Plugin>,
+    next: i64,
+}
+
+/// Host functions for creating and calling child plugins
+#[derive(Clone, Default)]
+pub struct NestedPlugins {
+    limits: NestedLimits,
+    state: Arc<Mutex<State>>,
+}
+
+impl NestedPlugins {
+    /// Create a new set of host functions using the default limits
+    pub fn new() -> NestedPlugins {
+        NestedPlugins::default()
+    }
+
+    /// Set the limits applied to child plugins
+    pub fn with_limits(mut self, limits: NestedLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The number of child plugins that currently exist
+    pub fn count(&self) -> usize {
+        lock(&self.state).plugins.len()
+    }
+
+    /// Create the host functions, these can be passed to `PluginBuilder::with_functions`
+    pub fn functions(&self) -> Vec<Function> {
+        let limits = self.limits.clone();
+        let state = self.state.clone();
+        let new = Function::new(
+            "nested_plugin_new",
+            [ValType::I64],
+            [ValType::I64],
+            None,
+            move |plugin, inputs, outputs, _user_data| {
+                let manifest: Manifest =
+                    serde_json::from_slice(plugin.memory_get_val::<&[u8]>(&inputs[0])?)?;
+                let manifest = restrict(manifest, &limits, plugin.policy())?;
+
+                if lock(&state).plugins.len() >= limits.max_plugins {
+                    anyhow::bail!(
+                        "Too many nested plugins, the limit is {}",
+                        limits.max_plugins
+                    );
+                }
+
+                let child = Plugin::new_with_manifest(&manifest, [], limits.wasi)?;
+                let mut state = lock(&state);
+                state.next += 1;
+                let id = state.next;
+                trace!("Created nested plugin {id}: {}", child.id);
+                state.plugins.insert(id, child);
+                outputs[0] = Val::I64(id);
+                Ok(())
+            },
+        );
+
+        let state = self.state.clone();
+        let call = Function::new(
+            "nested_plugin_call",
+            [ValType::I64, ValType::I64, ValType::I64],
+            [ValType::I64],
+            None,
+            move |plugin, inputs, outputs, _user_data| {
+                let id = inputs[0].unwrap_i64();
+                let name = plugin.memory_get_val::<&str>(&inputs[1])?.to_string();
+                let input = plugin.memory_get_val::<&[u8]>(&inputs[2])?.to_vec();
+
+                // The child is removed while it's called so other children can be used from other threads
+                let mut child = match lock(&state).plugins.remove(&id) {
+                    Some(x) => x,
+                    None => anyhow::bail!("Invalid nested plugin handle: {id}"),
+                };
+                let output = child.call_bytes(&name, &input).map(|x| x.to_vec());
+                lock(&state).plugins.insert(id, child);
+
+                let handle = plugin.memory_new(&output?)?;
+                outputs[0] = plugin.memory_to_val(handle);
+                Ok(())
+            },
+        );
+
+        let state = self.state.clone();
+        let free = Function::new(
+            "nested_plugin_free",
+            [ValType::I64],
+            [],
+            None,
+            move |_plugin, inputs, _outputs, _user_data| {
+                lock(&state).plugins.remove(&inputs[0].unwrap_i64());
+                Ok(())
+            },
+        );
+
+        vec![
+            new.with_namespace("env"),
+            call.with_namespace("env"),
+            free.with_namespace("env"),
+        ]
+    }
+}
+
+fn lock(m: &Mutex<State>) -> std::sync::MutexGuard<'_, State> {
+    match m.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
+
+// Restrict a child manifest so it can't access anything the parent can't
+pub(crate) fn restrict(
+    mut manifest: Manifest,
+    limits: &NestedLimits,
+    parent: &Policy,
+) -> Result<Manifest, Error> {
+    if !manifest.include.is_empty() || manifest.extends.is_some() {
+        anyhow::bail!("Nested plugin manifests can't include other manifests");
+    }
+
+    // Secrets would be read from the host's filesystem and returned to the guest by `extism_config_get`
+    let has_secrets = !manifest.config_secrets.is_empty()
+        || manifest
+            .wasm
+            .iter()
+            .any(|x| !x.meta().config_secrets.is_empty());
+    if has_secrets {
+        anyhow::bail!("Nested plugin manifests can't reference secret files");
+    }
+
+    for wasm in manifest.wasm.iter() {
+        match wasm {
+            extism_manifest::Wasm::Data { .. } => (),
+            extism_manifest::Wasm::File { path, .. }
+            | extism_manifest::Wasm::Precompiled { path, .. }
+            | extism_manifest::Wasm::Dir { path, .. }
+            | extism_manifest::Wasm::Archive { path, .. } => {
+                anyhow::bail!(
+                    "Nested plugins can't be loaded from files: {}",
+                    path.display()
+                )
+            }
+            extism_manifest::Wasm::Url { req, .. } => parent.check_http(&req.url)?,
+            extism_manifest::Wasm::Registry { registry, .. } => {
+                let r = oci::Reference::parse(registry)?;
+                parent.check_http(&r.url("/v2/"))?
+            }
+        }
+    }
+
+    let max_pages = limits.max_memory_pages;
+    manifest.memory.max_pages = Some(
+        manifest
+            .memory
+            .max_pages
+            .map_or(max_pages, |x| x.min(max_pages)),
+    );
+
+    let max_timeout = limits.max_timeout.as_millis() as u64;
+    manifest.timeout_ms = Some(
+        manifest
+            .timeout_ms
+            .map_or(max_timeout, |x| x.min(max_timeout)),
+    );
+    for timeout in manifest.function_timeouts.values_mut() {
+        *timeout = (*timeout).min(max_timeout);
+    }
+
+    if let Some(hosts) = &mut manifest.allowed_hosts {
+        hosts.retain(|x| parent.allows_host_pattern(x));
+    }
+    for host in parent.denied_hosts() {
+        if !manifest.denied_hosts.contains(host) {
+            manifest.denied_hosts.push(host.clone());
+        }
+    }
+
+    if let Some(paths) = &mut manifest.allowed_paths {
+        paths.retain(|src, dest| parent.allows_path(src, dest.path(), dest.is_readonly()));
+    }
+
+    Ok(manifest)
+}
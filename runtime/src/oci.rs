@@ -0,0 +1,323 @@
+// Pull WebAssembly modules from OCI registries, using the distribution API:
+// https://github.com/opencontainers/distribution-spec/blob/main/spec.md
+#[cfg(feature = "register-http")]
+use sha2::Digest;
+
+use crate::*;
+
+const PREFIX: &str = "oci://";
+
+// Accepted manifest types, image indexes aren't supported since Wasm artifacts aren't platform specific
+#[cfg(feature = "register-http")]
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+// Layer types used by Wasm artifacts
+#[cfg(feature = "register-http")]
+const WASM_LAYER_TYPES: &[&str] = &[
+    "application/vnd.wasm.content.layer.v1+wasm",
+    "application/vnd.module.wasm.content.layer.v1+wasm",
+    "application/wasm",
+];
+
+// The largest manifest that will be read
+#[cfg(feature = "register-http")]
+const MAX_MANIFEST_SIZE: u64 = 4 * 1024 * 1024;
+
+// A parsed `oci://registry/repository[:tag|@digest]` reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Reference {
+    pub(crate) registry: String,
+    pub(crate) repository: String,
+
+    // A tag or digest, tags default to `latest`
+    pub(crate) reference: String,
+}
+
+impl Reference {
+    pub(crate) fn parse(s: &str) -> Result<Reference, Error> {
+        let rest = match s.strip_prefix(PREFIX) {
+            Some(x) => x,
+            None => anyhow::bail!("OCI reference must start with {PREFIX}: {s}"),
+        };
+
+        let (registry, path) = match rest.split_once('/') {
+            Some(x) => x,
+            None => anyhow::bail!("OCI reference is missing a repository: {s}"),
+        };
+
+        let (repository, reference) = match path.split_once('@') {
+            Some((repo, digest)) => {
+                if !is_sha256_digest(digest) {
+                    anyhow::bail!("Invalid digest in OCI reference, expected sha256: {s}");
+                }
+                (repo, digest.to_string())
+            }
+            None => {
+                let name_start = path.rfind('/').map(|x| x + 1).unwrap_or(0);
+                match path[name_start..].rfind(':') {
+                    Some(i) => (
+                        &path[..name_start + i],
+                        path[name_start + i + 1..].to_string(),
+                    ),
+                    None => (path, "latest".to_string()),
+                }
+            }
+        };
+
+        if registry.is_empty()
+            || reference.is_empty()
+            || repository
+                .split('/')
+                .any(|x| x.is_empty() || x == "." || x == "..")
+        {
+            anyhow::bail!("Invalid OCI reference: {s}");
+        }
+
+        Ok(Reference {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference,
+        })
+    }
+
+    // Returns `true` if the reference is pinned to a digest
+    #[cfg(feature = "register-http")]
+    pub(crate) fn is_digest(&self) -> bool {
+        self.reference.starts_with("sha256:")
+    }
+
+    // The default module name, the last segment of the repository
+    pub(crate) fn name(&self) -> &str {
+        self.repository.rsplit('/').next().unwrap_or_default()
+    }
+
+    // Registries on the local machine are accessed using plain HTTP
+    #[cfg(any(feature = "register-http", feature = "nested"))]
+    pub(crate) fn url(&self, path: &str) -> String {
+        let host = self.registry.split(':').next().unwrap_or_default();
+        let scheme = if host == "localhost" || host == "127.0.0.1" {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{scheme}://{}{path}", self.registry)
+    }
+}
+
+fn is_sha256_digest(s: &str) -> bool {
+    match s.strip_prefix("sha256:") {
+        Some(hex) => hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+// Returns an error if `data` doesn't match `digest`
+#[cfg(feature = "register-http")]
+fn check_digest(digest: &str, data: &[u8]) -> Result<(), Error> {
+    if !is_sha256_digest(digest) {
+        anyhow::bail!("Unsupported OCI digest: {digest}");
+    }
+
+    let found = format!("sha256:{}", manifest::hex(&sha2::Sha256::digest(data)));
+    if !found.eq_ignore_ascii_case(digest) {
+        anyhow::bail!("OCI digest mismatch, found {found} but expected {digest}");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "register-http")]
+#[derive(serde::Deserialize)]
+struct OciManifest {
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[cfg(feature = "register-http")]
+#[derive(serde::Deserialize)]
+struct Descriptor {
+    #[serde(rename = "mediaType")]
+    media_type: String,
+    digest: String,
+    size: u64,
+}
+
+// Pick the layer that contains the module
+#[cfg(feature = "register-http")]
+fn wasm_layer(manifest: &OciManifest) -> Result<&Descriptor, Error> {
+    if let Some(layer) = manifest
+        .layers
+        .iter()
+        .find(|x| WASM_LAYER_TYPES.contains(&x.media_type.as_str()))
+    {
+        return Ok(layer);
+    }
+
+    match manifest.layers.as_slice() {
+        [layer] => Ok(layer),
+        _ => anyhow::bail!("OCI artifact doesn't contain a WebAssembly layer"),
+    }
+}
+
+// Download a module, returning the data and the hex-encoded SHA-256 digest of the layer
+#[cfg(feature = "register-http")]
+pub(crate) fn pull(
+    r: &Reference,
+    auth: Option<&extism_manifest::RegistryAuth>,
+) -> Result<(Vec<u8>, String), Error> {
+    let mut client = Client {
+        auth,
+        authorization: match auth {
+            Some(extism_manifest::RegistryAuth::Bearer { token }) => {
+                Some(format!("Bearer {token}"))
+            }
+            _ => None,
+        },
+    };
+
+    debug!("Fetching OCI manifest for {}/{}", r.registry, r.repository);
+    let url = r.url(&format!("/v2/{}/manifests/{}", r.repository, r.reference));
+    let data = client.get(&url, MANIFEST_TYPES, MAX_MANIFEST_SIZE)?;
+    if r.is_digest() {
+        check_digest(&r.reference, &data)?;
+    }
+
+    let manifest: OciManifest = serde_json::from_slice(&data)?;
+    let layer = wasm_layer(&manifest)?;
+    let url = r.url(&format!("/v2/{}/blobs/{}", r.repository, layer.digest));
+    let data = client.get(&url, "application/octet-stream", layer.size)?;
+    if data.len() as u64 != layer.size {
+        anyhow::bail!(
+            "OCI layer size mismatch, found {} bytes but expected {}",
+            data.len(),
+            layer.size
+        );
+    }
+    check_digest(&layer.digest, &data)?;
+
+    let hash = layer.digest["sha256:".len()..].to_ascii_lowercase();
+    Ok((data, hash))
+}
+
+#[cfg(feature = "register-http")]
+struct Client<'a> {
+    auth: Option<&'a extism_manifest::RegistryAuth>,
+
+    // The `Authorization` header, set after the first challenge
+    authorization: Option<String>,
+}
+
+#[cfg(feature = "register-http")]
+impl<'a> Client<'a> {
+    fn get(&mut self, url: &str, accept: &str, max_size: u64) -> Result<Vec<u8>, Error> {
+        use std::io::Read;
+
+        let res = match self.request(url, accept)?.call() {
+            Err(ureq::Error::Status(401, res)) if self.authorization.is_none() => {
+                let challenge = res.header("www-authenticate").unwrap_or_default();
+                self.authorization = Some(self.authenticate(challenge)?);
+                self.request(url, accept)?.call()?
+            }
+            res => res?,
+        };
+
+        let mut data = Vec::new();
+        res.into_reader()
+            .take(max_size + 1)
+            .read_to_end(&mut data)?;
+        if data.len() as u64 > max_size {
+            anyhow::bail!("OCI response from {url} is larger than {max_size} bytes");
+        }
+        Ok(data)
+    }
+
+    fn request(&self, url: &str, accept: &str) -> Result<ureq::Request, Error> {
+        let req = http_client::agent(url)?
+            .build()
+            .get(url)
+            .set("Accept", accept);
+        Ok(match &self.authorization {
+            Some(authorization) => req.set("Authorization", authorization),
+            None => req,
+        })
+    }
+
+    // Get the `Authorization` header for a `WWW-Authenticate` challenge
+    fn authenticate(&self, challenge: &str) -> Result<String, Error> {
+        use base64::Engine;
+
+        let basic = match self.auth {
+            Some(extism_manifest::RegistryAuth::Basic { username, password }) => Some(format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"))
+            )),
+            _ => None,
+        };
+
+        let (scheme, params) = challenge.split_once(' ').unwrap_or((challenge, ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            return match basic {
+                Some(x) => Ok(x),
+                None => anyhow::bail!("OCI registry requires credentials"),
+            };
+        }
+
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            anyhow::bail!("Unsupported OCI registry authentication: {challenge}");
+        }
+
+        // Exchange the credentials for a token
+        let params = parse_challenge(params);
+        let realm = match params.get("realm") {
+            Some(x) => x,
+            None => anyhow::bail!("OCI registry challenge is missing a realm: {challenge}"),
+        };
+        let mut url = url::Url::parse(realm)?;
+        for key in ["service", "scope"] {
+            if let Some(v) = params.get(key) {
+                url.query_pairs_mut().append_pair(key, v);
+            }
+        }
+
+        let mut req = http_client::agent(url.as_str())?.build().get(url.as_str());
+        if let Some(basic) = &basic {
+            req = req.set("Authorization", basic);
+        }
+        let res: serde_json::Value = serde_json::from_str(&req.call()?.into_string()?)?;
+        match res
+            .get("token")
+            .or_else(|| res.get("access_token"))
+            .and_then(|x| x.as_str())
+        {
+            Some(token) => Ok(format!("Bearer {token}")),
+            None => anyhow::bail!("OCI token response doesn't contain a token"),
+        }
+    }
+}
+
+// Parse the `key="value"` parameters of a `WWW-Authenticate` header
+#[cfg(feature = "register-http")]
+fn parse_challenge(s: &str) -> BTreeMap<String, String> {
+    let mut params = BTreeMap::new();
+    let mut rest = s.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(v) => match v.find('"') {
+                Some(end) => (&v[..end], &v[end + 1..]),
+                None => (v, ""),
+            },
+            None => match value.find(',') {
+                Some(end) => (&value[..end], &value[end..]),
+                None => (value, ""),
+            },
+        };
+        params.insert(key, value.to_string());
+        rest = next;
+    }
+    params
+}
@@ -21,12 +21,23 @@ macro_rules! args {
 /// Params: i64 (offset)
 /// Returns: i64 (offset)
 pub(crate) fn config_get(
+    caller: Caller<CurrentPlugin>,
+    input: &[Val],
+    output: &mut [Val],
+) -> Result<(), Error> {
+    module_config_get(caller, input, output, None)
+}
+
+/// Get a configuration value, checking the calling module's config before the manifest config
+/// Params: i64 (offset)
+/// Returns: i64 (offset)
+pub(crate) fn module_config_get(
     mut caller: Caller<CurrentPlugin>,
     input: &[Val],
     output: &mut [Val],
+    module_config: Option<&BTreeMap<String, String>>,
 ) -> Result<(), Error> {
     let data: &mut CurrentPlugin = caller.data_mut();
-
     let offset = args!(input, 0, i64) as u64;
     let handle = match data.memory_handle(offset) {
         Some(h) => h,
@@ -36,7 +47,9 @@ pub(crate) fn config_get(
     let key = unsafe {
         std::str::from_utf8_unchecked(std::slice::from_raw_parts(key.as_ptr(), key.len()))
     };
-    let val = data.manifest.config.get(key);
+    let val = module_config
+        .and_then(|x| x.get(key))
+        .or_else(|| data.manifest.config.get(key));
     let ptr = val.map(|x| (x.len(), x.as_ptr()));
     let mem = match ptr {
         Some((len, ptr)) => {
@@ -61,6 +74,9 @@ pub(crate) fn var_get(
     output: &mut [Val],
 ) -> Result<(), Error> {
     let data: &mut CurrentPlugin = caller.data_mut();
+    if data.policy.kv_max_bytes().is_none() {
+        anyhow::bail!("Plugin variables are not allowed");
+    }
 
     let offset = args!(input, 0, i64) as u64;
     let handle = match data.memory_handle(offset) {
@@ -71,13 +87,9 @@ pub(crate) fn var_get(
     let key = unsafe {
         std::str::from_utf8_unchecked(std::slice::from_raw_parts(key.as_ptr(), key.len()))
     };
-    let val = data.vars.get(key);
-    let ptr = val.map(|x| (x.len(), x.as_ptr()));
-    let mem = match ptr {
-        Some((len, ptr)) => {
-            let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
-            data.memory_new(bytes)?
-        }
+    let val = data.vars.get(key).cloned();
+    let mem = match val {
+        Some(bytes) => data.memory_new(&bytes[..])?,
         None => {
             output[0] = Val::I64(0);
             return Ok(());
@@ -96,6 +108,10 @@ pub(crate) fn var_set(
     _output: &mut [Val],
 ) -> Result<(), Error> {
     let data: &mut CurrentPlugin = caller.data_mut();
+    let max_bytes = match data.policy.kv_max_bytes() {
+        Some(n) => n,
+        None => anyhow::bail!("Plugin variables are not allowed"),
+    };
 
     let mut size = 0;
     for v in data.vars.values() {
@@ -104,8 +120,8 @@ pub(crate) fn var_set(
 
     let voffset = args!(input, 1, i64) as u64;
 
-    // If the store is larger than 100MB then stop adding things
-    if size > 1024 * 1024 * 100 && voffset != 0 {
+    // If the store is larger than the policy allows then stop adding things
+    if size > max_bytes && voffset != 0 {
         return Err(Error::msg("Variable store is full"));
     }
 
@@ -132,7 +148,7 @@ pub(crate) fn var_set(
         None => anyhow::bail!("invalid handle offset: {key_offs}"),
     };
 
-    let value = data.memory_bytes(handle)?.to_vec();
+    let value = Bytes::copy_from_slice(data.memory_bytes(handle)?);
 
     // Insert the value from memory into the `vars` map
     data.vars.insert(key.to_string(), value);
@@ -150,10 +166,10 @@ pub(crate) fn http_request(
 ) -> Result<(), Error> {
     #[cfg(not(feature = "http"))]
     {
-        let _ = (caller, input);
+        let _ = input;
 
         output[0] = Val::I64(0);
-        error!("http_request is not enabled");
+        error!("Plugin {}: http_request is not enabled", caller.data().id);
         return Ok(());
     }
 
@@ -161,6 +177,9 @@ pub(crate) fn http_request(
     {
         use std::io::Read;
         let data: &mut CurrentPlugin = caller.data_mut();
+        if data.manifest.deterministic_seed.is_some() {
+            anyhow::bail!("HTTP requests aren't allowed in deterministic mode");
+        }
         let http_req_offset = args!(input, 0, i64) as u64;
 
         let handle = match data.memory_handle(http_req_offset) {
@@ -171,47 +190,75 @@ pub(crate) fn http_request(
 
         let body_offset = args!(input, 1, i64) as u64;
 
-        let url = match url::Url::parse(&req.url) {
-            Ok(u) => u,
-            Err(e) => return Err(Error::msg(format!("Invalid URL: {e:?}"))),
-        };
-        let allowed_hosts = &data.manifest.allowed_hosts;
-        let host_str = url.host_str().unwrap_or_default();
-        let host_matches = if let Some(allowed_hosts) = allowed_hosts {
-            allowed_hosts.iter().any(|url| {
-                let pat = match glob::Pattern::new(url) {
-                    Ok(x) => x,
-                    Err(_) => return url == host_str,
-                };
-
-                pat.matches(host_str)
-            })
+        let body = if body_offset > 0 {
+            let handle = match data.memory_handle(body_offset) {
+                Some(h) => h,
+                None => anyhow::bail!("invalid handle offset: {http_req_offset}"),
+            };
+            Some(data.memory_bytes(handle)?.to_vec())
         } else {
-            false
+            req.body.as_ref().map(|x| x.as_bytes().to_vec())
         };
 
-        if !host_matches {
-            return Err(Error::msg(format!(
-                "HTTP request to {} is not allowed",
-                req.url
-            )));
-        }
-
-        let mut r = ureq::request(req.method.as_deref().unwrap_or("GET"), &req.url);
+        // Redirects are followed manually so every URL is checked against the policy
+        let mut url = req.url.clone();
+        let mut method = req.method.clone().unwrap_or_else(|| "GET".to_string());
+        let mut body = body;
+        let mut redirects = 0;
+        let res = loop {
+            data.policy.check_http(&url)?;
+
+            // Connect to the addresses checked by the policy, so the host can't be resolved again to a
+            // different address. Lookup failures are reported by the resolver, like any other connection error
+            let addrs = match data.policy.resolve(&url) {
+                Ok(x) => x,
+                Err(e) if e.is::<std::io::Error>() => vec![],
+                Err(e) => return Err(e),
+            };
+            let mut agent = ureq::AgentBuilder::new();
+            if let Some(ms) = req.timeout_ms {
+                agent = agent.timeout(std::time::Duration::from_millis(ms));
+            }
+            let agent = agent
+                .redirects(0)
+                .resolver(move |netloc: &str| {
+                    if addrs.is_empty() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("Unable to resolve {netloc}"),
+                        ));
+                    }
+                    Ok(addrs.clone())
+                })
+                .build();
+
+            let mut r = agent.request(&method, &url);
+            for (k, v) in req.headers.iter() {
+                r = r.set(k, v);
+            }
 
-        for (k, v) in req.headers.iter() {
-            r = r.set(k, v);
-        }
+            let res = match &body {
+                Some(buf) => r.send_bytes(buf),
+                None => r.call(),
+            };
 
-        let res = if body_offset > 0 {
-            let handle = match data.memory_handle(body_offset) {
-                Some(h) => h,
-                None => anyhow::bail!("invalid handle offset: {http_req_offset}"),
+            let (status, location) = match &res {
+                Ok(res) if (300..400).contains(&res.status()) => {
+                    (res.status(), res.header("location").map(|x| x.to_string()))
+                }
+                _ => (0, None),
             };
-            let buf: &[u8] = data.memory_bytes(handle)?;
-            r.send_bytes(buf)
-        } else {
-            r.call()
+            let location = match location {
+                Some(x) if redirects < req.redirect_limit() => x,
+                _ => break res,
+            };
+
+            redirects += 1;
+            url = url::Url::parse(&url)?.join(&location)?.to_string();
+            if status == 303 || ((status == 301 || status == 302) && method != "HEAD") {
+                method = "GET".to_string();
+                body = None;
+            }
         };
 
         let reader = match res {
@@ -230,10 +277,17 @@ pub(crate) fn http_request(
         };
 
         if let Some(reader) = reader {
+            // Read one byte past the limit to tell a response that fits exactly from one that's too large
+            let limit = data
+                .manifest
+                .memory
+                .max_http_response_bytes
+                .unwrap_or(extism_manifest::DEFAULT_MAX_HTTP_RESPONSE_BYTES);
             let mut buf = Vec::new();
-            reader
-                .take(1024 * 1024 * 50) // TODO: make this limit configurable
-                .read_to_end(&mut buf)?;
+            reader.take(limit.saturating_add(1)).read_to_end(&mut buf)?;
+            if buf.len() as u64 > limit {
+                anyhow::bail!("HTTP response is larger than the limit of {limit} bytes");
+            }
 
             let mem = data.memory_new(&buf)?;
             output[0] = Val::I64(mem.offset() as i64);
@@ -258,6 +312,32 @@ pub(crate) fn http_status_code(
     Ok(())
 }
 
+/// Write a chunk of output, this is passed to the host as soon as it's written instead of being set as the output
+/// once the call returns. Only available when the plugin is called using `Plugin::call_streaming`
+/// Params: i64 (offset)
+/// Returns: none
+pub(crate) fn output_write(
+    mut caller: Caller<CurrentPlugin>,
+    input: &[Val],
+    _output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut CurrentPlugin = caller.data_mut();
+    let offset = args!(input, 0, i64) as u64;
+
+    let sink = match data.output_sink {
+        Some(x) => x,
+        None => anyhow::bail!("extism_output_write can only be used with Plugin::call_streaming"),
+    };
+
+    let handle = match data.memory_handle(offset) {
+        Some(h) => h,
+        None => anyhow::bail!("invalid handle offset: {offset}"),
+    };
+
+    let buf = data.memory_bytes(handle)?;
+    unsafe { (*sink)(buf) }
+}
+
 pub fn log(
     level: log::Level,
     mut caller: Caller<CurrentPlugin>,
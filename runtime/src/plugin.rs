@@ -15,6 +15,10 @@ pub(crate) struct Output {
 pub struct CancelHandle {
     pub(crate) timer_tx: std::sync::mpsc::Sender<TimerAction>,
     pub id: uuid::Uuid,
+
+    /// Set for plugins that use the epoch ticker, these are interrupted directly instead of through the
+    /// timer thread
+    pub(crate) interrupted: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
 }
 
 unsafe impl Sync for CancelHandle {}
@@ -22,9 +26,50 @@ unsafe impl Send for CancelHandle {}
 
 impl CancelHandle {
     pub fn cancel(&self) -> Result<(), Error> {
+        if let Some(interrupted) = &self.interrupted {
+            interrupted.store(true, std::sync::atomic::Ordering::SeqCst);
+            return Ok(());
+        }
         self.timer_tx.send(TimerAction::Cancel { id: self.id })?;
         Ok(())
     }
+
+    /// Cancel the plugin once `duration` has elapsed, only the call that is running at that time is affected.
+    /// This doesn't start a new thread, the cancellation is scheduled on the thread used for timeouts.
+    pub fn cancel_after(&self, duration: std::time::Duration) -> Result<(), Error> {
+        self.timer_tx.send(TimerAction::CancelAt {
+            handle: self.clone(),
+            at: std::time::Instant::now() + duration,
+        })?;
+        Ok(())
+    }
+
+    /// Cancel the plugin when `token` is cancelled, this must be called from within a tokio runtime. The token is
+    /// watched by a task on the runtime until it's cancelled, the returned `JoinHandle` can be aborted to stop
+    /// watching it earlier.
+    #[cfg(feature = "cancellation-token")]
+    pub fn cancel_on(
+        &self,
+        token: tokio_util::sync::CancellationToken,
+    ) -> tokio::task::JoinHandle<()> {
+        let handle = self.clone();
+        tokio::spawn(async move {
+            token.cancelled().await;
+            if let Err(e) = handle.cancel() {
+                error!("Unable to cancel plugin {}: {e:?}", handle.id);
+            }
+        })
+    }
+
+    /// Create a `CancellationToken` that cancels the plugin when it's cancelled, this must be called from within a
+    /// tokio runtime. Use `CancelHandle::cancel_on` with a child token to cancel the plugin when an existing token,
+    /// like one used for shutdown, is cancelled.
+    #[cfg(feature = "cancellation-token")]
+    pub fn cancellation_token(&self) -> tokio_util::sync::CancellationToken {
+        let token = tokio_util::sync::CancellationToken::new();
+        self.cancel_on(token.clone());
+        token
+    }
 }
 
 /// Plugin contains everything needed to execute a WASM function
@@ -44,11 +89,21 @@ pub struct Plugin {
     /// All modules that were provided to the linker
     pub(crate) modules: BTreeMap<String, Module>,
 
+    /// Config values for modules that have their own `config`
+    module_config: manifest::ModuleConfig,
+
     /// Instance provides the ability to call functions in a module, a `Plugin` is initialized with
     /// an `instance_pre` but no `instance`. The `instance` will be created during `Plugin::raw_call`
     pub(crate) instance: std::sync::Arc<std::sync::Mutex<Option<Instance>>>,
     pub(crate) instance_pre: InstancePre<CurrentPlugin>,
 
+    /// Exported functions that have been looked up on the current instance, along with the number of
+    /// results they return. This is cleared whenever a new instance is created
+    exports: BTreeMap<String, (Func, usize)>,
+
+    /// Kernel functions used on every call, these are looked up once per store
+    kernel: Kernel,
+
     /// Keep track of the number of times we're instantiated, this exists
     /// to avoid issues with memory piling up since `Instance`s are only
     /// actually cleaned up along with a `Store`
@@ -64,9 +119,45 @@ pub struct Plugin {
     /// Communication with the timer thread
     pub(crate) timer_tx: std::sync::mpsc::Sender<TimerAction>,
 
+    /// Set by the timer thread when the plugin has timed out or been cancelled, the engine may be
+    /// shared with other plugins so this is used to determine which store should be interrupted
+    pub(crate) interrupted: std::sync::Arc<std::sync::atomic::AtomicBool>,
+
+    /// Set when a call trapped, timed out or ran out of memory or fuel, the instance may be left in an inconsistent
+    /// state so `PluginPool` replaces these plugins
+    pub(crate) trapped: bool,
+
+    /// Set when instances are allocated from a pool, in that case the store is replaced whenever the plugin is
+    /// re-instantiated so old instances don't hold on to pool slots
+    pooling: bool,
+
+    /// When set, every call runs in a new store and instance, see `PluginBuilder::with_stateless`
+    pub(crate) stateless: bool,
+
+    /// Set when the plugin uses the epoch ticker instead of the timer thread for timeouts
+    ticker: Option<TickerGuard>,
+
+    /// When set, new instances are restored from the snapshot instead of initializing the guest runtime
+    pub(crate) snapshot: Option<Snapshot>,
+
+    /// State version, memory regions and imported state that hasn't been applied yet
+    pub(crate) state: state::StateConfig,
+
     /// Information that gets populated after a call
     pub(crate) output: Output,
 
+    /// The fuel available to each call when fuel metering is enabled, `None` means calls aren't limited
+    pub(crate) fuel_limit: Option<u64>,
+
+    /// The fuel consumed by the last call, `None` when fuel metering is disabled
+    last_call_fuel: Option<u64>,
+
+    /// The largest combined memory size seen after a call, see `Plugin::memory_stats`
+    peak_pages: u32,
+
+    /// Statistics for the most recent call that reached the plugin
+    last_call_stats: Option<CallStats>,
+
     /// Set to `true` when de-initializarion may have occured (i.e.a call to `_start`),
     /// in this case we need to re-initialize the entire module.
     pub(crate) needs_reset: bool,
@@ -81,6 +172,15 @@ impl std::fmt::Debug for Plugin {
     }
 }
 
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        // Spawned threads keep the shared memory alive and would otherwise run until they return
+        if let Some(threads) = &self.store.data().threads {
+            threads.stop(self.store.engine());
+        }
+    }
+}
+
 impl Internal for Plugin {
     fn store(&self) -> &Store<CurrentPlugin> {
         &self.store
@@ -103,23 +203,414 @@ impl Internal for Plugin {
     }
 }
 
-const EXPORT_MODULE_NAME: &str = "env";
+pub(crate) const EXPORT_MODULE_NAME: &str = "env";
+
+// The size of the first block allocated when reading input from `Plugin::call_reader`
+const INPUT_CHUNK_SIZE: usize = 64 * 1024;
+
+// Plugin input, either borrowed bytes or a reader that's copied into plugin memory as it's read
+pub(crate) enum Input<'a> {
+    Bytes(&'a [u8]),
+    Reader(&'a mut dyn std::io::Read),
+}
+
+/// The export called by `Plugin::health_check`, if it exists
+pub const HEALTH_CHECK_FUNCTION: &str = "_health";
+
+/// The amount of time a plugin without a `_health` export has to respond to `Plugin::health_check`
+pub const HEALTH_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A function exported by the main module of a plugin, returned by `Plugin::functions`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionInfo {
+    /// The export name
+    pub name: String,
+
+    /// Parameter types
+    pub params: Vec<ValType>,
+
+    /// Result types
+    pub results: Vec<ValType>,
+
+    /// `true` when the function follows the Extism calling convention: no parameters and either no results or a
+    /// single `i32` result. The input and output are passed using the kernel, so only these functions can be called
+    /// using `Plugin::call`.
+    pub extism: bool,
+}
+
+/// Something a plugin module imports, returned by `Plugin::imports` and `MissingImportsError`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportInfo {
+    /// The name of the plugin module that requires the import
+    pub module: String,
+
+    /// The namespace of the import, `env` for host functions
+    pub namespace: String,
+
+    /// The import name
+    pub name: String,
+
+    /// Parameter types, empty if the import isn't a function
+    pub params: Vec<ValType>,
+
+    /// Result types, empty if the import isn't a function
+    pub results: Vec<ValType>,
+
+    /// `false` for imported memories, tables and globals
+    pub function: bool,
+}
+
+impl ImportInfo {
+    fn new(module: &str, import: &ImportType) -> ImportInfo {
+        let ty = import.ty();
+        let f = ty.func();
+        ImportInfo {
+            module: module.to_string(),
+            namespace: import.module().to_string(),
+            name: import.name().to_string(),
+            params: f
+                .map(|f| f.params().map(ValType::from).collect())
+                .unwrap_or_default(),
+            results: f
+                .map(|f| f.results().map(ValType::from).collect())
+                .unwrap_or_default(),
+            function: f.is_some(),
+        }
+    }
+
+    /// Returns true if the import is provided by WASI
+    pub fn is_wasi(&self) -> bool {
+        self.namespace.starts_with("wasi_")
+    }
+}
+
+/// Statistics for a single call, returned by `Plugin::last_call_stats`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CallStats {
+    /// The name of the function that was called
+    pub function: String,
+
+    /// Time spent in the call, including instantiating the plugin when needed
+    pub elapsed: std::time::Duration,
+
+    /// The fuel consumed by the call, `None` when fuel metering is disabled
+    pub fuel: Option<u64>,
+
+    /// The number of pages the plugin's memories grew by during the call
+    pub memory_growth: u32,
+
+    /// The number of times each host function was called, functions that weren't called are left out. Functions with
+    /// the same name in different namespaces are counted together
+    pub host_calls: BTreeMap<String, u64>,
+}
+
+impl CallStats {
+    /// The total number of host function calls
+    pub fn total_host_calls(&self) -> u64 {
+        self.host_calls.values().sum()
+    }
+}
+
+/// Output borrowed directly from plugin memory, returned by `Plugin::call_ref`. The plugin stays borrowed while this
+/// exists, so the output can't be invalidated by another call
+#[derive(Debug, Clone, Copy)]
+pub struct OutputRef<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> OutputRef<'a> {
+    /// Decode the output using `FromBytes`, borrowed types like `&[u8]` and `&str` are decoded without copying
+    pub fn decode<T: FromBytes<'a>>(&self) -> Result<T, Error> {
+        T::from_bytes(self.data)
+    }
+}
+
+impl std::ops::Deref for OutputRef<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl AsRef<[u8]> for OutputRef<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+/// The result of running a WASI command using `Plugin::run_command`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommandOutput {
+    /// The exit code passed to `proc_exit`, zero when `_start` returns normally
+    pub exit_code: i32,
+
+    /// Data written to stdout
+    pub stdout: Vec<u8>,
+
+    /// Data written to stderr
+    pub stderr: Vec<u8>,
+}
+
+/// Memory usage returned by `Plugin::memory_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+    /// Size of the main module's exported memories in pages, zero when the plugin hasn't been instantiated
+    pub pages: u32,
+
+    /// Size of the Extism memory in pages, this is where inputs, outputs and host function arguments are stored
+    pub extism_pages: u32,
+
+    /// The largest value of `pages + extism_pages` seen since the plugin was created
+    pub peak_pages: u32,
+
+    /// Bytes the current instance can still grow by before `MemoryOptions::max_pages` is hit, `None` when memory
+    /// isn't limited
+    pub remaining_bytes: Option<usize>,
+
+    /// Number of live allocations in Extism memory
+    pub allocations: usize,
+
+    /// Total size of the live allocations in Extism memory
+    pub allocated_bytes: usize,
+}
+
+/// Timings returned by `Plugin::warm_up`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WarmUpStats {
+    /// Time spent creating the instance and running `_initialize` or restoring a snapshot, this is close to
+    /// zero if the plugin was already instantiated
+    pub instantiate: std::time::Duration,
+
+    /// Time spent making the priming call, `None` when no call was made
+    pub call: Option<std::time::Duration>,
+}
+
+impl WarmUpStats {
+    /// The total time spent warming up the plugin
+    pub fn total(&self) -> std::time::Duration {
+        self.instantiate + self.call.unwrap_or_default()
+    }
+}
+
+// Kernel functions that are called by the runtime on every call
+#[derive(Clone, Copy)]
+pub(crate) struct Kernel {
+    pub(crate) reset: Func,
+    input_set: Func,
+    error_set: Func,
+    output_offset: Func,
+    output_length: Func,
+}
+
+impl Kernel {
+    // Look up the kernel functions, this needs to be done any time the kernel is linked into a new store
+    pub(crate) fn new(
+        linker: &Linker<CurrentPlugin>,
+        store: &mut Store<CurrentPlugin>,
+    ) -> Result<Kernel, Error> {
+        store.data_mut().kernel_memory = linker
+            .get(&mut *store, EXPORT_MODULE_NAME, "memory")
+            .and_then(|x| x.into_memory());
+        let mut get = |name: &str| {
+            linker
+                .get(&mut *store, EXPORT_MODULE_NAME, name)
+                .and_then(|x| x.into_func())
+                .ok_or_else(|| anyhow::anyhow!("Kernel function not found: {name}"))
+        };
+        Ok(Kernel {
+            reset: get("extism_reset")?,
+            input_set: get("extism_input_set")?,
+            error_set: get("extism_error_set")?,
+            output_offset: get("extism_output_offset")?,
+            output_length: get("extism_output_length")?,
+        })
+    }
+}
+
+// Raise an error when the epoch deadline is encountered after the plugin has been interrupted or its deadline has
+// passed, other plugins sharing the same engine may also increment the epoch
+pub(crate) fn set_epoch_deadline_callback(
+    store: &mut Store<CurrentPlugin>,
+    interrupted: std::sync::Arc<std::sync::atomic::AtomicBool>,
+) {
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |ctx| {
+        let expired = ctx
+            .data()
+            .deadline
+            .is_some_and(|x| std::time::Instant::now() >= x);
+        if expired || interrupted.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(Error::msg("timeout"));
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+}
+
+// Set the fuel remaining in the store to `fuel`, or an unlimited amount when `fuel` is `None`. Returns the fuel
+// remaining afterwards, or `None` when fuel metering is disabled. Only the difference is added or consumed, wasmtime
+// keeps a running `i64` total of the fuel added to a store and silently stops adding fuel once that would overflow
+pub(crate) fn set_fuel(store: &mut Store<CurrentPlugin>, fuel: Option<u64>) -> Option<u64> {
+    // Leaves room in wasmtime's total for the fuel consumed over the life of the store
+    const UNLIMITED: u64 = 1 << 62;
+
+    let remaining = store.fuel_remaining()?;
+    let fuel = fuel.unwrap_or(UNLIMITED);
+    if remaining > fuel {
+        let _ = store.consume_fuel(remaining - fuel);
+    } else if remaining < fuel {
+        let _ = store.add_fuel(fuel - remaining);
+    }
+    store.fuel_remaining()
+}
+
+// Create a `Linker` with the PDK functions, WASI and the provided host functions defined. This
+// doesn't depend on the store so it can be cloned and re-used by plugins that share an engine
+pub(crate) fn base_linker(
+    engine: &Engine,
+    with_wasi: bool,
+    preview2: bool,
+    imports: &[Function],
+) -> Result<Linker<CurrentPlugin>, Error> {
+    let mut linker = Linker::new(engine);
+    linker.allow_shadowing(true);
+
+    // If wasi is enabled then add it to the linker
+    if with_wasi && preview2 {
+        wasmtime_wasi::preview2::preview1::add_to_linker_sync(&mut linker)?;
+        #[cfg(feature = "wasi-http")]
+        crate::wasi_http::add_to_linker(&mut linker)?;
+    } else if with_wasi {
+        wasmtime_wasi::add_to_linker(&mut linker, |x: &mut CurrentPlugin| {
+            &mut x.wasi.as_mut().unwrap().ctx
+        })?;
+    }
+    #[cfg(feature = "wasi-nn")]
+    if with_wasi {
+        crate::wasi_nn::add_to_linker(&mut linker)?;
+    }
+
+    // Define PDK functions
+    macro_rules! define_funcs {
+        ({ $($name:ident($($args:expr),*) $(-> $($r:expr),*)?);* $(;)?}) => {
+            $(
+                let t = FuncType::new([$($args),*], [$($($r),*)?]);
+                linker.func_new(EXPORT_MODULE_NAME, concat!("extism_", stringify!($name)), t, pdk::$name)?;
+            )*
+        };
+    }
+
+    use wasmtime::ValType::*;
+    define_funcs!({
+        config_get(I64) -> I64;
+        var_get(I64) -> I64;
+        var_set(I64, I64);
+        http_request(I64, I64) -> I64;
+        http_status_code() -> I32;
+        output_write(I64);
+        log_warn(I64);
+        log_info(I64);
+        log_debug(I64);
+        log_error(I64);
+    });
+
+    for (i, f) in imports.iter().enumerate() {
+        let name = f.name().to_string();
+        let ns = f.namespace().unwrap_or(EXPORT_MODULE_NAME);
+        let func = f.f.clone();
+        linker.func_new(
+            ns,
+            f.name(),
+            f.ty().clone(),
+            move |mut caller, params, results| {
+                caller.data_mut().count_host_call(i);
+                let id = caller.data().id;
+                let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    func(caller.data_mut(), params, results)
+                }));
+                res.unwrap_or_else(|e| {
+                    error!("Plugin {id}: host function {name} panicked");
+                    std::panic::resume_unwind(e)
+                })
+            },
+        )?;
+    }
+
+    Ok(linker)
+}
+
+// Link every module except `main`. When any module has its own config, `extism_config_get` is redefined before
+// each module is linked, since imports are resolved when the module is added to the linker. The kernel is linked
+// first, other modules may import its functions
+pub(crate) fn link_modules(
+    linker: &mut Linker<CurrentPlugin>,
+    store: &mut Store<CurrentPlugin>,
+    modules: &BTreeMap<String, Module>,
+    module_config: &manifest::ModuleConfig,
+    main_name: &str,
+) -> Result<(), Error> {
+    let define_config_get = |linker: &mut Linker<CurrentPlugin>, name: &str| {
+        if module_config.is_empty() {
+            return Ok::<_, Error>(());
+        }
+
+        let config = module_config.get(name).cloned();
+        let t = FuncType::new([wasmtime::ValType::I64], [wasmtime::ValType::I64]);
+        linker.func_new(
+            EXPORT_MODULE_NAME,
+            "extism_config_get",
+            t,
+            move |caller, input, output| {
+                pdk::module_config_get(caller, input, output, config.as_deref())
+            },
+        )?;
+        Ok(())
+    };
 
-fn profiling_strategy() -> ProfilingStrategy {
-    match std::env::var("EXTISM_PROFILE").as_deref() {
-        Ok("perf") => ProfilingStrategy::PerfMap,
-        Ok(x) => {
-            log::warn!("Invalid value for EXTISM_PROFILE: {x}");
-            ProfilingStrategy::None
+    // Modules with missing imports aren't linked, so every missing import can be reported at once instead of
+    // failing on the first one
+    let mut missing = vec![];
+    let kernel = modules.get_key_value(EXPORT_MODULE_NAME);
+    let rest = modules
+        .iter()
+        .filter(|(name, _)| *name != EXPORT_MODULE_NAME);
+    for (name, module) in kernel.into_iter().chain(rest) {
+        if name != main_name {
+            define_config_get(linker, name)?;
+            let m = missing_imports(linker, store, name, module);
+            if m.is_empty() {
+                linker.module(&mut *store, name, module)?;
+            } else {
+                missing.extend(m);
+            }
         }
-        Err(_) => ProfilingStrategy::None,
     }
+    define_config_get(linker, main_name)?;
+    missing.extend(missing_imports(
+        linker,
+        store,
+        main_name,
+        &modules[main_name],
+    ));
+
+    if !missing.is_empty() {
+        return Err(MissingImportsError { imports: missing }.into());
+    }
+    Ok(())
 }
 
-// Raise an error when the epoch deadline is encountered - this is used for timeout/cancellation
-// to stop a plugin that is executing
-fn deadline_callback(_: StoreContextMut<CurrentPlugin>) -> Result<UpdateDeadline, Error> {
-    Err(Error::msg("timeout"))
+// Imports of `module` that aren't defined in the linker
+fn missing_imports(
+    linker: &Linker<CurrentPlugin>,
+    store: &mut Store<CurrentPlugin>,
+    name: &str,
+    module: &Module,
+) -> Vec<ImportInfo> {
+    module
+        .imports()
+        .filter(|import| linker.get_by_import(&mut *store, import).is_none())
+        .map(|import| ImportInfo::new(name, &import))
+        .collect()
 }
 
 impl Plugin {
@@ -141,36 +632,98 @@ impl Plugin {
         imports: impl IntoIterator<Item = Function>,
         with_wasi: bool,
     ) -> Result<Plugin, Error> {
-        // Create a new engine, if the `EXTISM_DEBUG` environment variable is set
-        // then we enable debug info
-        let engine = Engine::new(
-            Config::new()
-                .epoch_interruption(true)
-                .debug_info(std::env::var("EXTISM_DEBUG").is_ok())
-                .profiler(profiling_strategy()),
-        )?;
-        let mut imports = imports.into_iter();
-        let (manifest, modules) = manifest::load(&engine, wasm.as_ref())?;
+        Self::new_with_options(PluginOptions::default(), wasm, imports, with_wasi)
+    }
+
+    /// Create a new plugin from a WebAssembly module or JSON encoded manifest read from `reader`, this can be used
+    /// to load modules from sockets, databases or object storage. When `hash` is set the digest is computed while
+    /// reading and an error is returned if it doesn't match, see `WasmMetadata::hash` for the supported formats.
+    pub fn new_from_reader(
+        reader: impl std::io::Read,
+        hash: Option<&str>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> Result<Plugin, Error> {
+        let data = manifest::read_verified(reader, hash)?;
+        Self::new(data, imports, with_wasi)
+    }
+
+    /// Create a new plugin in the background, this returns immediately and the plugin is compiled and
+    /// instantiated on another thread. See `DeferredPlugin` for waiting on the plugin to be ready.
+    pub fn new_deferred(
+        wasm: impl Into<Vec<u8>>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> DeferredPlugin {
+        PluginBuilder::new_with_module(wasm)
+            .with_wasi(with_wasi)
+            .with_functions(imports)
+            .build_deferred()
+    }
+
+    // Create a new plugin using the given options, engine settings are combined with any settings from the
+    // manifest
+    pub(crate) fn new_with_options(
+        options: PluginOptions,
+        wasm: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> Result<Plugin, Error> {
+        let PluginOptions {
+            mut config,
+            shared,
+            policy,
+            keys,
+            max_threads,
+            deterministic_seed,
+            precompiled,
+        } = options;
+        let (manifest, module) = manifest::parse(wasm.as_ref())?;
+        let mut manifest = manifest::resolve_includes(manifest, None)?;
+        manifest::check_precompiled(&manifest, precompiled)?;
+        if deterministic_seed.is_some() {
+            manifest.deterministic_seed = deterministic_seed;
+        }
+        if manifest.deterministic_seed.is_some() {
+            if max_threads.is_some() {
+                anyhow::bail!("Threads can't be used in deterministic mode");
+            }
+            if !manifest.wasi.nn.models.is_empty() || !manifest.wasi.nn.hashes.is_empty() {
+                anyhow::bail!("wasi-nn can't be used in deterministic mode");
+            }
+        }
+        let policy = policy.unwrap_or_else(|| Policy::from_manifest(&manifest));
+        config.update(&manifest);
+        let engine = if shared {
+            module_cache::engine(&config)?
+        } else {
+            config.engine()?
+        };
+        let (modules, module_config) = manifest::load(&engine, &manifest, module, keys.as_ref())?;
 
         let available_pages = manifest.memory.max_pages;
-        log::trace!("Available pages: {available_pages:?}");
 
         let mut store = Store::new(
             &engine,
-            CurrentPlugin::new(manifest, with_wasi, available_pages)?,
+            CurrentPlugin::new(manifest, policy, with_wasi, available_pages)?,
         );
 
-        store.set_epoch_deadline(1);
-
-        let mut linker = Linker::new(&engine);
-        linker.allow_shadowing(true);
+        let interrupted = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        set_epoch_deadline_callback(&mut store, interrupted.clone());
+        set_fuel(&mut store, None);
 
-        // If wasi is enabled then add it to the linker
+        let imports: Vec<Function> = imports.into_iter().collect();
+        let preview2 = with_wasi && store.data().wasi_preview2.is_some();
+        let mut linker = module_cache::linker(&engine, with_wasi, preview2, &imports, || {
+            base_linker(&engine, with_wasi, preview2, &imports)
+        })?;
         if with_wasi {
-            wasmtime_wasi::add_to_linker(&mut linker, |x: &mut CurrentPlugin| {
-                &mut x.wasi.as_mut().unwrap().ctx
-            })?;
+            store.data().policy.restrict_linker(&mut linker)?;
         }
+        if max_threads.is_some() {
+            threads::add_to_linker(&mut linker)?;
+        }
+        let thread_linker = max_threads.map(|_| linker.clone());
 
         // Get the `main` module, or the last one if `main` doesn't exist
         let (main_name, main) = modules.get("main").map(|x| ("main", x)).unwrap_or_else(|| {
@@ -178,61 +731,32 @@ impl Plugin {
             (entry.0.as_str(), entry.1)
         });
 
-        // Define PDK functions
-        macro_rules! define_funcs {
-            ($m:expr, { $($name:ident($($args:expr),*) $(-> $($r:expr),*)?);* $(;)?}) => {
-                match $m {
-                $(
-                    concat!("extism_", stringify!($name)) => {
-                        let t = FuncType::new([$($args),*], [$($($r),*)?]);
-                        linker.func_new(EXPORT_MODULE_NAME, concat!("extism_", stringify!($name)), t, pdk::$name)?;
-                        continue
-                    }
-                )*
-                    _ => ()
-                }
-            };
-        }
+        // Link modules, this needs to be done per-store
+        link_modules(&mut linker, &mut store, &modules, &module_config, main_name)?;
 
-        // Add builtins
-        for (name, module) in modules.iter() {
-            if name != main_name {
-                linker.module(&mut store, name, module)?;
-            }
-            for import in module.imports() {
-                let module_name = import.module();
-                let name = import.name();
-                use wasmtime::ValType::*;
-
-                if module_name == EXPORT_MODULE_NAME {
-                    define_funcs!(name,  {
-                        config_get(I64) -> I64;
-                        var_get(I64) -> I64;
-                        var_set(I64, I64);
-                        http_request(I64, I64) -> I64;
-                        http_status_code() -> I32;
-                        log_warn(I64);
-                        log_info(I64);
-                        log_debug(I64);
-                        log_error(I64);
-                    });
-                }
-            }
-        }
-
-        for f in &mut imports {
-            let name = f.name().to_string();
-            let ns = f.namespace().unwrap_or(EXPORT_MODULE_NAME);
-            linker.func_new(ns, &name, f.ty().clone(), unsafe {
-                &*std::sync::Arc::as_ptr(&f.f)
-            })?;
+        let kernel = Kernel::new(&linker, &mut store)?;
+        if let (Some(max), Some(thread_linker)) = (max_threads, thread_linker) {
+            let threads = Threads::new(
+                &engine,
+                max,
+                thread_linker,
+                modules.clone(),
+                module_config.clone(),
+                main_name,
+                with_wasi,
+                interrupted.clone(),
+            )?;
+            threads.define_memory(&mut linker, &store)?;
+            store.data_mut().threads = Some(std::sync::Arc::new(threads));
         }
-
         let instance_pre = linker.instantiate_pre(main)?;
         let id = uuid::Uuid::new_v4();
+        store.data_mut().id = id;
+        trace!("Plugin {id}: available pages: {available_pages:?}");
         let timer_tx = Timer::tx();
         let mut plugin = Plugin {
             modules,
+            module_config,
             linker,
             instance: std::sync::Arc::new(std::sync::Mutex::new(None)),
             instance_pre,
@@ -240,16 +764,33 @@ impl Plugin {
             runtime: None,
             id,
             timer_tx: timer_tx.clone(),
-            cancel_handle: CancelHandle { id, timer_tx },
+            cancel_handle: CancelHandle {
+                id,
+                timer_tx,
+                interrupted: None,
+            },
+            interrupted,
+            ticker: None,
+            stateless: false,
+            pooling: config.pooling.is_some(),
+            trapped: false,
+            exports: BTreeMap::new(),
+            kernel,
             instantiations: 0,
             output: Output::default(),
-            _functions: imports.collect(),
+            snapshot: None,
+            state: Default::default(),
+            fuel_limit: None,
+            last_call_fuel: None,
+            peak_pages: 0,
+            last_call_stats: None,
+            _functions: imports,
             needs_reset: false,
         };
 
         plugin.current_plugin_mut().store = &mut plugin.store;
         plugin.current_plugin_mut().linker = &mut plugin.linker;
-        if available_pages.is_some() {
+        if plugin.current_plugin().memory_limiter.is_some() {
             plugin
                 .store
                 .limiter(|internal| internal.memory_limiter.as_mut().unwrap());
@@ -262,48 +803,99 @@ impl Plugin {
         &mut self,
         instance_lock: &mut std::sync::MutexGuard<Option<Instance>>,
     ) -> Result<(), Error> {
-        if self.instantiations > 100 {
-            let engine = self.store.engine().clone();
-            let internal = self.current_plugin_mut();
-            self.store = Store::new(
-                &engine,
-                CurrentPlugin::new(
-                    internal.manifest.clone(),
-                    internal.wasi.is_some(),
-                    internal.available_pages,
-                )?,
-            );
+        // Instances aren't freed until the store is dropped, so a new store is needed for every
+        // re-instantiation when the number of instances is limited
+        let limit_instances = self
+            .current_plugin()
+            .manifest
+            .memory
+            .max_instances
+            .is_some();
+        if self.instantiations > 100
+            || ((limit_instances || self.pooling) && self.instantiations > 0)
+        {
+            self.new_store()?;
+        }
 
-            self.store.set_epoch_deadline(1);
-            let store = &mut self.store as *mut _;
-            let linker = &mut self.linker as *mut _;
-            let current_plugin = self.current_plugin_mut();
-            current_plugin.store = store;
-            current_plugin.linker = linker;
-            if current_plugin.available_pages.is_some() {
-                self.store
-                    .limiter(|internal| internal.memory_limiter.as_mut().unwrap());
-            }
+        **instance_lock = None;
+        Ok(())
+    }
 
-            let (main_name, main) = self
-                .modules
-                .get("main")
-                .map(|x| ("main", x))
-                .unwrap_or_else(|| {
-                    let entry = self.modules.iter().last().unwrap();
-                    (entry.0.as_str(), entry.1)
-                });
-
-            for (name, module) in self.modules.iter() {
-                if name != main_name {
-                    self.linker.module(&mut self.store, name, module)?;
-                }
+    // Replace the store with a new one, the modules are linked again but not recompiled. This drops all
+    // instances, along with plugin variables and WASI state
+    fn new_store(&mut self) -> Result<(), Error> {
+        let engine = self.store.engine().clone();
+        let internal = self.current_plugin_mut();
+        let threads = match &internal.threads {
+            Some(t) => {
+                t.stop(&engine);
+                Some(t.with_new_memory(&engine)?)
             }
-            self.instantiations = 0;
-            self.instance_pre = self.linker.instantiate_pre(main)?;
+            None => None,
+        };
+        self.store = Store::new(
+            &engine,
+            CurrentPlugin::new(
+                internal.manifest.clone(),
+                internal.policy.clone(),
+                internal.wasi.is_some() || internal.wasi_preview2.is_some(),
+                internal.available_pages,
+            )?,
+        );
+        self.store.data_mut().id = self.id;
+
+        set_epoch_deadline_callback(&mut self.store, self.interrupted.clone());
+        let fuel = self.idle_fuel();
+        set_fuel(&mut self.store, fuel);
+        let store = &mut self.store as *mut _;
+        let linker = &mut self.linker as *mut _;
+        let current_plugin = self.current_plugin_mut();
+        current_plugin.store = store;
+        current_plugin.linker = linker;
+        if current_plugin.memory_limiter.is_some() {
+            self.store
+                .limiter(|internal| internal.memory_limiter.as_mut().unwrap());
         }
 
-        **instance_lock = None;
+        let (main_name, main) = self
+            .modules
+            .get("main")
+            .map(|x| ("main", x))
+            .unwrap_or_else(|| {
+                let entry = self.modules.iter().last().unwrap();
+                (entry.0.as_str(), entry.1)
+            });
+
+        link_modules(
+            &mut self.linker,
+            &mut self.store,
+            &self.modules,
+            &self.module_config,
+            main_name,
+        )?;
+        self.kernel = Kernel::new(&self.linker, &mut self.store)?;
+        if let Some(threads) = threads {
+            threads.define_memory(&mut self.linker, &self.store)?;
+            self.store.data_mut().threads = Some(std::sync::Arc::new(threads));
+        }
+        self.instantiations = 0;
+        self.trapped = false;
+        self.instance_pre = self.linker.instantiate_pre(main)?;
+        Ok(())
+    }
+
+    /// Restore the plugin to the state it was in when it was created. Guest memory, globals, plugin variables and
+    /// WASI state are dropped and the next call runs in a new instance, the compiled modules are re-used so this is
+    /// much cheaper than creating a new plugin. This can be used to isolate requests from each other in long-lived
+    /// hosts.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        self.new_store()?;
+        *lock = None;
+        self.exports.clear();
+        self.output = Output::default();
+        self.needs_reset = false;
         Ok(())
     }
 
@@ -313,85 +905,457 @@ impl Plugin {
         &mut self,
         instance_lock: &mut std::sync::MutexGuard<Option<Instance>>,
     ) -> Result<(), Error> {
+        self.update_internal_pointers();
         if instance_lock.is_some() {
             return Ok(());
         }
 
         let instance = self.instance_pre.instantiate(&mut self.store)?;
-        trace!("Plugin::instance is none, instantiating");
+        trace!("Plugin {}: instance is none, instantiating", self.id);
         **instance_lock = Some(instance);
+        self.exports.clear();
+        self.current_plugin_mut().instance = Some(instance);
+        self.current_plugin_mut().memories.clear();
         self.instantiations += 1;
         if let Some(limiter) = &mut self.current_plugin_mut().memory_limiter {
             limiter.reset();
         }
         self.detect_guest_runtime(instance_lock);
-        self.initialize_guest_runtime()?;
+        let res = match &self.snapshot {
+            Some(snapshot) => snapshot.restore(&mut self.store, instance),
+            None => self.initialize_guest_runtime(),
+        };
+
+        // The instance is dropped when initialization fails, so it's retried before the next call instead of
+        // running the call against a partially initialized instance
+        if res.is_err() {
+            **instance_lock = None;
+        }
+        res
+    }
+
+    // Instantiate the plugin ahead of the first call
+    pub(crate) fn preinstantiate(&mut self) -> Result<(), Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        self.instantiate(&mut lock)
+    }
+
+    /// Instantiate the plugin and run its initialization functions ahead of the first call, so the first request
+    /// doesn't pay for it. When `call` is set that function is also called with the given input and the output is
+    /// discarded, this can be used to fill caches inside the plugin. Stateless plugins start each call with a new
+    /// instance, so only the compiled modules benefit from warming them up.
+    pub fn warm_up(&mut self, call: Option<(&str, &[u8])>) -> Result<WarmUpStats, Error> {
+        let start = std::time::Instant::now();
+        self.preinstantiate()?;
+        let instantiate = start.elapsed();
+
+        let call = match call {
+            Some((name, input)) => {
+                let start = std::time::Instant::now();
+                self.call_bytes(name, input)?;
+                Some(start.elapsed())
+            }
+            None => None,
+        };
+
+        let stats = WarmUpStats { instantiate, call };
+        debug!("Plugin {} warmed up in {:?}", self.id, stats.total());
+        Ok(stats)
+    }
+
+    /// Create a `Snapshot` of a new instance of the plugin after initialization. If `init` is set then that
+    /// function is also called before the snapshot is taken, this can be used to perform expensive setup
+    /// once and re-use the results in every plugin created with `PluginBuilder::with_snapshot`.
+    pub fn snapshot(&mut self, init: Option<&str>) -> Result<Snapshot, Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+
+        // Start from a new instance so the snapshot only includes the effects of initialization
+        self.reset_store(&mut lock)?;
+        match init {
+            Some(name) => {
+                self.raw_call(&mut lock, name, b"").map_err(|e| e.0)?;
+            }
+            None => self.instantiate(&mut lock)?,
+        }
+
+        match *lock {
+            Some(instance) => Snapshot::capture(&mut self.store, instance),
+            None => anyhow::bail!("Plugin is not instantiated"),
+        }
+    }
+
+    /// Export the plugin variables and the memory regions declared using `PluginBuilder::with_state_region`
+    pub fn export_state(&mut self) -> Result<PluginState, Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+
+        let mut regions = vec![];
+        if !self.state.regions.is_empty() {
+            self.instantiate(&mut lock)?;
+            if let Some(state) = self.state.pending.take() {
+                self.apply_state(&mut lock, state).map_err(|e| e.0)?;
+            }
+
+            let instance = match *lock {
+                Some(x) => x,
+                None => anyhow::bail!("Plugin is not instantiated"),
+            };
+            regions = state::read_regions(&mut self.store, instance, &self.state.regions)?;
+        }
+
+        let vars = self
+            .current_plugin()
+            .vars
+            .iter()
+            .map(|(k, v)| (k.clone(), v.to_vec()))
+            .collect();
+        Ok(PluginState {
+            format: state::STATE_FORMAT_VERSION,
+            version: self.state.version,
+            vars,
+            regions,
+        })
+    }
+
+    /// Import state created using `Plugin::export_state`, the plugin variables are replaced immediately and the
+    /// memory regions are restored on the next call. If the state was exported by a plugin with a different state
+    /// version, the plugin must export a `migrate` function instead: it's called with the encoded `PluginState` as
+    /// input before the next call, and the memory regions are not restored.
+    pub fn import_state(&mut self, state: PluginState) -> Result<(), Error> {
+        if state.version != self.state.version && !self.function_exists(state::MIGRATE_FUNCTION) {
+            anyhow::bail!(
+                "Unable to import state version {} into plugin with state version {}, the plugin doesn't export `{}`",
+                state.version,
+                self.state.version,
+                state::MIGRATE_FUNCTION
+            );
+        }
+
+        self.current_plugin_mut().vars = state
+            .vars
+            .iter()
+            .map(|(k, v)| (k.clone(), Bytes::from(v.clone())))
+            .collect();
+        self.state.pending = Some(state);
         Ok(())
     }
 
+    // Apply imported state to a new instance, if the state versions don't match the plugin's `migrate` function
+    // is called instead. When migration fails the state is kept, so it's retried on the next call.
+    fn apply_state(
+        &mut self,
+        lock: &mut std::sync::MutexGuard<Option<Instance>>,
+        state: PluginState,
+    ) -> Result<(), (Error, i32)> {
+        if state.version == self.state.version {
+            let instance = match **lock {
+                Some(x) => x,
+                None => return Err((Error::msg("Plugin is not instantiated"), -1)),
+            };
+            return state::write_regions(&mut self.store, instance, &state.regions)
+                .map_err(|e| (e, -1));
+        }
+
+        trace!(
+            "Migrating plugin {} from state version {} to {}",
+            self.id,
+            state.version,
+            self.state.version
+        );
+        let data = state.encode().map_err(|e| (e, -1))?;
+        let res = self.raw_call_inner(
+            lock,
+            state::MIGRATE_FUNCTION,
+            Input::Bytes(&data),
+            None,
+            None,
+        );
+        let res = match res {
+            Ok(_) => match self.current_plugin_mut().get_error() {
+                Some(e) => Err((anyhow::format_err!("Migration failed: {e}"), -1)),
+                None => Ok(()),
+            },
+            Err(e) => Err(e),
+        };
+
+        if res.is_err() {
+            self.state.pending = Some(state);
+        }
+        res
+    }
+
     /// Get an exported function by name
     pub(crate) fn get_func(
         &mut self,
         instance_lock: &mut std::sync::MutexGuard<Option<Instance>>,
         function: impl AsRef<str>,
     ) -> Option<Func> {
-        if let Some(instance) = &mut **instance_lock {
-            instance.get_func(&mut self.store, function.as_ref())
-        } else {
-            None
+        self.get_export(instance_lock, function.as_ref())
+            .map(|(f, _)| f)
+    }
+
+    // Get an exported function and the number of results it returns, the lookup is cached until the
+    // plugin is re-instantiated
+    fn get_export(
+        &mut self,
+        instance_lock: &mut std::sync::MutexGuard<Option<Instance>>,
+        function: &str,
+    ) -> Option<(Func, usize)> {
+        if let Some(export) = self.exports.get(function) {
+            return Some(*export);
         }
+
+        let instance = (**instance_lock).as_ref()?;
+        let f = match instance.get_func(&mut self.store, function) {
+            Some(f) => f,
+            None => {
+                // `module::function` calls an export of one of the other linked modules
+                let (module, name) = function.split_once("::")?;
+                if module == "main"
+                    || module == EXPORT_MODULE_NAME
+                    || !self.modules.contains_key(module)
+                {
+                    return None;
+                }
+                self.linker
+                    .get(&mut self.store, module, name)?
+                    .into_func()?
+            }
+        };
+        let n_results = f.ty(&self.store).results().len();
+        self.exports.insert(function.to_string(), (f, n_results));
+        Some((f, n_results))
     }
 
-    /// Returns `true` if the given function exists, otherwise `false`
+    /// Returns `true` if the given function exists, otherwise `false`. Functions exported by other linked modules can
+    /// be referred to using `module::function`
     pub fn function_exists(&mut self, function: impl AsRef<str>) -> bool {
+        let function = function.as_ref();
+        let is_func = |module: &Module, name| {
+            module
+                .get_export(name)
+                .map(|x| x.func().is_some())
+                .unwrap_or(false)
+        };
+
+        if is_func(&self.modules["main"], function) {
+            return true;
+        }
+
+        match function.split_once("::") {
+            Some((module, name)) if module != "main" && module != EXPORT_MODULE_NAME => self
+                .modules
+                .get(module)
+                .map(|m| is_func(m, name))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Returns the names of all functions exported by the main module
+    pub fn function_names(&self) -> Vec<String> {
         self.modules["main"]
-            .get_export(function.as_ref())
-            .map(|x| x.func().is_some())
-            .unwrap_or(false)
+            .exports()
+            .filter(|x| x.ty().func().is_some())
+            .map(|x| x.name().to_string())
+            .collect()
     }
 
-    // Store input in memory and re-initialize `Internal` pointer
-    pub(crate) fn set_input(&mut self, input: *const u8, mut len: usize) -> Result<(), Error> {
-        self.output = Output::default();
-        self.clear_error();
+    /// Returns the name and signature of every function exported by the main module, in the order they're
+    /// exported. This can be used to discover which extension points a plugin implements.
+    pub fn functions(&self) -> Vec<FunctionInfo> {
+        self.modules["main"]
+            .exports()
+            .filter_map(|x| {
+                let ty = x.ty().func()?.clone();
+                let params: Vec<ValType> = ty.params().map(ValType::from).collect();
+                let results: Vec<ValType> = ty.results().map(ValType::from).collect();
+                let extism = params.is_empty() && (results.is_empty() || results == [ValType::I32]);
+                Some(FunctionInfo {
+                    name: x.name().to_string(),
+                    params,
+                    results,
+                    extism,
+                })
+            })
+            .collect()
+    }
 
-        if input.is_null() {
-            len = 0;
-        }
+    /// Returns everything imported by the plugin's modules, including host functions and WASI imports. Modules are
+    /// listed in name order, imports are listed in the order they're declared.
+    pub fn imports(&self) -> Vec<ImportInfo> {
+        self.modules
+            .iter()
+            .flat_map(|(name, module)| module.imports().map(move |x| ImportInfo::new(name, &x)))
+            .collect()
+    }
 
-        {
-            let store = &mut self.store as *mut _;
-            let linker = &mut self.linker as *mut _;
-            let current_plugin = self.current_plugin_mut();
-            current_plugin.store = store;
-            current_plugin.linker = linker;
+    /// Returns the current memory usage of the plugin. This can be used to monitor memory pressure and decide when a
+    /// plugin should be reset or replaced, since WebAssembly memory never shrinks while an instance is alive.
+    pub fn memory_stats(&mut self) -> MemoryStats {
+        let lock = self.instance.clone();
+        let lock = lock.lock().unwrap();
+        let (pages, extism_pages) = self.memory_pages(&lock);
+        self.peak_pages = self.peak_pages.max(pages + extism_pages);
+        let (allocations, allocated_bytes) = self.kernel_allocations();
+        let internal = self.current_plugin();
+        MemoryStats {
+            pages,
+            extism_pages,
+            peak_pages: self.peak_pages,
+            remaining_bytes: internal
+                .available_pages
+                .and(internal.memory_limiter.as_ref())
+                .map(|x| x.remaining()),
+            allocations,
+            allocated_bytes,
         }
+    }
 
-        let bytes = unsafe { std::slice::from_raw_parts(input, len) };
-        trace!("Input size: {}", bytes.len());
+    // Current size of the main module's memories and the Extism memory in pages
+    fn memory_pages(
+        &mut self,
+        instance_lock: &std::sync::MutexGuard<Option<Instance>>,
+    ) -> (u32, u32) {
+        let memories: Vec<Memory> = match **instance_lock {
+            Some(instance) => instance
+                .exports(&mut self.store)
+                .filter_map(|x| x.into_memory())
+                .collect(),
+            None => vec![],
+        };
+        let pages = memories.iter().map(|x| x.size(&self.store) as u32).sum();
+        let extism_pages = self
+            .kernel_memory()
+            .map_or(0, |x| x.size(&self.store) as u32);
+        (pages, extism_pages)
+    }
 
-        if let Some(f) = self.linker.get(&mut self.store, "env", "extism_reset") {
-            f.into_func().unwrap().call(&mut self.store, &[], &mut [])?;
-        } else {
-            error!("Call to extism_reset failed");
-        }
+    fn kernel_memory(&mut self) -> Option<Memory> {
+        self.current_plugin().kernel_memory
+    }
 
-        let handle = self.current_plugin_mut().memory_new(bytes)?;
+    // Count the active blocks in the kernel allocator by walking its block list, see `MemoryRoot` in the kernel for
+    // the layout. The kernel claims the first page past its initial memory when it's first used, before that there
+    // are no allocations.
+    fn kernel_allocations(&mut self) -> (usize, usize) {
+        const ROOT_SIZE: usize = 16;
+        const BLOCK_SIZE: usize = 12;
+        const ACTIVE: u8 = 1;
+
+        let start_page = self.modules[EXPORT_MODULE_NAME]
+            .exports()
+            .find_map(|x| x.ty().memory().map(|m| m.minimum()))
+            .unwrap_or_default() as usize;
+        let Some(memory) = self.kernel_memory() else {
+            return (0, 0);
+        };
+        let data = memory.data(&self.store);
+        let root = start_page * 65536;
+        if data.len() < root + ROOT_SIZE {
+            return (0, 0);
+        }
 
-        if let Some(f) = self.linker.get(&mut self.store, "env", "extism_input_set") {
-            f.into_func().unwrap().call(
-                &mut self.store,
-                &[Val::I64(handle.offset() as i64), Val::I64(len as i64)],
-                &mut [],
-            )?;
+        let read_u32 = |offs: usize| {
+            data.get(offs..offs + 4)
+                .map_or(0, |x| u32::from_le_bytes(x.try_into().unwrap()) as usize)
+        };
+        let position = u64::from_le_bytes(data[root..root + 8].try_into().unwrap()) as usize;
+        let blocks = root + ROOT_SIZE;
+        let end = (blocks + position).min(data.len());
+
+        let (mut count, mut bytes) = (0, 0);
+        let mut block = blocks;
+        while block + BLOCK_SIZE <= end {
+            let size = read_u32(block + 4);
+            if data[block] == ACTIVE {
+                count += 1;
+                bytes += read_u32(block + 8);
+            }
+            block += BLOCK_SIZE + size;
         }
+        (count, bytes)
+    }
+
+    // Point `CurrentPlugin` at this plugin's store and linker, these are raw pointers so they need to be updated
+    // after the plugin has been moved
+    fn update_internal_pointers(&mut self) {
+        let store = &mut self.store as *mut _;
+        let linker = &mut self.linker as *mut _;
+        let current_plugin = self.current_plugin_mut();
+        current_plugin.store = store;
+        current_plugin.linker = linker;
+    }
+
+    // Store input in memory and re-initialize `Internal` pointer
+    pub(crate) fn set_input(&mut self, input: Input) -> Result<(), Error> {
+        self.output = Output::default();
+        self.clear_error();
+        self.update_internal_pointers();
+
+        self.kernel.reset.call(&mut self.store, &[], &mut [])?;
+
+        let (offset, len) = match input {
+            Input::Bytes(bytes) => {
+                trace!("Plugin {}: input size: {}", self.id, bytes.len());
+
+                // The input is copied straight from the caller's buffer into plugin memory, this is the only copy
+                let handle = self.current_plugin_mut().memory_alloc(bytes.len() as u64)?;
+                self.current_plugin_mut()
+                    .memory_bytes(handle)?
+                    .copy_from_slice(bytes);
+                (handle.offset(), bytes.len())
+            }
+            Input::Reader(reader) => {
+                let (offset, len) = self.read_input(reader)?;
+                trace!("Plugin {}: input size: {}", self.id, len);
+                (offset, len)
+            }
+        };
+
+        self.kernel.input_set.call(
+            &mut self.store,
+            &[Val::I64(offset as i64), Val::I64(len as i64)],
+            &mut [],
+        )?;
 
         Ok(())
     }
 
+    // Read input from `reader` directly into plugin memory, growing the block as needed. Returns the offset and length
+    // of the input.
+    fn read_input(&mut self, reader: &mut dyn std::io::Read) -> Result<(u64, usize), Error> {
+        let plugin = self.current_plugin_mut();
+        let mut handle = plugin.memory_alloc(INPUT_CHUNK_SIZE as u64)?;
+        let mut len = 0;
+        loop {
+            if len == handle.len() {
+                // The block is full, move the input into a block twice the size
+                let next = plugin.memory_alloc(handle.len() as u64 * 2)?;
+                let src = plugin.memory_bytes(handle)?.as_ptr();
+                let dest = plugin.memory_bytes(next)?;
+                unsafe { std::ptr::copy_nonoverlapping(src, dest.as_mut_ptr(), len) };
+                plugin.memory_free(handle)?;
+                handle = next;
+            }
+
+            let buf = &mut plugin.memory_bytes(handle)?[len..];
+            match reader.read(buf) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::new(e).context("Unable to read plugin input")),
+            }
+        }
+        Ok((handle.offset(), len))
+    }
+
     /// Determine if wasi is enabled
     pub fn has_wasi(&self) -> bool {
-        self.current_plugin().wasi.is_some()
+        let plugin = self.current_plugin();
+        plugin.wasi.is_some() || plugin.wasi_preview2.is_some()
     }
 
     // Do a best-effort attempt to detect any guest runtime.
@@ -406,12 +1370,13 @@ impl Plugin {
             let reactor_init = if let Some(init) = self.get_func(instance_lock, "_initialize") {
                 if init.typed::<(), ()>(&self.store()).is_err() {
                     trace!(
-                        "_initialize function found with type {:?}",
+                        "Plugin {}: _initialize function found with type {:?}",
+                        self.id,
                         init.ty(self.store())
                     );
                     None
                 } else {
-                    trace!("WASI reactor module detected");
+                    trace!("Plugin {}: WASI reactor module detected", self.id);
                     Some(init)
                 }
             } else {
@@ -421,42 +1386,43 @@ impl Plugin {
             return;
         }
 
-        // Check for `__wasm_call_ctors` or `_initialize`, this is used by WASI to
-        // initialize certain interfaces.
-        let init = if let Some(init) = self.get_func(instance_lock, "__wasm_call_ctors") {
-            if init.typed::<(), ()>(&self.store()).is_err() {
-                trace!(
-                    "__wasm_call_ctors function found with type {:?}",
-                    init.ty(self.store())
-                );
-                return;
-            }
-            trace!("WASI runtime detected");
-            init
-        } else if let Some(init) = self.get_func(instance_lock, "_initialize") {
-            if init.typed::<(), ()>(&self.store()).is_err() {
-                trace!(
-                    "_initialize function found with type {:?}",
-                    init.ty(self.store())
-                );
-                return;
-            }
-            trace!("Reactor module detected");
-            init
+        // Reactors are initialized using `_initialize`, which runs the global constructors itself.
+        // `__wasm_call_ctors` is only used when there's no `_initialize`, and never for commands since
+        // `_start` runs the constructors
+        self.runtime = None;
+        let (name, init) = if let Some(init) = self.get_func(instance_lock, "_initialize") {
+            ("_initialize", init)
+        } else if self.get_func(instance_lock, "_start").is_some() {
+            trace!("Plugin {}: command module detected", self.id);
+            return;
+        } else if let Some(init) = self.get_func(instance_lock, "__wasm_call_ctors") {
+            ("__wasm_call_ctors", init)
         } else {
+            trace!("Plugin {}: no runtime detected", self.id);
             return;
         };
 
-        self.runtime = Some(GuestRuntime::Wasi { init });
+        if init.typed::<(), ()>(&self.store()).is_err() {
+            trace!(
+                "Plugin {}: {name} function found with type {:?}",
+                self.id,
+                init.ty(self.store())
+            );
+            return;
+        }
 
-        trace!("No runtime detected");
+        trace!(
+            "Plugin {}: WASI runtime detected, initializing with {name}",
+            self.id
+        );
+        self.runtime = Some(GuestRuntime::Wasi { init });
     }
 
     // Initialize the guest runtime
     pub(crate) fn initialize_guest_runtime(&mut self) -> Result<(), Error> {
         let mut store = &mut self.store;
         if let Some(runtime) = &self.runtime {
-            trace!("Plugin::initialize_runtime");
+            trace!("Plugin {}: initializing runtime", self.id);
             match runtime {
                 GuestRuntime::Haskell { init, reactor_init } => {
                     if let Some(reactor_init) = reactor_init {
@@ -468,11 +1434,11 @@ impl Plugin {
                         &[Val::I32(0), Val::I32(0)],
                         results.as_mut_slice(),
                     )?;
-                    debug!("Initialized Haskell language runtime");
+                    debug!("Plugin {}: initialized Haskell language runtime", self.id);
                 }
                 GuestRuntime::Wasi { init } => {
                     init.call(&mut store, &[], &mut [])?;
-                    debug!("Initialied WASI runtime");
+                    debug!("Plugin {}: initialized WASI runtime", self.id);
                 }
             }
         }
@@ -484,20 +1450,13 @@ impl Plugin {
     fn output_memory_position(&mut self) -> (u64, u64) {
         let out = &mut [Val::I64(0)];
         let out_len = &mut [Val::I64(0)];
-        let mut store = &mut self.store;
-        self.linker
-            .get(&mut store, "env", "extism_output_offset")
-            .unwrap()
-            .into_func()
-            .unwrap()
-            .call(&mut store, &[], out)
+        self.kernel
+            .output_offset
+            .call(&mut self.store, &[], out)
             .unwrap();
-        self.linker
-            .get(&mut store, "env", "extism_output_length")
-            .unwrap()
-            .into_func()
-            .unwrap()
-            .call(&mut store, &[], out_len)
+        self.kernel
+            .output_length
+            .call(&mut self.store, &[], out_len)
             .unwrap();
 
         let offs = out[0].unwrap_i64() as u64;
@@ -507,7 +1466,7 @@ impl Plugin {
 
     // Get the output data after a call has returned
     fn output<'a, T: FromBytes<'a>>(&'a mut self) -> Result<T, Error> {
-        trace!("Output offset: {}", self.output.offset);
+        trace!("Plugin {}: output offset: {}", self.id, self.output.offset);
         let offs = self.output.offset;
         let len = self.output.length;
         T::from_bytes(
@@ -527,69 +1486,203 @@ impl Plugin {
         self.output.error_length = err.1;
     }
 
+    // Use the epoch ticker for timeouts and cancellation instead of the timer thread
+    pub(crate) fn use_epoch_ticker(&mut self) {
+        self.ticker = Some(TickerGuard::register(self.store.engine()));
+        self.cancel_handle.interrupted = Some(self.interrupted.clone());
+    }
+
+    // Arm the timer thread, execution will be interrupted once `duration` has elapsed or the
+    // plugin is cancelled
+    fn start_timer(&mut self, duration: Option<std::time::Duration>) {
+        use std::sync::atomic::Ordering;
+
+        self.interrupted.store(false, Ordering::SeqCst);
+        // Threads spawned during the call inherit the deadline
+        self.current_plugin_mut().deadline = duration.map(|x| std::time::Instant::now() + x);
+        if self.ticker.is_some() {
+            return;
+        }
+
+        self.timer_tx
+            .send(TimerAction::Start {
+                id: self.id,
+                engine: self.store.engine().clone(),
+                duration,
+                interrupted: self.interrupted.clone(),
+            })
+            .unwrap();
+    }
+
+    // Disarm the timer thread after a call has completed
+    fn stop_timer(&mut self) {
+        self.current_plugin_mut().deadline = None;
+        if self.ticker.is_some() {
+            self.interrupted
+                .store(false, std::sync::atomic::Ordering::SeqCst);
+            return;
+        }
+
+        self.timer_tx
+            .send(TimerAction::Stop { id: self.id })
+            .unwrap();
+        self.interrupted
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+
     // Implements the build of the `call` function, `raw_call` is also used in the SDK
-    // code
+    // code. Any error returned will have an `ErrorContext` attached.
     pub(crate) fn raw_call(
         &mut self,
         lock: &mut std::sync::MutexGuard<Option<Instance>>,
         name: impl AsRef<str>,
         input: impl AsRef<[u8]>,
     ) -> Result<i32, (Error, i32)> {
+        self.raw_call_input(lock, name, Input::Bytes(input.as_ref()), None, None)
+            .map(|_| 0)
+    }
+
+    fn raw_call_input(
+        &mut self,
+        lock: &mut std::sync::MutexGuard<Option<Instance>>,
+        name: impl AsRef<str>,
+        input: Input,
+        sink: Option<&mut OutputSink>,
+        params: Option<&[Val]>,
+    ) -> Result<Vec<Val>, (Error, i32)> {
         let name = name.as_ref();
-        let input = input.as_ref();
+        let start = std::time::Instant::now();
+        self.raw_call_inner(lock, name, input, sink, params)
+            .map_err(|(e, rc)| {
+                let ctx = ErrorContext {
+                    plugin_id: self.id,
+                    function: name.to_string(),
+                    source: error::manifest_source(&self.current_plugin().manifest),
+                    elapsed: start.elapsed(),
+                };
+                (e.context(ctx), rc)
+            })
+    }
+
+    fn raw_call_inner(
+        &mut self,
+        lock: &mut std::sync::MutexGuard<Option<Instance>>,
+        name: &str,
+        input: Input,
+        sink: Option<&mut OutputSink>,
+        params: Option<&[Val]>,
+    ) -> Result<Vec<Val>, (Error, i32)> {
+        let start = std::time::Instant::now();
+        self.last_call_stats = None;
 
         if self.needs_reset {
             if let Err(e) = self.reset_store(lock) {
-                error!("Call to Plugin::reset_store failed: {e:?}");
+                error!(
+                    "Plugin {}: call to Plugin::reset_store failed: {e:?}",
+                    self.id
+                );
             }
             self.needs_reset = false;
         }
 
+        if self.stateless && lock.is_some() {
+            self.new_store().map_err(|e| (e, -1))?;
+            **lock = None;
+        }
+
         self.instantiate(lock).map_err(|e| (e, -1))?;
 
-        self.set_input(input.as_ptr(), input.len())
-            .map_err(|x| (x, -1))?;
+        if let Some(state) = self.state.pending.take() {
+            self.apply_state(lock, state)?;
+        }
+
+        self.set_input(input).map_err(|x| (x, -1))?;
 
-        let func = match self.get_func(lock, name) {
+        let (func, n_results) = match self.get_export(lock, name) {
             Some(x) => x,
             None => return Err((anyhow::anyhow!("Function not found: {name}"), -1)),
         };
 
-        // Check the number of results, reject functions with more than 1 result
-        let n_results = func.ty(self.store()).results().len();
-        if n_results > 1 {
-            return Err((
-                anyhow::anyhow!("Function {name} has {n_results} results, expected 0 or 1"),
-                -1,
-            ));
-        }
+        let params = match params {
+            // Check the number of results, reject functions with more than 1 result
+            None if n_results > 1 => {
+                return Err((
+                    anyhow::anyhow!("Function {name} has {n_results} results, expected 0 or 1"),
+                    -1,
+                ));
+            }
+            None => &[][..],
+
+            // Raw calls pass their params straight through, the types are checked here so a mismatch doesn't
+            // count as a trap
+            Some(params) => {
+                let ty = func.ty(&self.store);
+                if !ty.params().eq(params.iter().map(Val::ty)) {
+                    return Err((
+                        anyhow::anyhow!(
+                            "Function {name} expects params {:?}, got {:?}",
+                            ty.params().collect::<Vec<_>>(),
+                            params.iter().map(Val::ty).collect::<Vec<_>>()
+                        ),
+                        -1,
+                    ));
+                }
+                params
+            }
+        };
 
         // Start timer
-        self.timer_tx
-            .send(TimerAction::Start {
-                id: self.id,
-                engine: self.store.engine().clone(),
-                duration: self
-                    .current_plugin()
-                    .manifest
-                    .timeout_ms
-                    .map(std::time::Duration::from_millis),
-            })
-            .unwrap();
-        self.store.epoch_deadline_callback(deadline_callback);
+        self.start_timer(
+            self.current_plugin()
+                .manifest
+                .timeout_for(name)
+                .map(std::time::Duration::from_millis),
+        );
+
+        let (pages, extism_pages) = self.memory_pages(lock);
+        let pages_start = pages + extism_pages;
+        self.current_plugin_mut().host_calls.fill(0);
+
+        // Limit the fuel available to this call
+        let fuel_start = set_fuel(&mut self.store, self.fuel_limit);
+
+        // The sink only outlives the call because it's replaced before every call
+        self.current_plugin_mut().output_sink = sink.map(|x| unsafe {
+            std::mem::transmute::<*mut OutputSink<'_>, *mut OutputSink<'static>>(x)
+        });
 
         // Call the function
         let mut results = vec![wasmtime::Val::null(); n_results];
-        let res = func.call(self.store_mut(), &[], results.as_mut_slice());
+        let res = func.call(self.store_mut(), params, &mut results);
+        self.current_plugin_mut().output_sink = None;
 
         // Stop timer
-        self.timer_tx
-            .send(TimerAction::Stop { id: self.id })
-            .unwrap();
-        self.store
-            .epoch_deadline_callback(|_| Ok(UpdateDeadline::Continue(1)));
+        self.stop_timer();
+
+        self.last_call_fuel = fuel_start
+            .zip(self.store.fuel_remaining())
+            .map(|(start, end)| start.saturating_sub(end));
+        let fuel = self.idle_fuel();
+        set_fuel(&mut self.store, fuel);
 
         self.get_output_after_call();
+        let (pages, extism_pages) = self.memory_pages(lock);
+        self.peak_pages = self.peak_pages.max(pages + extism_pages);
+        let mut host_calls = BTreeMap::new();
+        for (i, n) in self.current_plugin().host_calls.iter().enumerate() {
+            if *n > 0 {
+                *host_calls
+                    .entry(self._functions[i].name().to_string())
+                    .or_default() += n;
+            }
+        }
+        self.last_call_stats = Some(CallStats {
+            function: name.to_string(),
+            elapsed: start.elapsed(),
+            fuel: self.last_call_fuel,
+            memory_growth: (pages + extism_pages).saturating_sub(pages_start),
+            host_calls,
+        });
 
         match res {
             Ok(()) => {
@@ -597,36 +1690,43 @@ impl Plugin {
             }
             Err(e) => match e.downcast::<wasmtime_wasi::I32Exit>() {
                 Ok(exit) => {
-                    trace!("WASI return code: {}", exit.0);
+                    trace!("Plugin {}: WASI return code: {}", self.id, exit.0);
                     if exit.0 != 0 {
-                        return Err((Error::msg("WASI return code"), exit.0));
+                        let code = exit.0;
+                        return Err((Error::new(exit).context("WASI return code"), code));
                     }
-                    return Ok(0);
+                    return Ok(results);
                 }
                 Err(e) => {
+                    self.trapped = true;
+                    if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+                        return Err((Error::msg("out of fuel"), -1));
+                    }
+
                     let cause = e.root_cause().to_string();
                     if cause == "timeout" || cause == "oom" {
                         return Err((Error::msg(cause), -1));
                     }
 
-                    error!("Call: {e:?}");
+                    error!("Plugin {}: call to {name} failed: {e:?}", self.id);
                     return Err((e.context("Call failed"), -1));
                 }
             },
         };
 
-        // If `results` is empty and the return value wasn't a WASI exit code then
-        // the call succeeded
-        if results.is_empty() {
-            return Ok(0);
-        }
-
         // Return result to caller
-        Ok(0)
+        Ok(results)
     }
 
     /// Call a function by name with the given input, the return value is the output data returned by the plugin.
     /// This data will be invalidated next time the plugin is called.
+    ///
+    /// The input is encoded using `ToBytes` and then copied into plugin memory. Types that are already bytes, like
+    /// `&[u8]` and `&str`, are copied directly; types that need to be encoded, like `Json`, are first serialized into
+    /// an owned buffer. See `Plugin::call_bytes` to avoid encoding entirely.
+    ///
+    /// Functions exported by other modules in the manifest can be called using `module::function`, exports of the
+    /// main module are used first when the name is ambiguous.
     pub fn call<'a, 'b, T: ToBytes<'a>, U: FromBytes<'b>>(
         &'b mut self,
         name: impl AsRef<str>,
@@ -640,6 +1740,241 @@ impl Plugin {
             .and_then(move |_| self.output())
     }
 
+    /// Call a function by name, encoding the input and decoding the output using `ToBytes` and `FromBytes`. This
+    /// works like `Plugin::call`, except the output is owned and the error shows which step failed:
+    ///
+    /// ```rust,ignore
+    /// let Json(count) = plugin.call_typed::<_, Json<Count>>("count_vowels", "abc")?;
+    /// ```
+    pub fn call_typed<'a, I: ToBytes<'a>, O: FromBytesOwned>(
+        &mut self,
+        name: impl AsRef<str>,
+        input: I,
+    ) -> Result<O, CallError> {
+        let data = input.to_bytes().map_err(CallError::Encode)?;
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        self.raw_call(&mut lock, name, data)
+            .map_err(|e| CallError::Call(e.0))?;
+        self.output().map_err(CallError::Decode)
+    }
+
+    /// Call a function by name with `input` copied directly into plugin memory, returning the output without copying
+    /// it out of plugin memory. Plugins can't access host memory, so copying the input into the plugin is unavoidable,
+    /// but no other copies or allocations are made on the host. The output is invalidated the next time the plugin is
+    /// called.
+    pub fn call_bytes(&mut self, name: impl AsRef<str>, input: &[u8]) -> Result<&[u8], Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        self.raw_call(&mut lock, name, input).map_err(|e| e.0)?;
+        self.output()
+    }
+
+    /// Call a function by name, returning the output without copying it out of plugin memory. This is useful when the
+    /// output is parsed and dropped right away, use `Plugin::call` with an owned type like `Vec<u8>` to keep it.
+    pub fn call_ref<'a, T: ToBytes<'a>>(
+        &mut self,
+        name: impl AsRef<str>,
+        input: T,
+    ) -> Result<OutputRef<'_>, Error> {
+        let data = self.call(name, input)?;
+        Ok(OutputRef { data })
+    }
+
+    /// Call a function by name, reading the input from `input`. The input is copied into plugin memory in chunks as
+    /// it's read, so large inputs like files or sockets never need to be buffered on the host. The output is
+    /// decoded using `FromBytes` and is invalidated the next time the plugin is called.
+    pub fn call_reader<'b, U: FromBytes<'b>>(
+        &'b mut self,
+        name: impl AsRef<str>,
+        mut input: impl std::io::Read,
+    ) -> Result<U, Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        self.raw_call_input(&mut lock, name, Input::Reader(&mut input), None, None)
+            .map_err(|e| e.0)
+            .and_then(move |_| self.output())
+    }
+
+    /// Call a function by name, passing each chunk of output the plugin writes using `extism_output_write` to `sink` as
+    /// soon as it's written, so large outputs never have to fit in plugin memory. Any output set using
+    /// `extism_output_set` is passed to `sink` as the last chunk. Returning an error from `sink` stops the call.
+    pub fn call_streaming<'a, T: ToBytes<'a>>(
+        &mut self,
+        name: impl AsRef<str>,
+        input: T,
+        mut sink: impl FnMut(&[u8]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        let data = input.to_bytes()?;
+        self.raw_call_input(
+            &mut lock,
+            name,
+            Input::Bytes(data.as_ref()),
+            Some(&mut sink),
+            None,
+        )
+        .map_err(|e| e.0)?;
+
+        let output: &[u8] = self.output()?;
+        if !output.is_empty() {
+            sink(output)?;
+        }
+        Ok(())
+    }
+
+    /// Call a function that doesn't follow the Extism calling convention, passing `params` directly and returning its
+    /// results. This can be used to call exports of modules that weren't written for Extism. The plugin input is
+    /// empty during the call and any output it sets is ignored.
+    pub fn call_raw(&mut self, name: impl AsRef<str>, params: &[Val]) -> Result<Vec<Val>, Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+        self.raw_call_input(&mut lock, name, Input::Bytes(&[]), None, Some(params))
+            .map_err(|e| e.0)
+    }
+
+    /// Run a WASI command module by calling `_start` with `stdin` as its standard input, instead of using the Extism
+    /// calling convention. Arguments and environment variables are set using the manifest's WASI options. The
+    /// command runs in a new instance with its output captured, a non-zero exit code isn't treated as an error.
+    pub fn run_command(&mut self, stdin: impl Into<Vec<u8>>) -> Result<CommandOutput, Error> {
+        use wasi_common::pipe::{ReadPipe, WritePipe};
+
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+
+        // Commands always start from a fresh store, the pipes are replaced by the default stdio afterwards
+        self.new_store()?;
+        *lock = None;
+        let stdout = WritePipe::new_in_memory();
+        let stderr = WritePipe::new_in_memory();
+        let preview2 = self.current_plugin().wasi_preview2.is_some();
+        match self.current_plugin_mut().wasi.as_mut() {
+            Some(wasi) => {
+                wasi.ctx.set_stdin(Box::new(ReadPipe::from(stdin.into())));
+                wasi.ctx.set_stdout(Box::new(stdout.clone()));
+                wasi.ctx.set_stderr(Box::new(stderr.clone()));
+            }
+            None if preview2 => {
+                anyhow::bail!("Plugin::run_command isn't supported with WASI preview 2")
+            }
+            None => anyhow::bail!("WASI must be enabled to run a command"),
+        }
+
+        let res = self.raw_call(&mut lock, "_start", b"");
+        self.new_store()?;
+        *lock = None;
+        self.needs_reset = false;
+
+        let exit_code = match res {
+            Ok(_) => 0,
+            Err((e, rc)) if e.downcast_ref::<wasmtime_wasi::I32Exit>().is_some() => rc,
+            Err((e, _)) => return Err(e),
+        };
+
+        let into_vec = |pipe: WritePipe<std::io::Cursor<Vec<u8>>>| {
+            pipe.try_into_inner()
+                .map(|x| x.into_inner())
+                .unwrap_or_default()
+        };
+        Ok(CommandOutput {
+            exit_code,
+            stdout: into_vec(stdout),
+            stderr: into_vec(stderr),
+        })
+    }
+
+    /// Call a function with arbitrary input and discard the output, this is intended to be used as a fuzz target.
+    /// After a failed call the instance is dropped, so state left behind by a trap can't leak into the next
+    /// input, and a poisoned instance lock is recovered instead of panicking.
+    ///
+    /// ```rust,ignore
+    /// libfuzzer_sys::fuzz_target!(|input: &[u8]| {
+    ///     let _ = PLUGIN.lock().unwrap().call_unchecked_input("count_vowels", input);
+    /// });
+    /// ```
+    #[cfg(feature = "fuzzing")]
+    pub fn call_unchecked_input(
+        &mut self,
+        name: impl AsRef<str>,
+        input: &[u8],
+    ) -> Result<(), Error> {
+        let lock = self.instance.clone();
+        let mut lock = match lock.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        };
+        let res = self.raw_call(&mut lock, name, input).map_err(|e| e.0);
+        if res.is_err() {
+            *lock = None;
+        }
+        res.map(|_| ())
+    }
+
+    /// Check that the plugin is alive and responsive. If the plugin exports a `_health` function
+    /// it is called with an empty input and any error it reports is returned. Otherwise the plugin
+    /// is instantiated and the Extism kernel is queried, which must complete within
+    /// `HEALTH_CHECK_TIMEOUT`.
+    pub fn health_check(&mut self) -> Result<(), Error> {
+        let lock = self.instance.clone();
+        let mut lock = lock.lock().unwrap();
+
+        if self.function_exists(HEALTH_CHECK_FUNCTION) {
+            self.raw_call(&mut lock, HEALTH_CHECK_FUNCTION, b"")
+                .map_err(|e| e.0)?;
+            if let Some(e) = self.current_plugin_mut().get_error() {
+                anyhow::bail!("Health check failed: {e}");
+            }
+            return Ok(());
+        }
+
+        let start = std::time::Instant::now();
+        self.start_timer(Some(HEALTH_CHECK_TIMEOUT));
+        let res = self.instantiate(&mut lock).map(|()| {
+            // `extism_length` is a cheap round-trip through the kernel
+            self.current_plugin_mut().memory_length(0);
+        });
+        self.stop_timer();
+
+        if let Err(e) = res {
+            return Err(e.context("Health check failed"));
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed > HEALTH_CHECK_TIMEOUT {
+            anyhow::bail!("Health check failed: plugin took {elapsed:?} to respond");
+        }
+
+        trace!("Health check for plugin {} took {:?}", self.id, elapsed);
+        Ok(())
+    }
+
+    /// The fuel consumed by the most recent call, this includes calls that failed. `None` is returned when fuel
+    /// metering isn't enabled (see `PluginBuilder::with_fuel_metering`) or the plugin hasn't been called.
+    pub fn last_call_fuel(&self) -> Option<u64> {
+        self.last_call_fuel
+    }
+
+    // Fuel available outside of calls, for instantiation and the kernel. Plugins with a limit get a fixed amount
+    // instead of an unlimited one, since the fuel that was taken away to enforce the limit is added back after every
+    // call and counts towards wasmtime's total, see `set_fuel`
+    fn idle_fuel(&self) -> Option<u64> {
+        self.fuel_limit.map(|_| u32::MAX as u64)
+    }
+
+    /// Statistics for the most recent call, this includes calls that failed or timed out. `None` is returned when
+    /// the plugin hasn't been called or the last call failed before the function was run, for example when the
+    /// function doesn't exist.
+    pub fn last_call_stats(&self) -> Option<&CallStats> {
+        self.last_call_stats.as_ref()
+    }
+
+    /// A unique ID for the plugin, this is included in log messages and in the `ErrorContext` attached to errors
+    /// returned by calls
+    pub fn id(&self) -> uuid::Uuid {
+        self.id
+    }
+
     /// Get a `CancelHandle`, which can be used from another thread to cancel a running plugin
     pub fn cancel_handle(&self) -> CancelHandle {
         self.cancel_handle.clone()
@@ -647,15 +1982,10 @@ impl Plugin {
 
     pub(crate) fn clear_error(&mut self) {
         trace!("Clearing error on plugin {}", self.id);
-        let (linker, mut store) = self.linker_and_store();
-        if let Some(f) = linker.get(&mut store, "env", "extism_error_set") {
-            f.into_func()
-                .unwrap()
-                .call(&mut store, &[Val::I64(0)], &mut [])
-                .unwrap();
-        } else {
-            error!("Plugin::clear_error failed, extism_error_set not found")
-        }
+        self.kernel
+            .error_set
+            .call(&mut self.store, &[Val::I64(0)], &mut [])
+            .unwrap();
     }
 
     // A convenience method to set the plugin error and return a value
@@ -666,29 +1996,27 @@ impl Plugin {
         x: E,
     ) -> E {
         if instance_lock.is_none() {
-            error!("No instance, unable to set error: {:?}", e);
+            error!(
+                "Plugin {}: no instance, unable to set error: {:?}",
+                self.id, e
+            );
             return x;
         }
         let s = format!("{e:?}");
-        debug!("Set error: {:?}", s);
+        debug!("Plugin {}: set error: {:?}", self.id, s);
         match self.current_plugin_mut().memory_new(&s) {
             Ok(handle) => {
-                let (linker, mut store) = self.linker_and_store();
-                if let Some(f) = linker.get(&mut store, "env", "extism_error_set") {
-                    if let Ok(()) = f.into_func().unwrap().call(
-                        &mut store,
-                        &[Val::I64(handle.offset() as i64)],
-                        &mut [],
-                    ) {
-                        self.output.error_offset = handle.offset();
-                        self.output.error_length = s.len() as u64;
-                    }
-                } else {
-                    error!("extism_error_set not found");
+                if let Ok(()) = self.kernel.error_set.call(
+                    &mut self.store,
+                    &[Val::I64(handle.offset() as i64)],
+                    &mut [],
+                ) {
+                    self.output.error_offset = handle.offset();
+                    self.output.error_length = s.len() as u64;
                 }
             }
             Err(e) => {
-                error!("Unable to set error: {e:?}")
+                error!("Plugin {}: unable to set error: {e:?}", self.id)
             }
         }
         x
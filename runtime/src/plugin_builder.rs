@@ -1,15 +1,56 @@
+use crate::backend::Backend;
 use crate::*;
 
+/// Settings used to create a plugin, these are set using `PluginBuilder`
+#[derive(Clone, Default)]
+pub(crate) struct PluginOptions {
+    pub(crate) config: EngineConfig,
+
+    /// Use the shared engine from the module cache
+    pub(crate) shared: bool,
+
+    /// When no policy is given it's derived from the manifest
+    pub(crate) policy: Option<Policy>,
+
+    /// Used to decrypt encrypted modules
+    pub(crate) keys: Option<KeyProvider>,
+
+    /// The max number of threads the plugin can spawn, threads are disabled when this isn't set
+    pub(crate) max_threads: Option<u32>,
+
+    /// Replaces `Manifest::deterministic_seed`
+    pub(crate) deterministic_seed: Option<u64>,
+
+    /// Allow `Wasm::Precompiled` modules to be loaded
+    pub(crate) precompiled: bool,
+}
+
+#[derive(Clone)]
 enum Source {
-    Manifest(Manifest),
+    Manifest(Box<Manifest>),
     Data(Vec<u8>),
 }
 
 /// PluginBuilder is used to configure and create `Plugin` instances
+#[derive(Clone)]
 pub struct PluginBuilder {
     source: Source,
     wasi: bool,
     functions: Vec<Function>,
+    module_cache: bool,
+    config: EngineConfig,
+    compilation_threads: Option<usize>,
+    snapshot: Option<Snapshot>,
+    policy: Option<Policy>,
+    keys: Option<KeyProvider>,
+    state_version: u32,
+    state_regions: Vec<MemoryRegion>,
+    fuel_limit: Option<u64>,
+    epoch_ticker: bool,
+    stateless: bool,
+    max_threads: Option<u32>,
+    deterministic_seed: Option<u64>,
+    precompiled: bool,
 }
 
 impl PluginBuilder {
@@ -19,15 +60,43 @@ impl PluginBuilder {
             source: Source::Data(data.into()),
             wasi: false,
             functions: vec![],
+            module_cache: false,
+            config: EngineConfig::default(),
+            compilation_threads: None,
+            snapshot: None,
+            policy: None,
+            keys: None,
+            state_version: 0,
+            state_regions: vec![],
+            fuel_limit: None,
+            epoch_ticker: false,
+            stateless: false,
+            max_threads: None,
+            deterministic_seed: None,
+            precompiled: false,
         }
     }
 
     /// Create a new `PluginBuilder` from a `Manifest`
     pub fn new(manifest: Manifest) -> Self {
         PluginBuilder {
-            source: Source::Manifest(manifest),
+            source: Source::Manifest(Box::new(manifest)),
             wasi: false,
             functions: vec![],
+            module_cache: false,
+            config: EngineConfig::default(),
+            compilation_threads: None,
+            snapshot: None,
+            policy: None,
+            keys: None,
+            state_version: 0,
+            state_regions: vec![],
+            fuel_limit: None,
+            epoch_ticker: false,
+            stateless: false,
+            max_threads: None,
+            deterministic_seed: None,
+            precompiled: false,
         }
     }
 
@@ -37,6 +106,198 @@ impl PluginBuilder {
         self
     }
 
+    /// Use the process-wide module cache, plugins created with this option enabled share a wasmtime `Engine`
+    /// and only compile each module (identified by its SHA-256 digest) once
+    pub fn with_module_cache(mut self, enable: bool) -> Self {
+        self.module_cache = enable;
+        self
+    }
+
+    /// Cache compiled modules on disk, so plugins created by other processes with the same modules and engine
+    /// settings don't need to compile them again. This enables the module cache, see `with_module_cache`.
+    pub fn with_cache_config(mut self, cache: CacheConfig) -> Self {
+        self.config.cache = Some(cache);
+        self.module_cache = true;
+        self
+    }
+
+    /// Use wasmtime's pooling instance allocator, which makes instantiation much faster for plugins that are
+    /// instantiated often, like stateless plugins. When the pool is full, creating an instance fails until another
+    /// plugin using the same engine is dropped. Memory limits from the manifest are still enforced for each instance.
+    pub fn with_pooling_allocator(mut self, pooling: PoolingConfig) -> Self {
+        self.config.pooling = Some(pooling);
+        self
+    }
+
+    /// Enable or disable parallel compilation, this is enabled by default. Disabling it will compile each
+    /// module on the calling thread.
+    pub fn with_parallel_compilation(mut self, enable: bool) -> Self {
+        self.config.parallel_compilation = enable;
+        self
+    }
+
+    /// Limit the number of worker threads used to compile this plugin's modules, by default all available
+    /// cores are used. Plugins built with the same number of threads share a thread pool, so this can also be
+    /// used to throttle batch-loading many plugins on a shared machine.
+    pub fn with_compilation_threads(mut self, n: usize) -> Self {
+        self.compilation_threads = Some(n);
+        self
+    }
+
+    /// Set the compiler used to compile this plugin's modules, `Compiler::Winch` compiles faster but the
+    /// generated code is slower, see `Compiler`
+    pub fn with_compiler(mut self, compiler: Compiler) -> Self {
+        self.config.compiler = compiler;
+        self
+    }
+
+    /// Set the Cranelift optimization level, this overrides `Manifest::opt_level`
+    pub fn with_opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.config.opt_level = Some(opt_level);
+        self
+    }
+
+    /// Enable or disable copy-on-write memory initialization, this is enabled by default. When enabled, a
+    /// module's initial memory image is mapped into each instance and only copied when it's written to, so
+    /// instances of the same module share their read-only pages. Combine this with `with_module_cache` to
+    /// share a single image between all plugins that load the same module.
+    pub fn with_memory_init_cow(mut self, enable: bool) -> Self {
+        self.config.memory_init_cow = enable;
+        self
+    }
+
+    /// Change the wasmtime `Config` used to create the plugin's engine, `f` is called after the runtime's
+    /// settings are applied so it can override them, like enabling SIMD or threads or changing the Cranelift
+    /// optimization level. Timeouts and cancellation depend on `epoch_interruption`, so it shouldn't be disabled.
+    /// Plugins only share an engine through the module cache when they use the same function.
+    pub fn with_wasmtime_config(mut self, f: impl Fn(&mut Config) + Send + Sync + 'static) -> Self {
+        self.config
+            .configure
+            .push(engine::ConfigureFn(std::sync::Arc::new(f)));
+        self
+    }
+
+    /// Enable fuel metering, the fuel consumed by each call is returned by `Plugin::last_call_fuel`. Most
+    /// WebAssembly instructions consume one unit of fuel. Metering adds a small overhead to every call, so it's
+    /// disabled by default.
+    pub fn with_fuel_metering(mut self, enable: bool) -> Self {
+        self.config.consume_fuel = enable;
+        self
+    }
+
+    /// Limit the fuel each call can consume, calls that run out of fuel fail with an `out of fuel` error. This
+    /// enables fuel metering.
+    pub fn with_fuel_limit(mut self, fuel: u64) -> Self {
+        self.config.consume_fuel = true;
+        self.fuel_limit = Some(fuel);
+        self
+    }
+
+    /// Use a shared epoch ticker for timeouts and cancellation instead of the timer thread. A single background
+    /// thread increments the epoch of every engine used by these plugins every 10ms and each call checks its own
+    /// deadline when the epoch changes, so starting a call doesn't require any coordination with another thread.
+    /// This scales better for hosts with many plugins making short calls, timeouts are accurate to within 10ms.
+    pub fn with_epoch_ticker(mut self, enable: bool) -> Self {
+        self.epoch_ticker = enable;
+        self
+    }
+
+    /// Run every call in a new instance, so no guest memory, globals, plugin variables or WASI state are shared
+    /// between calls. Compiled modules are re-used, so the cost of each call only increases by the time it takes to
+    /// instantiate the modules, this can be reduced using `with_snapshot`. The previous instance is dropped when the
+    /// next call starts, since the output of a call is read from its instance.
+    pub fn with_stateless(mut self, enable: bool) -> Self {
+        self.stateless = enable;
+        self
+    }
+
+    /// Enable the threads proposal, so plugins compiled with `-pthread` can use shared memories, atomics and
+    /// `wasi::thread-spawn`. Each spawned thread runs in a new instance of the main module with its own store and
+    /// WASI context, only the shared memory is shared with the plugin. At most `max_threads` threads can be running
+    /// at once, `thread-spawn` returns an error to the plugin after that. Cancelling the plugin also interrupts
+    /// its threads.
+    pub fn with_threads(mut self, max_threads: u32) -> Self {
+        self.config.threads = true;
+        self.max_threads = Some(max_threads);
+        self
+    }
+
+    /// Enable the multi-memory proposal, so modules can define or import more than one memory. Memories other
+    /// than the default memory can be accessed from host functions using `CurrentPlugin::memory_index`. This is
+    /// disabled by default.
+    pub fn with_multi_memory(mut self, enable: bool) -> Self {
+        self.config.multi_memory = enable;
+        self
+    }
+
+    /// Enable the tail call proposal, which adds the `return_call` instructions used by functional languages. This
+    /// is disabled by default.
+    ///
+    /// The exception handling and GC proposals, used by toolchains like Kotlin/Wasm and wasm_of_ocaml, aren't
+    /// supported by Wasmtime 13 and modules that use them fail validation.
+    // TODO: add `with_exceptions` and `with_gc` once Wasmtime is upgraded to a version that implements them
+    pub fn with_tail_calls(mut self, enable: bool) -> Self {
+        self.config.tail_call = enable;
+        self
+    }
+
+    /// Run the plugin deterministically, so calls with the same input produce bit-identical results. NaNs are
+    /// canonicalized, WASI random data is generated from `seed`, WASI clocks are virtual and advance by 1ms each
+    /// time they're read, and HTTP requests fail. Creating the plugin fails if it uses threads (`with_threads`) or
+    /// loads wasi-nn models. This is the same as setting `Manifest::deterministic_seed`.
+    ///
+    /// Some nondeterminism is outside of the runtime's control:
+    /// - host functions are called as usual, so they need to be deterministic themselves
+    /// - `poll_oneoff` sleeps in real time, when it waits on files or stdin as well as clocks the subscriptions that
+    ///   are ready can depend on the host
+    pub fn with_deterministic(mut self, seed: u64) -> Self {
+        self.deterministic_seed = Some(seed);
+        self
+    }
+
+    /// Restore new instances from a `Snapshot` created using `Plugin::snapshot`, instead of initializing
+    /// the guest runtime
+    pub fn with_snapshot(mut self, snapshot: Snapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Set the capabilities granted to the plugin, this replaces the `allowed_hosts` and `allowed_paths`
+    /// fields of the manifest
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Set the function used to get the key for encrypted modules, it's called with the `key_id` from the
+    /// module's `Encryption` metadata and should return the raw 256-bit key
+    pub fn with_key_provider(
+        mut self,
+        f: impl Fn(&str) -> Result<Vec<u8>, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.keys = Some(std::sync::Arc::new(f));
+        self
+    }
+
+    /// Set the version of the plugin's state, this is stored in `PluginState` and a plugin that imports state
+    /// with a different version must export a `migrate` function, see `Plugin::import_state`
+    pub fn with_state_version(mut self, version: u32) -> Self {
+        self.state_version = version;
+        self
+    }
+
+    /// Include a region of an exported memory in the state returned by `Plugin::export_state`
+    pub fn with_state_region(
+        mut self,
+        memory: impl Into<String>,
+        offset: u64,
+        length: u64,
+    ) -> Self {
+        self.state_regions
+            .push(MemoryRegion::new(memory, offset, length));
+        self
+    }
+
     /// Add a single host function
     pub fn with_function<F>(
         mut self,
@@ -68,11 +329,116 @@ impl PluginBuilder {
         self
     }
 
+    /// Allow the manifest to load `Wasm::Precompiled` modules, these are rejected by default. Precompiled modules
+    /// contain native code that's loaded without being validated, so only enable this when every manifest the
+    /// builder is used with, including included and extended manifests, comes from a trusted source.
+    pub fn with_precompiled_modules(mut self, allow: bool) -> Self {
+        self.precompiled = allow;
+        self
+    }
+
+    /// Compile `wasm` using this builder's engine settings and serialize it, the result can be loaded using
+    /// `Wasm::Precompiled` by plugins created with the same settings and version of Extism, see
+    /// `with_precompiled_modules`
+    pub fn precompile(&self, wasm: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
+        backend::Active::precompile(&self.config.engine()?, wasm.as_ref())
+    }
+
+    /// Identifies the engine settings used by this builder, modules precompiled with a different hash can't be
+    /// loaded
+    pub fn compatibility_hash(&self) -> Result<String, Error> {
+        Ok(backend::Active::compatibility_hash(&self.config.engine()?))
+    }
+
     /// Generate a new plugin with the configured settings
     pub fn build(self) -> Result<Plugin, Error> {
-        match self.source {
-            Source::Manifest(m) => Plugin::new_with_manifest(&m, self.functions, self.wasi),
-            Source::Data(d) => Plugin::new(d, self.functions, self.wasi),
+        if let Some(n) = self.compilation_threads {
+            let pool = engine::compilation_pool(n)?;
+            let builder = PluginBuilder {
+                compilation_threads: None,
+                ..self
+            };
+            return pool.install(move || builder.build());
+        }
+
+        let data = match self.source {
+            Source::Manifest(m) => serde_json::to_vec(&m)?,
+            Source::Data(d) => d,
+        };
+        let options = PluginOptions {
+            config: self.config,
+            shared: self.module_cache,
+            policy: self.policy,
+            keys: self.keys,
+            max_threads: self.max_threads,
+            deterministic_seed: self.deterministic_seed,
+            precompiled: self.precompiled,
+        };
+        let mut plugin = Plugin::new_with_options(options, data, self.functions, self.wasi)?;
+        plugin.snapshot = self.snapshot;
+        plugin.state.version = self.state_version;
+        plugin.state.regions = self.state_regions;
+        plugin.fuel_limit = self.fuel_limit;
+        plugin.stateless = self.stateless;
+        if self.epoch_ticker {
+            plugin.use_epoch_ticker();
+        }
+        Ok(plugin)
+    }
+
+    /// Generate a `ComponentPlugin` from a WebAssembly component with the configured settings. Snapshots, state,
+    /// threads, stateless calls and the epoch ticker only apply to core modules and aren't used by components.
+    pub fn build_component(self) -> Result<ComponentPlugin, Error> {
+        if let Some(n) = self.compilation_threads {
+            let pool = engine::compilation_pool(n)?;
+            let builder = PluginBuilder {
+                compilation_threads: None,
+                ..self
+            };
+            return pool.install(move || builder.build_component());
         }
+
+        let data = match self.source {
+            Source::Manifest(m) => serde_json::to_vec(&m)?,
+            Source::Data(d) => d,
+        };
+        let options = PluginOptions {
+            config: self.config,
+            shared: self.module_cache,
+            policy: self.policy,
+            keys: self.keys,
+            max_threads: self.max_threads,
+            deterministic_seed: self.deterministic_seed,
+            precompiled: self.precompiled,
+        };
+        let mut plugin =
+            ComponentPlugin::new_with_options(options, data, self.functions, self.wasi)?;
+        plugin.fuel_limit = self.fuel_limit;
+        Ok(plugin)
+    }
+
+    // The number of plugins that pools may create from this builder, set using `Manifest::max_concurrent_calls`
+    // and `Manifest::max_instances`
+    pub(crate) fn concurrency_limit(&self) -> Option<usize> {
+        let parsed;
+        let manifest = match &self.source {
+            Source::Manifest(m) => m.as_ref(),
+            Source::Data(d) => {
+                parsed = manifest::parse(d).ok()?.0;
+                &parsed
+            }
+        };
+        let limits = [manifest.max_concurrent_calls, manifest.max_instances];
+        limits
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|n| (n as usize).max(1))
+    }
+
+    /// Compile and instantiate the plugin on a background thread, the returned `DeferredPlugin` can
+    /// be used immediately
+    pub fn build_deferred(self) -> DeferredPlugin {
+        DeferredPlugin::new(self)
     }
 }
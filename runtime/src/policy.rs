@@ -0,0 +1,524 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use crate::*;
+
+/// The default limit for the total size of plugin variables, 100MiB
+pub const DEFAULT_KV_MAX_BYTES: usize = 1024 * 1024 * 100;
+
+// WASI `errno::acces`
+const ERRNO_ACCES: i32 = 2;
+
+fn default_kv_max_bytes() -> usize {
+    DEFAULT_KV_MAX_BYTES
+}
+
+/// A capability that can be granted to a plugin
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "capability", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Capability {
+    /// Make HTTP requests to the listed hosts, wildcards may be used. Entries may also be origins, like
+    /// `https://api.example.com:8443`, to restrict the scheme and port, see `Policy::check_http`. Requests
+    /// matching an entry in `denied` are blocked even if they match `hosts`.
+    Http {
+        hosts: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        denied: Vec<String>,
+    },
+
+    /// Mount directories read-only using WASI, this is a mapping from the path on disk to the path inside the
+    /// plugin
+    FsRead { paths: BTreeMap<PathBuf, PathBuf> },
+
+    /// Mount directories using WASI, the plugin can create, modify and remove files in these directories
+    FsWrite { paths: BTreeMap<PathBuf, PathBuf> },
+
+    /// Use plugin variables, the total size of all variables is limited to `max_bytes`
+    Kv {
+        #[serde(default = "default_kv_max_bytes")]
+        max_bytes: usize,
+    },
+
+    /// Read the system clocks using WASI
+    Clock,
+}
+
+/// `Policy` determines what a plugin is allowed to do. Every host function, the WASI context and the HTTP
+/// layer check the same policy, so it can be audited in one place. Anything that isn't granted by a capability
+/// is denied.
+///
+/// Policies are usually created from a JSON description:
+///
+/// ```json
+/// {
+///   "capabilities": [
+///     {"capability": "http", "hosts": ["*.example.com"]},
+///     {"capability": "fs-read", "paths": {"./data": "/data"}},
+///     {"capability": "fs-write", "paths": {"./out": "/out"}},
+///     {"capability": "kv", "max_bytes": 1048576},
+///     {"capability": "clock"}
+///   ]
+/// }
+/// ```
+///
+/// When a plugin is created without a policy, one is derived from the manifest using `Policy::from_manifest`.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "Description", into = "Description")]
+pub struct Policy {
+    capabilities: Vec<Capability>,
+    hosts: Vec<HostRule>,
+    denied: Vec<HostRule>,
+}
+
+// A parsed `allowed_hosts` entry
+#[derive(Debug, Clone)]
+struct HostRule {
+    scheme: Option<String>,
+    host: String,
+    pattern: Option<glob::Pattern>,
+    port: Option<u16>,
+
+    // Set for IP addresses and CIDR ranges, like `10.0.0.0/8`
+    net: Option<Network>,
+}
+
+// An IP network, a single address is a network with the full prefix length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    fn parse(s: &str) -> Option<Network> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+            None => (s, None),
+        };
+        let addr: IpAddr = strip_brackets(addr).parse().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+        Some(Network { addr, prefix })
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(a) & mask == u32::from(b) & mask
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(a) & mask == u128::from(b) & mask
+            }
+            _ => false,
+        }
+    }
+
+    fn covers(&self, other: &Network) -> bool {
+        other.prefix >= self.prefix && self.contains(other.addr)
+    }
+}
+
+fn strip_brackets(s: &str) -> &str {
+    s.strip_prefix('[')
+        .and_then(|x| x.strip_suffix(']'))
+        .unwrap_or(s)
+}
+
+// Returns `true` for addresses that aren't publicly routable: loopback, private, link-local and other special
+// purpose ranges
+fn is_internal(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(a) => {
+            let [x, y, ..] = a.octets();
+            a.is_private()
+                || a.is_loopback()
+                || a.is_link_local()
+                || a.is_unspecified()
+                || a.is_broadcast()
+                || a.is_documentation()
+                || x == 0
+                || x >= 240
+                || (x == 100 && (64..128).contains(&y))
+                || (x == 198 && (18..20).contains(&y))
+                || (x == 192 && y == 0 && a.octets()[2] == 0)
+        }
+        IpAddr::V6(a) => {
+            if let Some(v4) = a.to_ipv4_mapped() {
+                return is_internal(IpAddr::V4(v4));
+            }
+
+            // NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`) addresses embed an IPv4 address
+            let s = a.segments();
+            let embedded = |hi: u16, lo: u16| {
+                let [a, b] = hi.to_be_bytes();
+                let [c, d] = lo.to_be_bytes();
+                is_internal(IpAddr::V4(std::net::Ipv4Addr::new(a, b, c, d)))
+            };
+            if s[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return embedded(s[6], s[7]);
+            }
+            if s[0] == 0x2002 {
+                return embedded(s[1], s[2]);
+            }
+
+            a.is_loopback()
+                || a.is_unspecified()
+                || (s[0] & 0xfe00) == 0xfc00
+                || (s[0] & 0xffc0) == 0xfe80
+                || (s[0] == 0x2001 && s[1] == 0x0db8)
+                || (s[0] == 0x64 && s[1] == 0xff9b && s[2] == 1)
+        }
+    }
+}
+
+impl HostRule {
+    fn parse(s: &str) -> HostRule {
+        let (scheme, rest) = match s.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+            None => (None, s),
+        };
+        let (host, port) = split_port(rest.trim_end_matches('/'));
+
+        // An origin without a port only matches the default port for the scheme
+        let port = port.or(match scheme.as_deref() {
+            Some("http") => Some(80),
+            Some("https") => Some(443),
+            _ => None,
+        });
+
+        HostRule {
+            scheme,
+            host: host.to_string(),
+            pattern: glob::Pattern::new(host).ok(),
+            port,
+            net: Network::parse(host),
+        }
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        if let Some(net) = &self.net {
+            return match strip_brackets(host).parse() {
+                Ok(addr) => net.contains(addr),
+                Err(_) => false,
+            };
+        }
+
+        match &self.pattern {
+            Some(pat) => pat.matches(host),
+            None => self.host == host,
+        }
+    }
+
+    // Rules without wildcards name a single host
+    fn is_exact(&self) -> bool {
+        self.net.is_none() && !self.host.contains(['*', '?', '['])
+    }
+
+    // A bare `*` matches every host, including internal addresses
+    fn is_any(&self) -> bool {
+        self.net.is_none() && self.host == "*"
+    }
+
+    fn matches(&self, scheme: &str, host: &str, port: Option<u16>) -> bool {
+        (self.scheme.is_none() || self.scheme.as_deref() == Some(scheme))
+            && (self.port.is_none() || self.port == port)
+            && self.matches_host(host)
+    }
+
+    // Returns `true` if everything matched by `other` is also matched by this rule
+    fn covers(&self, other: &HostRule) -> bool {
+        let host = match (&self.net, &other.net) {
+            (Some(a), Some(b)) => a.covers(b),
+            (Some(_), None) => false,
+            (None, _) => self.matches_host(&other.host),
+        };
+        (self.scheme.is_none() || self.scheme == other.scheme)
+            && (self.port.is_none() || self.port == other.port)
+            && host
+    }
+}
+
+// Split `host:port`, IPv6 addresses must be wrapped in brackets when a port is used
+fn split_port(s: &str) -> (&str, Option<u16>) {
+    if let Some((host, port)) = s.rsplit_once(':') {
+        if !host.is_empty() && (!host.contains(':') || host.ends_with(']')) {
+            if let Ok(port) = port.parse() {
+                return (host, Some(port));
+            }
+        }
+    }
+    (s, None)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Description {
+    #[serde(default)]
+    capabilities: Vec<Capability>,
+}
+
+impl TryFrom<Description> for Policy {
+    type Error = Error;
+
+    fn try_from(desc: Description) -> Result<Self, Self::Error> {
+        Policy::compile(desc.capabilities)
+    }
+}
+
+impl From<Policy> for Description {
+    fn from(policy: Policy) -> Self {
+        Description {
+            capabilities: policy.capabilities,
+        }
+    }
+}
+
+impl Policy {
+    /// Create a policy from a list of capabilities, an error is returned if the same capability is listed
+    /// more than once
+    pub fn compile(capabilities: impl IntoIterator<Item = Capability>) -> Result<Policy, Error> {
+        let capabilities: Vec<Capability> = capabilities.into_iter().collect();
+        for (i, c) in capabilities.iter().enumerate() {
+            let kind = std::mem::discriminant(c);
+            if capabilities[..i]
+                .iter()
+                .any(|x| std::mem::discriminant(x) == kind)
+            {
+                anyhow::bail!("Capability is listed more than once: {c:?}");
+            }
+        }
+
+        let mut hosts = vec![];
+        let mut denied = vec![];
+        for c in capabilities.iter() {
+            if let Capability::Http {
+                hosts: h,
+                denied: d,
+            } = c
+            {
+                hosts.extend(h.iter().map(|x| HostRule::parse(x)));
+                denied.extend(d.iter().map(|x| HostRule::parse(x)));
+            }
+        }
+
+        Ok(Policy {
+            capabilities,
+            hosts,
+            denied,
+        })
+    }
+
+    /// Parse a JSON policy description
+    pub fn from_json(s: &str) -> Result<Policy, Error> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Create the policy described by the `allowed_hosts`, `denied_hosts` and `allowed_paths` fields of a
+    /// manifest, plugin variables and clocks are always allowed
+    pub fn from_manifest(manifest: &Manifest) -> Policy {
+        let mut capabilities = vec![];
+        if let Some(hosts) = &manifest.allowed_hosts {
+            capabilities.push(Capability::Http {
+                hosts: hosts.clone(),
+                denied: manifest.denied_hosts.clone(),
+            });
+        }
+        if let Some(paths) = &manifest.allowed_paths {
+            let (read, write): (Vec<_>, Vec<_>) =
+                paths.iter().partition(|(_, dest)| dest.is_readonly());
+            let collect = |x: Vec<(&PathBuf, &extism_manifest::AllowedPath)>| {
+                x.into_iter()
+                    .map(|(src, dest)| (src.clone(), dest.path().to_path_buf()))
+                    .collect()
+            };
+            capabilities.push(Capability::FsRead {
+                paths: collect(read),
+            });
+            capabilities.push(Capability::FsWrite {
+                paths: collect(write),
+            });
+        }
+        capabilities.push(Capability::Kv {
+            max_bytes: DEFAULT_KV_MAX_BYTES,
+        });
+        capabilities.push(Capability::Clock);
+
+        // The manifest can't contain duplicate capabilities
+        Policy::compile(capabilities).unwrap()
+    }
+
+    /// The capabilities granted by this policy
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// Returns an error if HTTP requests to `url` aren't allowed. Host entries can be a hostname (`example.com`),
+    /// a hostname and port (`example.com:8080`), an origin (`https://example.com`, `https://example.com:8443`),
+    /// an IP address (`10.1.2.3`, `[::1]:8080`) or a CIDR range (`10.0.0.0/8`). Origins without a port only match
+    /// the default port for the scheme, so `https://*.example.com` allows HTTPS requests on port 443 to any
+    /// subdomain of `example.com` and blocks plaintext HTTP.
+    ///
+    /// Denied hosts take precedence: a request matching any `denied` entry is blocked, even if it's also
+    /// matched by an allowed host.
+    ///
+    /// This only checks the URL, see `Policy::resolve` for the check of the addresses a host resolves to.
+    pub fn check_http(&self, url: &str) -> Result<(), Error> {
+        let parsed = match url::Url::parse(url) {
+            Ok(u) => u,
+            Err(e) => return Err(Error::msg(format!("Invalid URL: {e:?}"))),
+        };
+        let host_str = parsed.host_str().unwrap_or_default();
+        let port = parsed.port_or_known_default();
+        if self
+            .denied
+            .iter()
+            .any(|rule| rule.matches(parsed.scheme(), host_str, port))
+        {
+            return Err(Error::msg(format!("HTTP request to {url} is denied")));
+        }
+
+        let host_matches = self
+            .hosts
+            .iter()
+            .any(|rule| rule.matches(parsed.scheme(), host_str, port));
+
+        if !host_matches {
+            return Err(Error::msg(format!("HTTP request to {url} is not allowed")));
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the host of `url` and return the addresses the request may connect to. Internal addresses
+    /// (loopback, private, link-local and other non-public ranges) are only allowed if they're matched by an IP
+    /// or CIDR entry, or if the host is matched by an entry without wildcards or by a bare `*`. This prevents a host
+    /// matched by a wildcard, like `*.example.com`, from being used to reach internal services. Addresses matched
+    /// by a denied IP or CIDR entry are never allowed. An error is returned if none of the addresses are allowed.
+    pub fn resolve(&self, url: &str) -> Result<Vec<SocketAddr>, Error> {
+        use std::net::ToSocketAddrs;
+
+        let parsed = url::Url::parse(url)?;
+        let scheme = parsed.scheme();
+        let port = match parsed.port_or_known_default() {
+            Some(x) => x,
+            None => anyhow::bail!("HTTP request to {url} has no port"),
+        };
+        let host = match parsed.host() {
+            Some(url::Host::Domain(x)) => x.to_string(),
+            Some(url::Host::Ipv4(x)) => x.to_string(),
+            Some(url::Host::Ipv6(x)) => x.to_string(),
+            None => anyhow::bail!("HTTP request to {url} has no host"),
+        };
+
+        let exact = self.hosts.iter().any(|rule| {
+            (rule.is_exact() || rule.is_any())
+                && rule.matches(scheme, parsed.host_str().unwrap_or_default(), Some(port))
+        });
+        let addrs: Vec<SocketAddr> = (host.as_str(), port)
+            .to_socket_addrs()?
+            .filter(|addr| {
+                !self.denied.iter().any(|rule| {
+                    rule.net.is_some() && rule.matches(scheme, &addr.ip().to_string(), Some(port))
+                })
+            })
+            .filter(|addr| {
+                exact
+                    || !is_internal(addr.ip())
+                    || self.hosts.iter().any(|rule| {
+                        rule.net.is_some()
+                            && rule.matches(scheme, &addr.ip().to_string(), Some(port))
+                    })
+            })
+            .collect();
+
+        if addrs.is_empty() {
+            anyhow::bail!(
+                "HTTP request to {url} is not allowed, {host} resolves to an internal address"
+            );
+        }
+        Ok(addrs)
+    }
+
+    // Returns `true` if everything matched by the `allowed_hosts` entry `pattern` is also allowed by this policy
+    pub(crate) fn allows_host_pattern(&self, pattern: &str) -> bool {
+        let other = HostRule::parse(pattern);
+        self.hosts.iter().any(|rule| rule.covers(&other))
+    }
+
+    // The `denied` entries of the HTTP capability
+    #[cfg(feature = "nested")]
+    pub(crate) fn denied_hosts(&self) -> &[String] {
+        self.capabilities
+            .iter()
+            .find_map(|c| match c {
+                Capability::Http { denied, .. } => Some(denied.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Directories that should be mounted read-only using WASI
+    pub fn fs_read_paths(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.capabilities
+            .iter()
+            .filter_map(|c| match c {
+                Capability::FsRead { paths } => Some(paths.iter()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    /// Directories that should be mounted using WASI with write access
+    pub fn fs_write_paths(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
+        self.capabilities
+            .iter()
+            .filter_map(|c| match c {
+                Capability::FsWrite { paths } => Some(paths.iter()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    // Returns `true` if mounting `src` at `dest` is allowed, read-only mounts are allowed by any mount of the
+    // same directory but writable mounts require write access
+    pub(crate) fn allows_path(&self, src: &Path, dest: &Path, readonly: bool) -> bool {
+        let same = |(s, d): (&PathBuf, &PathBuf)| s == src && d == dest;
+        self.fs_write_paths().any(same) || (readonly && self.fs_read_paths().any(same))
+    }
+
+    /// The maximum total size of plugin variables, `None` if variables aren't allowed
+    pub fn kv_max_bytes(&self) -> Option<usize> {
+        self.capabilities.iter().find_map(|c| match c {
+            Capability::Kv { max_bytes } => Some(*max_bytes),
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if the plugin may read the system clocks
+    pub fn allows_clock(&self) -> bool {
+        self.capabilities.contains(&Capability::Clock)
+    }
+
+    // Replace any WASI functions that aren't allowed with functions that return an error
+    pub(crate) fn restrict_linker(&self, linker: &mut Linker<CurrentPlugin>) -> Result<(), Error> {
+        if !self.allows_clock() {
+            linker.allow_shadowing(true);
+            linker.func_wrap(
+                "wasi_snapshot_preview1",
+                "clock_time_get",
+                |_id: i32, _precision: i64, _out: i32| ERRNO_ACCES,
+            )?;
+            linker.func_wrap(
+                "wasi_snapshot_preview1",
+                "clock_res_get",
+                |_id: i32, _out: i32| ERRNO_ACCES,
+            )?;
+        }
+        Ok(())
+    }
+}
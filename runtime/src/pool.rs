@@ -0,0 +1,257 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::*;
+
+struct State {
+    idle: Vec<Plugin>,
+
+    // The number of plugins that are idle, checked out or being created
+    created: usize,
+}
+
+// The function name and input for the priming call made by `Plugin::warm_up`
+type WarmUpCall = Option<(String, Vec<u8>)>;
+
+struct Shared {
+    builder: PluginBuilder,
+    size: usize,
+
+    // Set by `PluginPool::warm_up`, replacement plugins are warmed up the same way
+    warm_up: Mutex<Option<WarmUpCall>>,
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn build(&self) -> Result<Plugin, Error> {
+        let mut plugin = self.builder.clone().build()?;
+        let warm_up = match self.warm_up.lock() {
+            Ok(x) => x.clone(),
+            Err(e) => e.into_inner().clone(),
+        };
+        if let Some(call) = warm_up {
+            plugin.warm_up(
+                call.as_ref()
+                    .map(|(name, input)| (name.as_str(), input.as_slice())),
+            )?;
+        }
+        Ok(plugin)
+    }
+
+    // Return a plugin to the pool, `None` is used when the plugin was dropped
+    fn release(self: &Arc<Self>, plugin: Option<Plugin>) {
+        match plugin {
+            Some(plugin) => {
+                self.lock().idle.push(plugin);
+                self.available.notify_one();
+            }
+            None => {
+                // Replace the plugin in the background, so the pool stays full
+                let shared = self.clone();
+                std::thread::spawn(move || {
+                    let plugin = shared.build();
+                    let mut state = shared.lock();
+                    match plugin {
+                        Ok(plugin) => state.idle.push(plugin),
+                        Err(e) => {
+                            error!("Unable to replace plugin in PluginPool: {e:?}");
+                            state.created -= 1;
+                        }
+                    }
+                    shared.available.notify_one();
+                });
+            }
+        }
+    }
+}
+
+/// `PluginPool` keeps a fixed number of plugins created from the same `PluginBuilder` and hands them out to one
+/// caller at a time, this can be used to share plugins between the threads of a server. Modules are only compiled
+/// once since the pool enables the module cache.
+///
+/// Plugins are returned to the pool when the `PooledPlugin` is dropped. A plugin whose last call trapped, timed out
+/// or exceeded its memory or fuel limit is discarded instead and replaced in the background, since its instance
+/// may be left in an inconsistent state.
+#[derive(Clone)]
+pub struct PluginPool {
+    shared: Arc<Shared>,
+}
+
+impl PluginPool {
+    /// Create a pool of `size` plugins, all plugins are created before this returns. `size` is capped by the
+    /// manifest's `max_concurrent_calls` and `max_instances`.
+    pub fn new(builder: PluginBuilder, size: usize) -> Result<PluginPool, Error> {
+        let size = match builder.concurrency_limit() {
+            Some(limit) => size.min(limit),
+            None => size,
+        }
+        .max(1);
+        let builder = builder.with_module_cache(true);
+        let idle = (0..size)
+            .map(|_| builder.clone().build())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PluginPool {
+            shared: Arc::new(Shared {
+                builder,
+                size,
+                warm_up: Mutex::new(None),
+                state: Mutex::new(State {
+                    idle,
+                    created: size,
+                }),
+                available: Condvar::new(),
+            }),
+        })
+    }
+
+    fn guard(&self, plugin: Plugin) -> PooledPlugin {
+        PooledPlugin {
+            plugin: Some(plugin),
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Take a plugin from the pool, blocking until one is available. An error is returned if a plugin that was
+    /// discarded can't be replaced and no other plugins are left.
+    pub fn get(&self) -> Result<PooledPlugin, Error> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(plugin) = state.idle.pop() {
+                return Ok(self.guard(plugin));
+            }
+
+            // Every plugin failed to be replaced, try again on the calling thread so the error is returned
+            if state.created == 0 {
+                state.created += 1;
+                drop(state);
+                return match self.shared.build() {
+                    Ok(plugin) => Ok(self.guard(plugin)),
+                    Err(e) => {
+                        self.shared.lock().created -= 1;
+                        Err(e)
+                    }
+                };
+            }
+
+            state = match self.shared.available.wait(state) {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+        }
+    }
+
+    /// Warm up the idle plugins using `Plugin::warm_up` and return the time spent on each one, plugins created
+    /// later to replace discarded plugins are also warmed up. Plugins that are checked out aren't affected. A plugin
+    /// that fails to warm up is discarded and the error is returned.
+    pub fn warm_up(&self, call: Option<(&str, &[u8])>) -> Result<Vec<WarmUpStats>, Error> {
+        {
+            let mut warm_up = match self.shared.warm_up.lock() {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+            *warm_up = Some(call.map(|(name, input)| (name.to_string(), input.to_vec())));
+        }
+
+        let mut plugins = vec![];
+        while let Some(plugin) = self.try_get() {
+            plugins.push(plugin);
+        }
+
+        let mut stats = vec![];
+        for mut plugin in plugins {
+            match plugin.warm_up(call) {
+                Ok(x) => stats.push(x),
+                Err(e) => {
+                    plugin.discard();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Take a plugin from the pool, blocking for at most `timeout`. Returns `None` if no plugin became available.
+    pub fn get_timeout(&self, timeout: std::time::Duration) -> Option<PooledPlugin> {
+        let deadline = std::time::Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(plugin) = state.idle.pop() {
+                return Some(self.guard(plugin));
+            }
+
+            let now = std::time::Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = match self.shared.available.wait_timeout(state, deadline - now) {
+                Ok(x) => x.0,
+                Err(e) => e.into_inner().0,
+            };
+        }
+    }
+
+    /// Take a plugin from the pool, returns `None` if none are available
+    pub fn try_get(&self) -> Option<PooledPlugin> {
+        let plugin = self.shared.lock().idle.pop()?;
+        Some(self.guard(plugin))
+    }
+
+    /// The number of plugins that are ready to be checked out
+    pub fn available(&self) -> usize {
+        self.shared.lock().idle.len()
+    }
+
+    /// The number of plugins in the pool
+    pub fn size(&self) -> usize {
+        self.shared.size
+    }
+}
+
+/// A plugin checked out from a `PluginPool`, it's returned to the pool when dropped
+pub struct PooledPlugin {
+    plugin: Option<Plugin>,
+    shared: Arc<Shared>,
+}
+
+impl PooledPlugin {
+    /// Drop the plugin instead of returning it to the pool, a replacement is created in the background
+    pub fn discard(mut self) {
+        self.plugin = None;
+    }
+}
+
+impl std::ops::Deref for PooledPlugin {
+    type Target = Plugin;
+
+    fn deref(&self) -> &Plugin {
+        self.plugin.as_ref().unwrap()
+    }
+}
+
+impl std::ops::DerefMut for PooledPlugin {
+    fn deref_mut(&mut self) -> &mut Plugin {
+        self.plugin.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledPlugin {
+    fn drop(&mut self) {
+        let plugin = self.plugin.take();
+        let id = plugin.as_ref().map(|x| x.id);
+        let plugin = plugin.filter(|x| !x.trapped);
+        if plugin.is_none() {
+            match id {
+                Some(id) => debug!("Replacing plugin {id} in PluginPool"),
+                None => debug!("Replacing plugin in PluginPool"),
+            }
+        }
+        self.shared.release(plugin);
+    }
+}
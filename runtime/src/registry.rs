@@ -0,0 +1,244 @@
+//! A client for resolving plugins by name and version, instead of hard-coding URLs.
+//!
+//! A registry is an HTTP server that responds to `GET {url}/{name}/{version}` with a JSON manifest. Every
+//! module in the manifest must include a SHA-256 `hash`, modules are verified and stored in a local cache
+//! before they're used. Manifests for pinned versions are also cached, so once a plugin has been resolved
+//! it can be loaded again without a network connection. The cache is stored in a directory only the current
+//! user can access, cached manifests are trusted.
+//!
+//! ```rust,no_run
+//! let registry = extism::registry::Registry::new("https://registry.example.com");
+//! let manifest = registry.resolve("count-vowels@1.0.0").unwrap();
+//! let mut plugin = extism::Plugin::new_with_manifest(&manifest, [], true).unwrap();
+//! ```
+use std::path::PathBuf;
+
+use sha2::Digest;
+
+use crate::*;
+
+/// The version used when a reference doesn't include one, manifests for `latest` are never cached
+pub const LATEST: &str = "latest";
+
+/// A plugin reference in the form `name@version`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginRef {
+    /// Plugin name
+    pub name: String,
+
+    /// Plugin version, `None` refers to the latest version
+    pub version: Option<String>,
+}
+
+impl PluginRef {
+    /// The version that will be requested from the registry
+    pub fn version(&self) -> &str {
+        self.version.as_deref().unwrap_or(LATEST)
+    }
+
+    /// Returns `true` if the reference is pinned to a specific version
+    pub fn is_pinned(&self) -> bool {
+        self.version() != LATEST
+    }
+}
+
+impl std::str::FromStr for PluginRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = match s.rsplit_once('@') {
+            Some((name, version)) => (name, Some(version.to_string())),
+            None => (s, None),
+        };
+
+        if name.is_empty() || version.as_deref() == Some("") {
+            anyhow::bail!("Invalid plugin reference: {s}");
+        }
+
+        if name
+            .split('/')
+            .any(|x| x.is_empty() || x == "." || x == "..")
+        {
+            anyhow::bail!("Invalid plugin name: {name}");
+        }
+
+        Ok(PluginRef {
+            name: name.to_string(),
+            version,
+        })
+    }
+}
+
+impl std::fmt::Display for PluginRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.name, self.version())
+    }
+}
+
+/// Resolves plugin references to manifests
+#[derive(Debug, Clone)]
+pub struct Registry {
+    url: String,
+    cache_dir: PathBuf,
+    default_cache_dir: bool,
+    headers: BTreeMap<String, String>,
+}
+
+impl Registry {
+    /// Create a new registry client for the given base URL, the cache is stored in `extism-registry` in the
+    /// user's cache directory by default
+    pub fn new(url: impl Into<String>) -> Registry {
+        Registry {
+            url: url.into().trim_end_matches('/').to_string(),
+            cache_dir: download_cache::user_cache_dir("extism-registry"),
+            default_cache_dir: true,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Set the directory used to cache manifests and modules, the directory should only be writable by the
+    /// current user
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = dir.into();
+        self.default_cache_dir = false;
+        self
+    }
+
+    // The cache directory, `None` if the default directory can't be used because other users can access it
+    fn cache_dir(&self) -> Option<&std::path::Path> {
+        if self.default_cache_dir {
+            if let Err(e) = download_cache::create_private_dir(&self.cache_dir) {
+                error!("Registry cache disabled: {e:?}");
+                return None;
+            }
+        }
+        Some(&self.cache_dir)
+    }
+
+    /// Add a header to every manifest request, this can be used for authentication
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
+    /// Resolve a reference in the form `name@version` to a manifest. All modules referenced by URL are downloaded
+    /// and verified, the returned manifest contains the module data directly.
+    pub fn resolve(&self, reference: &str) -> Result<Manifest, Error> {
+        let r: PluginRef = reference.parse()?;
+        let mut manifest = self.manifest(&r)?;
+        for wasm in manifest.wasm.iter_mut() {
+            *wasm = self.module(&r, wasm)?;
+        }
+        Ok(manifest)
+    }
+
+    // Get the manifest for a plugin, using the cache if the version is pinned
+    fn manifest(&self, r: &PluginRef) -> Result<Manifest, Error> {
+        let digest = manifest::hex(&sha2::Sha256::digest(r.to_string().as_bytes()));
+        let path = match self.cache_dir() {
+            Some(dir) if r.is_pinned() => Some(dir.join("manifests").join(digest)),
+            _ => None,
+        };
+        if let Some(path) = &path {
+            if let Ok(data) = std::fs::read(path) {
+                trace!("Registry cache hit: {r}");
+                return Ok(serde_json::from_slice(&data)?);
+            }
+        }
+
+        let url = format!("{}/{}/{}", self.url, r.name, r.version());
+        debug!("Fetching manifest for {r} from {url}");
+        let mut req = http_client::agent(&url)?.build().get(&url);
+        for (k, v) in self.headers.iter() {
+            req = req.set(k, v);
+        }
+        let data = req.call()?.into_string()?;
+        let manifest: Manifest = serde_json::from_str(&data)?;
+
+        if let Some(path) = &path {
+            if let Err(e) = cache_write(path, data.as_bytes()) {
+                error!("Unable to cache manifest for {r}: {e:?}");
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    // Download and verify a module, modules are cached by hash
+    fn module(
+        &self,
+        r: &PluginRef,
+        wasm: &extism_manifest::Wasm,
+    ) -> Result<extism_manifest::Wasm, Error> {
+        let (req, retry, meta) = match wasm {
+            extism_manifest::Wasm::Data { data, meta } => {
+                if meta.hash.is_none() {
+                    anyhow::bail!("Registry manifest for {r} is missing a hash for a module");
+                }
+                manifest::check_hash(&meta.hash, data)?;
+                return Ok(wasm.clone());
+            }
+            extism_manifest::Wasm::File { path, .. }
+            | extism_manifest::Wasm::Precompiled { path, .. }
+            | extism_manifest::Wasm::Dir { path, .. }
+            | extism_manifest::Wasm::Archive { path, .. } => {
+                anyhow::bail!(
+                    "Registry manifest for {r} references a local file: {}",
+                    path.display()
+                )
+            }
+            extism_manifest::Wasm::Url { req, retry, meta } => (req, retry, meta),
+
+            // OCI artifacts are verified and cached by the runtime when the plugin is loaded
+            extism_manifest::Wasm::Registry { registry, meta, .. } => {
+                if meta.hash.is_none() {
+                    anyhow::bail!("Registry manifest for {r} is missing a hash for {registry}");
+                }
+                return Ok(wasm.clone());
+            }
+        };
+
+        let hash = match &meta.hash {
+            Some(hash) => hash,
+            None => anyhow::bail!(
+                "Registry manifest for {r} is missing a hash for {}",
+                req.url
+            ),
+        };
+
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("Invalid SHA-256 hash in registry manifest for {r}: {hash}");
+        }
+
+        let path = self.cache_dir().map(|dir| dir.join("modules").join(hash));
+        let cached = path.as_ref().and_then(|path| std::fs::read(path).ok());
+        let data = match cached {
+            Some(data) if manifest::check_hash(&meta.hash, &data).is_ok() => data,
+            _ => {
+                debug!("Fetching module for {r} from {}", req.url);
+                let data = manifest::fetch_with_retry(req, retry.as_ref())?;
+                manifest::check_hash(&meta.hash, &data)?;
+
+                if let Some(path) = &path {
+                    if let Err(e) = cache_write(path, &data) {
+                        error!("Unable to cache module for {r}: {e:?}");
+                    }
+                }
+                data
+            }
+        };
+
+        Ok(extism_manifest::Wasm::Data {
+            data,
+            meta: meta.clone(),
+        })
+    }
+}
+
+fn cache_write(path: &std::path::Path, data: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, data)?;
+    Ok(())
+}
@@ -0,0 +1,430 @@
+//! Run plugin functions periodically.
+//!
+//! A `Scheduler` runs each of its jobs on a fixed interval or a cron expression. Every job has its own plugins,
+//! created on demand from a `PluginBuilder`, so jobs don't share state with each other.
+//!
+//! ```rust,no_run
+//! use extism::schedule::{Job, Overlap, Scheduler, Trigger};
+//!
+//! let builder = extism::PluginBuilder::new_with_module(std::fs::read("sync.wasm").unwrap());
+//! let scheduler = Scheduler::new()
+//!     .job(
+//!         "sync",
+//!         Job::new("sync", builder, Trigger::cron("0 */5 * * * *").unwrap())
+//!             .with_overlap(Overlap::Skip)
+//!             .with_jitter(std::time::Duration::from_secs(10)),
+//!     )
+//!     .start()
+//!     .unwrap();
+//! scheduler.wait();
+//! ```
+//!
+//! When a run fails the next run is delayed using exponential backoff, starting at `Backoff::initial` and
+//! doubling after each consecutive failure up to `Backoff::max`. The backoff is reset after a successful run.
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use crate::*;
+
+/// When a job should run
+#[derive(Clone)]
+pub enum Trigger {
+    /// Run every `Duration`, the first run happens after one interval
+    Interval(Duration),
+
+    /// Run when the cron expression matches, expressions are evaluated in UTC
+    Cron(Box<cron::Schedule>),
+}
+
+impl Trigger {
+    /// Parse a cron expression, the fields are `sec min hour day-of-month month day-of-week [year]`
+    pub fn cron(expr: &str) -> Result<Trigger, Error> {
+        let schedule = cron::Schedule::from_str(expr)
+            .map_err(|e| anyhow::format_err!("Invalid cron expression {expr:?}: {e}"))?;
+        Ok(Trigger::Cron(Box::new(schedule)))
+    }
+
+    /// The first time the trigger fires after `after`
+    pub fn next(&self, after: SystemTime) -> Option<SystemTime> {
+        match self {
+            Trigger::Interval(d) => after.checked_add(*d),
+            Trigger::Cron(schedule) => {
+                let after = chrono::DateTime::<chrono::Utc>::from(after);
+                schedule.after(&after).next().map(SystemTime::from)
+            }
+        }
+    }
+}
+
+/// What to do when a job is triggered while a previous run is still in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlap {
+    /// Skip the new run
+    #[default]
+    Skip,
+
+    /// Run again as soon as the current run finishes, at most one run is queued
+    Queue,
+
+    /// Start the new run on another plugin, runs are only limited by `Job::with_max_concurrency`
+    Concurrent,
+}
+
+/// Delay used after a failed run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay after `failures` consecutive failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+
+        let factor = 1u32 << (failures - 1).min(31);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// A plugin function that's called by a `Scheduler`
+pub struct Job {
+    function: String,
+    builder: PluginBuilder,
+    trigger: Trigger,
+    input: Vec<u8>,
+    overlap: Overlap,
+    max_concurrency: usize,
+    jitter: Duration,
+    backoff: Backoff,
+}
+
+impl Job {
+    /// Create a job that calls `function` on plugins created from `builder` whenever `trigger` fires
+    pub fn new(function: impl Into<String>, builder: PluginBuilder, trigger: Trigger) -> Job {
+        Job {
+            function: function.into(),
+            builder,
+            trigger,
+            input: vec![],
+            overlap: Overlap::default(),
+            max_concurrency: 4,
+            jitter: Duration::ZERO,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Set the input passed to each call, by default the input is empty
+    pub fn with_input(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.input = input.into();
+        self
+    }
+
+    /// Set the overlap policy, the default is `Overlap::Skip`
+    pub fn with_overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Set the maximum number of concurrent runs when using `Overlap::Concurrent`, runs triggered above the
+    /// limit are skipped
+    pub fn with_max_concurrency(mut self, n: usize) -> Self {
+        self.max_concurrency = n.max(1);
+        self
+    }
+
+    /// Delay each run by a random amount up to `jitter`, this spreads out jobs that are triggered at the same time
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the backoff used after failed runs
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = Backoff { initial, max };
+        self
+    }
+
+    // The next time the job should run, including jitter
+    fn next(&self, after: SystemTime) -> Option<SystemTime> {
+        let t = self.trigger.next(after)?;
+        if self.jitter.is_zero() {
+            return Some(t);
+        }
+
+        let r = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        let max = self.jitter.as_nanos().min(u64::MAX as u128) as u64;
+        t.checked_add(Duration::from_nanos(r % max))
+    }
+}
+
+/// Information about the runs of a job
+#[derive(Debug, Clone, Default)]
+pub struct JobStatus {
+    /// Successful runs
+    pub runs: u64,
+
+    /// Failed runs
+    pub failures: u64,
+
+    /// Failures since the last successful run, used to determine the backoff
+    pub consecutive_failures: u32,
+
+    /// Runs that were skipped because of the overlap policy
+    pub skipped: u64,
+
+    /// The error from the last failed run
+    pub last_error: Option<String>,
+
+    /// When the job will run next
+    pub next_run: Option<SystemTime>,
+}
+
+struct JobState {
+    job: Job,
+    idle: Mutex<Vec<Plugin>>,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    status: JobStatus,
+    running: usize,
+    queued: bool,
+    retry_at: Option<SystemTime>,
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    match m.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
+
+impl JobState {
+    // Start a run, or skip/queue it depending on the overlap policy
+    fn dispatch(self: &Arc<Self>, name: &str) {
+        let mut inner = lock(&self.inner);
+        if inner.running > 0 {
+            match self.job.overlap {
+                Overlap::Skip => {
+                    inner.status.skipped += 1;
+                    return;
+                }
+                Overlap::Queue => {
+                    inner.queued = true;
+                    return;
+                }
+                Overlap::Concurrent if inner.running >= self.job.max_concurrency => {
+                    inner.status.skipped += 1;
+                    return;
+                }
+                Overlap::Concurrent => (),
+            }
+        }
+        inner.running += 1;
+        drop(inner);
+
+        let state = self.clone();
+        let name = name.to_string();
+        std::thread::spawn(move || loop {
+            state.run(&name);
+
+            let mut inner = lock(&state.inner);
+            if inner.queued {
+                inner.queued = false;
+                continue;
+            }
+            inner.running -= 1;
+            break;
+        });
+    }
+
+    fn run(&self, name: &str) {
+        let plugin = lock(&self.idle).pop();
+        let plugin = match plugin {
+            Some(x) => Ok(x),
+            None => self.job.builder.clone().build(),
+        };
+
+        trace!("Running scheduled job {name}");
+        let res = plugin.and_then(|mut plugin| {
+            plugin.call_bytes(&self.job.function, &self.job.input)?;
+            Ok(plugin)
+        });
+
+        let mut inner = lock(&self.inner);
+        match res {
+            Ok(plugin) => {
+                lock(&self.idle).push(plugin);
+                inner.status.runs += 1;
+                inner.status.consecutive_failures = 0;
+                inner.retry_at = None;
+            }
+            // A failed call may leave the plugin in an unknown state, so it's dropped
+            Err(e) => {
+                error!("Scheduled job {name} failed: {e:?}");
+                inner.status.failures += 1;
+                inner.status.consecutive_failures += 1;
+                inner.status.last_error = Some(format!("{e:?}"));
+                let delay = self.job.backoff.delay(inner.status.consecutive_failures);
+                inner.retry_at = SystemTime::now().checked_add(delay);
+            }
+        }
+    }
+}
+
+/// Runs jobs on a schedule
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: BTreeMap<String, Job>,
+}
+
+impl Scheduler {
+    /// Create a new scheduler with no jobs
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Add a job, `name` is used in logs and to get the job's status
+    pub fn job(mut self, name: impl Into<String>, job: Job) -> Self {
+        self.jobs.insert(name.into(), job);
+        self
+    }
+
+    /// Start running jobs on background threads, an error is returned if a job's trigger never fires
+    pub fn start(self) -> Result<Running, Error> {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let mut jobs = BTreeMap::new();
+        let mut threads = vec![];
+        for (name, job) in self.jobs {
+            let next = match job.next(SystemTime::now()) {
+                Some(x) => x,
+                None => anyhow::bail!("Job {name} is never scheduled"),
+            };
+
+            let state = Arc::new(JobState {
+                job,
+                idle: Mutex::new(vec![]),
+                inner: Mutex::new(Inner::default()),
+            });
+            jobs.insert(name.clone(), state.clone());
+
+            let shutdown = shutdown.clone();
+            threads.push(std::thread::spawn(move || {
+                run_job(&name, state, next, &shutdown)
+            }));
+        }
+
+        Ok(Running {
+            jobs,
+            shutdown,
+            threads,
+        })
+    }
+}
+
+// Wait for each scheduled run and dispatch it, until the scheduler is shut down
+fn run_job(
+    name: &str,
+    state: Arc<JobState>,
+    mut next: SystemTime,
+    shutdown: &(Mutex<bool>, Condvar),
+) {
+    loop {
+        // Delay the run until the backoff from the last failure has elapsed
+        let retry_at = lock(&state.inner).retry_at;
+        if let Some(retry_at) = retry_at {
+            next = next.max(retry_at);
+        }
+        lock(&state.inner).status.next_run = Some(next);
+
+        let mut stop = lock(&shutdown.0);
+        while !*stop {
+            let timeout = match next.duration_since(SystemTime::now()) {
+                Ok(x) if !x.is_zero() => x,
+                _ => break,
+            };
+            stop = match shutdown.1.wait_timeout(stop, timeout) {
+                Ok(x) => x.0,
+                Err(e) => e.into_inner().0,
+            };
+        }
+        if *stop {
+            return;
+        }
+        drop(stop);
+
+        // A run that failed while waiting may have extended the backoff
+        if lock(&state.inner)
+            .retry_at
+            .map(|x| x > next)
+            .unwrap_or(false)
+        {
+            continue;
+        }
+
+        state.dispatch(name);
+        next = match state.job.next(SystemTime::now()) {
+            Some(x) => x,
+            None => {
+                debug!("Scheduled job {name} has no more runs");
+                lock(&state.inner).status.next_run = None;
+                return;
+            }
+        };
+    }
+}
+
+/// A running scheduler, the scheduler is stopped when this is dropped
+pub struct Running {
+    jobs: BTreeMap<String, Arc<JobState>>,
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+}
+
+impl Running {
+    /// Get the status of the job named `name`
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.jobs
+            .get(name)
+            .map(|state| lock(&state.inner).status.clone())
+    }
+
+    /// Stop scheduling new runs, runs that are already in progress are allowed to finish
+    pub fn shutdown(&mut self) {
+        *lock(&self.shutdown.0) = true;
+        self.shutdown.1.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+
+    /// Block until every job has no more runs scheduled
+    pub fn wait(mut self) {
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -349,7 +349,7 @@ pub unsafe extern "C" fn extism_plugin_config(
         json
     );
     let data = std::slice::from_raw_parts(json, json_size as usize);
-    let json: std::collections::BTreeMap<String, Option<String>> =
+    let json: std::collections::BTreeMap<String, Option<serde_json::Value>> =
         match serde_json::from_slice(data) {
             Ok(x) => x,
             Err(e) => {
@@ -357,6 +357,16 @@ pub unsafe extern "C" fn extism_plugin_config(
             }
         };
 
+    // Non-string values are stored as JSON text, the same as config values in a manifest
+    let json = json.into_iter().map(|(k, v)| {
+        let v = v.map(|v| match v {
+            serde_json::Value::String(s) => s,
+            v => v.to_string(),
+        });
+        (k, v)
+    });
+    let json: std::collections::BTreeMap<String, Option<String>> = json.collect();
+
     let wasi = &mut plugin.current_plugin_mut().wasi;
     if let Some(Wasi { ctx, .. }) = wasi {
         for (k, v) in json.iter() {
@@ -490,7 +500,11 @@ pub unsafe extern "C" fn extism_plugin_output_length(plugin: *mut Plugin) -> Siz
     let plugin = &mut *plugin;
     let _lock = plugin.instance.clone();
     let _lock = _lock.lock().unwrap();
-    trace!("Output length: {}", plugin.output.length);
+    trace!(
+        "Output length for plugin {}: {}",
+        plugin.id,
+        plugin.output.length
+    );
     plugin.output.length
 }
 
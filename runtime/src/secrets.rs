@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+
+use crate::*;
+
+/// Returned when a secret file can't be read, see `Manifest::config_secrets`
+#[derive(Debug)]
+pub struct ConfigSecretError {
+    /// The config key
+    pub key: String,
+
+    /// The path of the secret file
+    pub path: PathBuf,
+
+    /// The error returned when reading the file
+    pub error: std::io::Error,
+}
+
+impl std::fmt::Display for ConfigSecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unable to read secret for config key {} from {}: {}",
+            self.key,
+            self.path.display(),
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for ConfigSecretError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+// Read a secret file, a single trailing newline is removed since most tools add one
+fn read(path: &Path) -> Result<String, std::io::Error> {
+    let data = std::fs::read(path)?;
+    let mut s = String::from_utf8(data)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "not valid UTF-8"))?;
+    if s.ends_with('\n') {
+        s.pop();
+        if s.ends_with('\r') {
+            s.pop();
+        }
+    }
+    Ok(s)
+}
+
+// Move `secrets` into `config`, replacing each path with the contents of the file. Relative paths are resolved
+// using `dir`, remote manifests can't reference secret files.
+pub(crate) fn resolve(
+    config: &mut BTreeMap<String, String>,
+    secrets: &mut BTreeMap<String, PathBuf>,
+    dir: Option<&Path>,
+    remote: Option<&str>,
+) -> Result<(), Error> {
+    for (key, path) in std::mem::take(secrets) {
+        if let Some(source) = remote {
+            anyhow::bail!(
+                "Remote manifest {source} references a secret file for config key {key}: {}",
+                path.display()
+            );
+        }
+
+        let path = match dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        };
+        let value = read(&path).map_err(|error| ConfigSecretError {
+            key: key.clone(),
+            path: path.clone(),
+            error,
+        })?;
+        debug!("Loaded secret for config key {key} from {}", path.display());
+        config.insert(key, value);
+    }
+    Ok(())
+}
@@ -0,0 +1,393 @@
+//! Expose plugin functions as HTTP endpoints.
+//!
+//! Each route maps a path to a plugin function: the body of a `POST` request is used as the input and the
+//! output is returned as the response body. Every route has its own plugins, created on demand from a
+//! `PluginBuilder`, and the number of concurrent calls to a route is limited to the number of plugins it may
+//! create.
+//!
+//! ```rust,no_run
+//! use extism::serve::{Route, Server};
+//!
+//! let builder = extism::PluginBuilder::new_with_module(std::fs::read("count_vowels.wasm").unwrap())
+//!     .with_wasi(true);
+//! Server::new()
+//!     .route("/count", Route::new("count_vowels", builder).with_concurrency(4))
+//!     .listen("127.0.0.1:8080")
+//!     .unwrap();
+//! ```
+//!
+//! The server speaks a minimal subset of HTTP/1.1: request bodies must have a `Content-Length` and each
+//! connection is closed after the response is sent. It is meant to be placed behind a reverse proxy. Failed
+//! calls are logged and respond with `500 Internal Server Error` and a generic body.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::*;
+
+/// The default limit for request bodies, 16MiB
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The default limit for open connections
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+
+// Limits for the request line and headers
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+struct Pool {
+    idle: Vec<Plugin>,
+    created: usize,
+}
+
+/// A plugin function exposed by the server
+pub struct Route {
+    function: String,
+    builder: PluginBuilder,
+    concurrency: usize,
+
+    // Set using `Manifest::max_concurrent_calls` and `Manifest::max_instances`
+    limit: Option<usize>,
+    pool: Mutex<Pool>,
+    available: Condvar,
+}
+
+impl Route {
+    /// Create a new route that calls `function` on plugins created from `builder`, by default one call is
+    /// handled at a time
+    pub fn new(function: impl Into<String>, builder: PluginBuilder) -> Route {
+        Route {
+            function: function.into(),
+            limit: builder.concurrency_limit(),
+            builder,
+            concurrency: 1,
+            pool: Mutex::new(Pool {
+                idle: vec![],
+                created: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Set the maximum number of concurrent calls, this is also the maximum number of plugins created for
+    /// the route. Additional requests wait until a plugin is available. The manifest's `max_concurrent_calls` and
+    /// `max_instances` take precedence when they're lower.
+    pub fn with_concurrency(mut self, n: usize) -> Self {
+        self.concurrency = n.max(1);
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pool> {
+        match self.pool.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    // Take an idle plugin or create a new one if the route is below its concurrency limit
+    fn checkout(&self) -> Result<Plugin, Error> {
+        let mut pool = self.lock();
+        loop {
+            if let Some(plugin) = pool.idle.pop() {
+                return Ok(plugin);
+            }
+
+            let concurrency = self
+                .limit
+                .map_or(self.concurrency, |x| x.min(self.concurrency));
+            if pool.created < concurrency {
+                pool.created += 1;
+                drop(pool);
+                let plugin = self.builder.clone().build();
+                if plugin.is_err() {
+                    self.release(None);
+                }
+                return plugin;
+            }
+
+            pool = match self.available.wait(pool) {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+        }
+    }
+
+    // Return a plugin to the pool, `None` is used when the plugin was dropped
+    fn release(&self, plugin: Option<Plugin>) {
+        let mut pool = self.lock();
+        match plugin {
+            Some(plugin) => pool.idle.push(plugin),
+            None => pool.created -= 1,
+        }
+        self.available.notify_one();
+    }
+
+    fn call(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut plugin = self.checkout()?;
+        let res = plugin.call_bytes(&self.function, input).map(|x| x.to_vec());
+
+        // A failed call may leave the plugin in an unknown state, so it's replaced
+        self.release(res.is_ok().then_some(plugin));
+        res
+    }
+}
+
+/// Maps paths to plugin functions
+pub struct Server {
+    routes: BTreeMap<String, Route>,
+    max_body_size: usize,
+    max_connections: usize,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    /// Create a new server with no routes
+    pub fn new() -> Server {
+        Server {
+            routes: BTreeMap::new(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+
+    /// Add a route, `path` must match the request path exactly
+    pub fn route(mut self, path: impl Into<String>, route: Route) -> Self {
+        self.routes.insert(path.into(), route);
+        self
+    }
+
+    /// Set the maximum request body size, larger requests are rejected with `413 Payload Too Large`
+    pub fn with_max_body_size(mut self, n: usize) -> Self {
+        self.max_body_size = n;
+        self
+    }
+
+    /// Set the maximum number of open connections, additional connections are rejected with
+    /// `503 Service Unavailable`
+    pub fn with_max_connections(mut self, n: usize) -> Self {
+        self.max_connections = n;
+        self
+    }
+
+    /// Start the server on a background thread
+    pub fn bind(self, addr: impl ToSocketAddrs) -> Result<Listening, Error> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = Arc::new(self);
+        let s = shutdown.clone();
+        let thread = std::thread::spawn(move || server.accept(listener, s));
+        debug!("Listening on {addr}");
+        Ok(Listening {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// Start the server and block until it stops
+    pub fn listen(self, addr: impl ToSocketAddrs) -> Result<(), Error> {
+        self.bind(addr)?.wait()
+    }
+
+    fn accept(
+        self: Arc<Self>,
+        listener: TcpListener,
+        shutdown: Arc<AtomicBool>,
+    ) -> Result<(), Error> {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            if shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let mut stream = match stream {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Unable to accept connection: {e:?}");
+                    continue;
+                }
+            };
+
+            if connections.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = respond(&mut stream, 503, b"Too many connections");
+                continue;
+            }
+
+            let server = self.clone();
+            let connections = connections.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = server.handle(&mut stream) {
+                    debug!("Connection error: {e:?}");
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    fn handle(&self, stream: &mut TcpStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let line = read_line(&mut reader)?;
+        let mut parts = line.split(' ');
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_string(), target.to_string())
+            }
+            _ => return respond(stream, 400, b"Invalid request line"),
+        };
+
+        let mut content_length = None;
+        let mut chunked = false;
+        for i in 0.. {
+            let line = read_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+
+            if i >= MAX_HEADERS {
+                return respond(stream, 431, b"Too many headers");
+            }
+
+            let (k, v) = match line.split_once(':') {
+                Some(x) => x,
+                None => return respond(stream, 400, b"Invalid header"),
+            };
+
+            if k.eq_ignore_ascii_case("content-length") {
+                match v.trim().parse::<usize>() {
+                    Ok(n) => content_length = Some(n),
+                    Err(_) => return respond(stream, 400, b"Invalid Content-Length"),
+                }
+            } else if k.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = true;
+            }
+        }
+
+        // Query strings are ignored when matching routes
+        let path = target.split('?').next().unwrap_or_default();
+        let route = match self.routes.get(path) {
+            Some(x) => x,
+            None => return respond(stream, 404, b"Not found"),
+        };
+
+        if method != "POST" {
+            return respond(stream, 405, b"Method not allowed");
+        }
+
+        if chunked {
+            return respond(stream, 411, b"Content-Length is required");
+        }
+
+        let len = content_length.unwrap_or(0);
+        if len > self.max_body_size {
+            return respond(stream, 413, b"Request body is too large");
+        }
+
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+
+        trace!("{method} {path} calling {}", route.function);
+        match route.call(&body) {
+            Ok(output) => respond(stream, 200, &output),
+            // The error may include host paths and other details that clients shouldn't see
+            Err(e) => {
+                error!("Call to {} failed: {e:?}", route.function);
+                respond(stream, 500, b"Internal server error")
+            }
+        }
+    }
+}
+
+/// A running server, the server is stopped when this is dropped
+pub struct Listening {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<Result<(), Error>>>,
+}
+
+impl Listening {
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting new connections, requests that are already being handled are allowed to finish
+    pub fn shutdown(&mut self) {
+        if self.shutdown.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // Wake up the accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Block until the server stops
+    pub fn wait(mut self) -> Result<(), Error> {
+        match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(res) => res,
+                Err(_) => anyhow::bail!("Server thread panicked"),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, Error> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_HEADER_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.len() > MAX_HEADER_LINE || !line.ends_with(b"\n") {
+        anyhow::bail!("Invalid header line");
+    }
+    Ok(String::from_utf8(line)?.trim_end().to_string())
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &[u8]) -> Result<(), Error> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    let content_type = if status == 200 {
+        "application/octet-stream"
+    } else {
+        "text/plain"
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use extism_manifest::{TrustedKey, WasmMetadata};
+
+use crate::*;
+
+/// Check the module signature against the trusted keys. This fails closed: when there are trusted keys every
+/// module must be signed by one of them, and a signed module can't be loaded unless there are keys to check it.
+pub(crate) fn verify(
+    meta: &WasmMetadata,
+    data: &[u8],
+    trusted_keys: &[TrustedKey],
+) -> Result<(), Error> {
+    if trusted_keys.is_empty() && meta.signature.is_none() {
+        return Ok(());
+    }
+
+    let name = meta.name.as_deref().unwrap_or("main");
+    let signature = match &meta.signature {
+        Some(x) => x,
+        None => anyhow::bail!("Module {name} isn't signed"),
+    };
+
+    if trusted_keys.is_empty() {
+        anyhow::bail!("Module {name} is signed but there are no trusted keys to verify it");
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    {
+        let _ = (data, signature);
+        anyhow::bail!("Module {name} is signed, this requires the `signatures` feature");
+    }
+
+    #[cfg(feature = "signatures")]
+    {
+        if trusted_keys.iter().any(|key| check(key, signature, data)) {
+            return Ok(());
+        }
+        anyhow::bail!("Module {name} isn't signed by a trusted key")
+    }
+}
+
+// Returns true when `signature` is a valid signature for `data` made by `key`, signatures in the wrong format for
+// the key are ignored
+#[cfg(feature = "signatures")]
+fn check(key: &TrustedKey, signature: &str, data: &[u8]) -> bool {
+    use base64::Engine;
+
+    match key {
+        TrustedKey::Ed25519 { key } => {
+            let b64 = base64::engine::general_purpose::STANDARD;
+            let (key, signature) = match (b64.decode(key), b64.decode(signature.trim())) {
+                (Ok(key), Ok(signature)) => (key, signature),
+                _ => return false,
+            };
+            ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key)
+                .verify(data, &signature)
+                .is_ok()
+        }
+        TrustedKey::Minisign { key } => {
+            let key = match minisign_verify::PublicKey::from_base64(key.trim()) {
+                Ok(x) => x,
+                Err(_) => return false,
+            };
+            let signature = match minisign_verify::Signature::decode(signature) {
+                Ok(x) => x,
+                Err(_) => return false,
+            };
+            key.verify(data, &signature, false).is_ok()
+        }
+    }
+}
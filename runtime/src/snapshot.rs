@@ -0,0 +1,104 @@
+use crate::*;
+
+/// A `Snapshot` contains the memory and exported globals of a plugin after it has been initialized.
+/// Plugins created using `PluginBuilder::with_snapshot` restore the snapshot instead of running the
+/// guest runtime initialization (`_initialize`, `__wasm_call_ctors`, `hs_init`) each time they're
+/// instantiated, this reduces cold-start time for plugins that do a lot of work during initialization.
+///
+/// Snapshots can only be restored into plugins created from the same modules. Globals that aren't exported
+/// can't be captured, so they shouldn't be modified during initialization.
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: std::sync::Arc<SnapshotData>,
+}
+
+struct SnapshotData {
+    memories: Vec<(String, Vec<u8>)>,
+    globals: Vec<(String, Val)>,
+}
+
+impl Snapshot {
+    // Capture the exported memories and mutable globals of `instance`
+    pub(crate) fn capture(
+        store: &mut Store<CurrentPlugin>,
+        instance: Instance,
+    ) -> Result<Snapshot, Error> {
+        let exports: Vec<(String, Extern)> = instance
+            .exports(&mut *store)
+            .map(|x| (x.name().to_string(), x.into_extern()))
+            .collect();
+
+        let mut memories = vec![];
+        let mut globals = vec![];
+        for (name, export) in exports {
+            match export {
+                Extern::Memory(mem) => {
+                    memories.push((name, mem.data(&*store).to_vec()));
+                }
+                Extern::Global(global) => {
+                    if global.ty(&*store).mutability() != Mutability::Var {
+                        continue;
+                    }
+
+                    match global.get(&mut *store) {
+                        x @ (Val::I32(_)
+                        | Val::I64(_)
+                        | Val::F32(_)
+                        | Val::F64(_)
+                        | Val::V128(_)) => globals.push((name, x)),
+                        _ => anyhow::bail!("Unable to snapshot reference type global: {name}"),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        trace!(
+            "Captured snapshot with {} memories and {} globals",
+            memories.len(),
+            globals.len()
+        );
+        Ok(Snapshot {
+            inner: std::sync::Arc::new(SnapshotData { memories, globals }),
+        })
+    }
+
+    // Restore the snapshot into a newly created instance
+    pub(crate) fn restore(
+        &self,
+        store: &mut Store<CurrentPlugin>,
+        instance: Instance,
+    ) -> Result<(), Error> {
+        for (name, data) in self.inner.memories.iter() {
+            let mem = match instance.get_memory(&mut *store, name) {
+                Some(x) => x,
+                None => anyhow::bail!("Snapshot memory not found: {name}"),
+            };
+
+            let size = mem.data_size(&*store);
+            if data.len() > size {
+                let pages = (data.len() - size) as u64 / WASM_PAGE_SIZE;
+                mem.grow(&mut *store, pages)?;
+            }
+
+            mem.data_mut(&mut *store)[..data.len()].copy_from_slice(data);
+        }
+
+        for (name, val) in self.inner.globals.iter() {
+            let global = match instance.get_global(&mut *store, name) {
+                Some(x) => x,
+                None => anyhow::bail!("Snapshot global not found: {name}"),
+            };
+            global.set(&mut *store, val.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// The total size of the memory captured in the snapshot, in bytes
+    pub fn memory_size(&self) -> usize {
+        self.inner.memories.iter().map(|(_, x)| x.len()).sum()
+    }
+}
+
+const WASM_PAGE_SIZE: u64 = 65536;
@@ -0,0 +1,188 @@
+use crate::*;
+
+/// The version of the `PluginState` encoding, blobs with a different format version are rejected
+pub const STATE_FORMAT_VERSION: u32 = 1;
+
+/// The export called with the previous state when a plugin is upgraded, see `Plugin::import_state`
+pub const MIGRATE_FUNCTION: &str = "migrate";
+
+/// A region of an exported memory that's included in the plugin state
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MemoryRegion {
+    /// The name of the exported memory
+    pub memory: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl MemoryRegion {
+    /// Create a new region of the memory exported as `memory`
+    pub fn new(memory: impl Into<String>, offset: u64, length: u64) -> MemoryRegion {
+        MemoryRegion {
+            memory: memory.into(),
+            offset,
+            length,
+        }
+    }
+}
+
+/// The contents of a `MemoryRegion` when the state was exported
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RegionData {
+    pub memory: String,
+    pub offset: u64,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// `PluginState` contains the plugin variables and declared memory regions of a plugin, it can be used to move
+/// state between plugin instances or to upgrade a stateful plugin without losing data. The state is encoded as
+/// JSON using `PluginState::encode`.
+///
+/// `version` is the state version of the plugin that exported the state, set using
+/// `PluginBuilder::with_state_version`. When the state is imported into a plugin with a different version the
+/// plugin is expected to export a `migrate` function, which receives the encoded state on the first call.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PluginState {
+    pub format: u32,
+    pub version: u32,
+    #[serde(with = "base64_vars")]
+    pub vars: BTreeMap<String, Vec<u8>>,
+    pub regions: Vec<RegionData>,
+}
+
+impl PluginState {
+    /// Encode the state as JSON
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Decode state created using `PluginState::encode`
+    pub fn decode(data: &[u8]) -> Result<PluginState, Error> {
+        let state: PluginState = serde_json::from_slice(data)?;
+        if state.format != STATE_FORMAT_VERSION {
+            anyhow::bail!(
+                "Unsupported plugin state format: {}, expected {STATE_FORMAT_VERSION}",
+                state.format
+            );
+        }
+        Ok(state)
+    }
+}
+
+// State settings for a plugin, `pending` is applied to the next instance
+#[derive(Default)]
+pub(crate) struct StateConfig {
+    pub(crate) version: u32,
+    pub(crate) regions: Vec<MemoryRegion>,
+    pub(crate) pending: Option<PluginState>,
+}
+
+fn memory(
+    store: &mut Store<CurrentPlugin>,
+    instance: Instance,
+    name: &str,
+) -> Result<Memory, Error> {
+    match instance.get_memory(&mut *store, name) {
+        Some(x) => Ok(x),
+        None => anyhow::bail!("State memory not found: {name}"),
+    }
+}
+
+// Read the declared regions from `instance`
+pub(crate) fn read_regions(
+    store: &mut Store<CurrentPlugin>,
+    instance: Instance,
+    regions: &[MemoryRegion],
+) -> Result<Vec<RegionData>, Error> {
+    let mut out = Vec::with_capacity(regions.len());
+    for region in regions {
+        let mem = memory(store, instance, &region.memory)?;
+        let mut data = vec![0; region.length as usize];
+        mem.read(&*store, region.offset as usize, &mut data)
+            .map_err(|_| {
+                anyhow::format_err!(
+                    "State region {}+{} is out of bounds for memory {}",
+                    region.offset,
+                    region.length,
+                    region.memory
+                )
+            })?;
+        out.push(RegionData {
+            memory: region.memory.clone(),
+            offset: region.offset,
+            data,
+        });
+    }
+    Ok(out)
+}
+
+// Copy exported regions back into `instance`
+pub(crate) fn write_regions(
+    store: &mut Store<CurrentPlugin>,
+    instance: Instance,
+    regions: &[RegionData],
+) -> Result<(), Error> {
+    for region in regions {
+        let mem = memory(store, instance, &region.memory)?;
+        mem.write(&mut *store, region.offset as usize, &region.data)
+            .map_err(|_| {
+                anyhow::format_err!(
+                    "State region {}+{} is out of bounds for memory {}",
+                    region.offset,
+                    region.data.len(),
+                    region.memory
+                )
+            })?;
+    }
+    Ok(())
+}
+
+mod base64_bytes {
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        general_purpose::STANDARD.encode(v).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+        general_purpose::STANDARD
+            .decode(s.as_bytes())
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+mod base64_vars {
+    use std::collections::BTreeMap;
+
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        v: &BTreeMap<String, Vec<u8>>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let encoded: BTreeMap<&String, String> = v
+            .iter()
+            .map(|(k, v)| (k, general_purpose::STANDARD.encode(v)))
+            .collect();
+        encoded.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<BTreeMap<String, Vec<u8>>, D::Error> {
+        let encoded = BTreeMap::<String, String>::deserialize(d)?;
+        encoded
+            .into_iter()
+            .map(|(k, v)| {
+                general_purpose::STANDARD
+                    .decode(v.as_bytes())
+                    .map(|v| (k, v))
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect()
+    }
+}
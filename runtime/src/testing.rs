@@ -0,0 +1,272 @@
+//! Utilities for testing plugin integrations without real host functions, networks or files.
+//!
+//! ```rust,no_run
+//! # const WASM: &[u8] = include_bytes!("../../wasm/code-functions.wasm");
+//! use extism::testing::{self, MockFunction, MockHttp};
+//!
+//! let hello = MockFunction::new("hello_world").returning("mocked");
+//! let http = MockHttp::new().respond("https://example.com/*", 200, "ok");
+//! let mut plugin = extism::PluginBuilder::new(testing::manifest(WASM))
+//!     .with_wasi(true)
+//!     .with_functions([hello.function(), http.function()])
+//!     .build()
+//!     .unwrap();
+//! plugin.call::<_, &[u8]>("count_vowels", "abc").unwrap();
+//! hello.assert_called();
+//! ```
+use std::sync::{Arc, Mutex};
+
+use crate::*;
+
+// Lock a mutex, ignoring poisoning since a failed assertion in one test shouldn't affect others
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    match m.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
+
+/// Create a manifest for tests: HTTP requests are allowed to any host, since they're expected to be handled by
+/// `MockHttp`, and the timeout is reduced to 5 seconds
+pub fn manifest(wasm: impl Into<Vec<u8>>) -> Manifest {
+    Manifest::new([extism_manifest::Wasm::data(wasm)])
+        .with_allowed_host("*")
+        .with_timeout(std::time::Duration::from_secs(5))
+}
+
+/// A host function that records its inputs and returns a canned output. The function follows the Extism
+/// convention: each parameter is the offset of a memory block and the result is the offset of the output.
+#[derive(Clone)]
+pub struct MockFunction {
+    name: String,
+    namespace: Option<String>,
+    params: usize,
+    output: Arc<Mutex<Option<Vec<u8>>>>,
+    calls: Arc<Mutex<Vec<Vec<Vec<u8>>>>>,
+}
+
+impl MockFunction {
+    /// Create a new mock with a single parameter that returns no output
+    pub fn new(name: impl Into<String>) -> MockFunction {
+        MockFunction {
+            name: name.into(),
+            namespace: None,
+            params: 1,
+            output: Arc::new(Mutex::new(None)),
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Set the module name the function is imported from
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Set the number of parameters
+    pub fn with_params(mut self, n: usize) -> Self {
+        self.params = n;
+        self
+    }
+
+    /// Set the output returned by each call
+    pub fn returning(self, output: impl Into<Vec<u8>>) -> Self {
+        *lock(&self.output) = Some(output.into());
+        self
+    }
+
+    /// Create the host function, this can be passed to `PluginBuilder::with_functions`
+    pub fn function(&self) -> Function {
+        let output = self.output.clone();
+        let calls = self.calls.clone();
+        let f = Function::new(
+            &self.name,
+            vec![ValType::I64; self.params],
+            [ValType::I64],
+            None,
+            move |plugin, inputs, outputs, _user_data| {
+                let args = inputs
+                    .iter()
+                    .map(|x| {
+                        plugin
+                            .memory_get_val::<&[u8]>(x)
+                            .map(|x| x.to_vec())
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                lock(&calls).push(args);
+
+                let output = lock(&output).clone();
+                outputs[0] = match output {
+                    Some(output) => {
+                        let handle = plugin.memory_new(&output)?;
+                        plugin.memory_to_val(handle)
+                    }
+                    None => Val::I64(0),
+                };
+                Ok(())
+            },
+        );
+
+        match &self.namespace {
+            Some(ns) => f.with_namespace(ns),
+            None => f,
+        }
+    }
+
+    /// The arguments of each call made so far
+    pub fn calls(&self) -> Vec<Vec<Vec<u8>>> {
+        lock(&self.calls).clone()
+    }
+
+    /// The number of calls made so far
+    pub fn call_count(&self) -> usize {
+        lock(&self.calls).len()
+    }
+
+    /// Panics if the function hasn't been called
+    pub fn assert_called(&self) {
+        assert!(self.call_count() > 0, "{} was not called", self.name);
+    }
+
+    /// Panics if the function has been called
+    pub fn assert_not_called(&self) {
+        let n = self.call_count();
+        assert!(n == 0, "{} was called {n} times", self.name);
+    }
+
+    /// Panics if no call has been made with `input` as the first argument
+    pub fn assert_called_with(&self, input: impl AsRef<[u8]>) {
+        let input = input.as_ref();
+        let calls = self.calls();
+        assert!(
+            calls
+                .iter()
+                .any(|x| x.first().map(|x| x.as_slice()) == Some(input)),
+            "{} was not called with {:?}, calls: {:?}",
+            self.name,
+            String::from_utf8_lossy(input),
+            calls
+                .iter()
+                .map(|x| x
+                    .iter()
+                    .map(|x| String::from_utf8_lossy(x))
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        );
+    }
+}
+
+/// A request made to `MockHttp`
+#[derive(Clone)]
+pub struct RecordedRequest {
+    /// The request made by the plugin
+    pub request: extism_manifest::HttpRequest,
+
+    /// Request body
+    pub body: Option<Vec<u8>>,
+}
+
+struct CannedResponse {
+    url: glob::Pattern,
+    method: Option<String>,
+    status: u16,
+    body: Vec<u8>,
+}
+
+/// Replaces the `extism_http_request` host function with canned responses, the plugin's `Policy` is still
+/// enforced. Requests that don't match any response return an error.
+#[derive(Clone, Default)]
+pub struct MockHttp {
+    responses: Arc<Mutex<Vec<CannedResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockHttp {
+    /// Create a new `MockHttp` with no responses
+    pub fn new() -> MockHttp {
+        MockHttp::default()
+    }
+
+    /// Respond to any request to `url` with the given status and body, `url` may contain wildcards. When
+    /// multiple responses match, the first one that was added is used.
+    pub fn respond(self, url: &str, status: u16, body: impl Into<Vec<u8>>) -> Self {
+        self.add(url, None, status, body.into())
+    }
+
+    /// Respond to requests to `url` using a specific method
+    pub fn respond_to(
+        self,
+        method: &str,
+        url: &str,
+        status: u16,
+        body: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.add(url, Some(method.to_uppercase()), status, body.into())
+    }
+
+    fn add(self, url: &str, method: Option<String>, status: u16, body: Vec<u8>) -> Self {
+        let url = match glob::Pattern::new(url) {
+            Ok(x) => x,
+            Err(_) => glob::Pattern::new(&glob::Pattern::escape(url)).unwrap(),
+        };
+        lock(&self.responses).push(CannedResponse {
+            url,
+            method,
+            status,
+            body,
+        });
+        self
+    }
+
+    /// All requests made so far
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        lock(&self.requests).clone()
+    }
+
+    /// Create the host function, this can be passed to `PluginBuilder::with_functions`
+    pub fn function(&self) -> Function {
+        let responses = self.responses.clone();
+        let requests = self.requests.clone();
+        Function::new(
+            "extism_http_request",
+            [ValType::I64, ValType::I64],
+            [ValType::I64],
+            None,
+            move |plugin, inputs, outputs, _user_data| {
+                let req: extism_manifest::HttpRequest =
+                    serde_json::from_slice(plugin.memory_get_val::<&[u8]>(&inputs[0])?)?;
+                let body = plugin
+                    .memory_get_val::<&[u8]>(&inputs[1])
+                    .ok()
+                    .map(|x| x.to_vec())
+                    .or_else(|| req.body.as_ref().map(|x| x.as_bytes().to_vec()));
+
+                plugin.policy.check_http(&req.url)?;
+
+                let method = req.method.as_deref().unwrap_or("GET").to_uppercase();
+                let response = lock(&responses)
+                    .iter()
+                    .find(|x| {
+                        x.url.matches(&req.url)
+                            && x.method.as_ref().map(|m| m == &method).unwrap_or(true)
+                    })
+                    .map(|x| (x.status, x.body.clone()));
+
+                let url = req.url.clone();
+                lock(&requests).push(RecordedRequest { request: req, body });
+
+                let (status, body) = match response {
+                    Some(x) => x,
+                    None => anyhow::bail!("No mock response for {method} {url}"),
+                };
+
+                plugin.http_status = status;
+                let handle = plugin.memory_new(&body)?;
+                outputs[0] = plugin.memory_to_val(handle);
+                Ok(())
+            },
+        )
+        .with_namespace("env")
+    }
+}
@@ -0,0 +1,332 @@
+// WebAssembly threads support, see `PluginBuilder::with_threads`
+//
+// Plugins compiled with `-pthread` import a shared memory and `wasi::thread-spawn`. Each spawned thread runs
+// `wasi_thread_start` in a new instance of the main module, with its own store, WASI context and kernel, but the
+// same shared memory. Threads inherit the deadline and remaining fuel of the call that spawned them, they're
+// interrupted when the plugin is cancelled and stopped when the plugin is reset or dropped.
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::plugin::{link_modules, set_fuel, Kernel};
+use crate::timer::{Timer, TimerAction};
+use crate::*;
+
+// Thread IDs must be between 1 and 0x1FFFFFFF, see the wasi-threads proposal
+const MAX_THREAD_ID: i32 = 0x1FFFFFFF;
+
+// How long `Threads::stop` waits for threads to exit, threads blocked in `memory.atomic.wait` or a host function
+// can't be interrupted
+const STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Threads started by a single store, each thread has a flag that interrupts it
+#[derive(Default)]
+struct Running {
+    stopped: bool,
+    threads: Vec<(Arc<AtomicBool>, std::thread::JoinHandle<()>)>,
+}
+
+/// Everything needed to instantiate the plugin on a new thread, this is created along with each store
+#[derive(Clone)]
+pub(crate) struct Threads {
+    max: u32,
+    running: Arc<AtomicU32>,
+    next_id: Arc<AtomicI32>,
+
+    /// The linker before any modules are linked, with `thread-spawn` defined
+    linker: Linker<CurrentPlugin>,
+    modules: BTreeMap<String, Module>,
+    module_config: manifest::ModuleConfig,
+    main_name: String,
+
+    /// The shared memory imported by the main module, along with the module and name it's imported from
+    memory: Option<(String, String, SharedMemory)>,
+    with_wasi: bool,
+    interrupted: Arc<AtomicBool>,
+    threads: Arc<Mutex<Running>>,
+}
+
+impl Threads {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        engine: &Engine,
+        max: u32,
+        linker: Linker<CurrentPlugin>,
+        modules: BTreeMap<String, Module>,
+        module_config: manifest::ModuleConfig,
+        main_name: &str,
+        with_wasi: bool,
+        interrupted: Arc<AtomicBool>,
+    ) -> Result<Threads, Error> {
+        let memory = shared_memory(engine, &modules[main_name])?;
+        Ok(Threads {
+            max,
+            running: Arc::new(AtomicU32::new(0)),
+            next_id: Arc::new(AtomicI32::new(1)),
+            linker,
+            modules,
+            module_config,
+            main_name: main_name.to_string(),
+            memory,
+            with_wasi,
+            interrupted,
+            threads: Default::default(),
+        })
+    }
+
+    /// Create a new shared memory for a new store, call `stop` first so the threads using the old memory exit
+    pub(crate) fn with_new_memory(&self, engine: &Engine) -> Result<Threads, Error> {
+        Ok(Threads {
+            memory: shared_memory(engine, &self.modules[&self.main_name])?,
+            threads: Default::default(),
+            ..self.clone()
+        })
+    }
+
+    /// Interrupt every running thread and wait for them to exit, no more threads can be spawned afterwards
+    pub(crate) fn stop(&self, engine: &Engine) {
+        let threads = {
+            let mut running = lock(&self.threads);
+            running.stopped = true;
+            std::mem::take(&mut running.threads)
+        };
+        if threads.is_empty() {
+            return;
+        }
+
+        for (interrupted, _) in threads.iter() {
+            interrupted.store(true, Ordering::SeqCst);
+        }
+        let start = std::time::Instant::now();
+        loop {
+            engine.increment_epoch();
+            let running = threads.iter().filter(|(_, t)| !t.is_finished()).count();
+            if running == 0 {
+                break;
+            }
+            if start.elapsed() >= STOP_TIMEOUT {
+                error!("{running} plugin threads didn't stop, they're blocked outside of WebAssembly code");
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        for (_, t) in threads {
+            if t.is_finished() {
+                let _ = t.join();
+            }
+        }
+    }
+
+    /// Define the shared memory in `linker`, this shadows the kernel's memory when it's imported from `env`
+    pub(crate) fn define_memory(
+        &self,
+        linker: &mut Linker<CurrentPlugin>,
+        store: &Store<CurrentPlugin>,
+    ) -> Result<(), Error> {
+        if let Some((module, name, memory)) = &self.memory {
+            linker.allow_shadowing(true);
+            linker.define(store, module, name, memory.clone())?;
+        }
+        Ok(())
+    }
+
+    // Start a new thread running `wasi_thread_start(id, arg)`, returns the thread ID. `fuel` is the fuel remaining
+    // in the spawning store
+    fn spawn(
+        self: Arc<Self>,
+        engine: &Engine,
+        plugin: &CurrentPlugin,
+        fuel: Option<u64>,
+        arg: i32,
+    ) -> Result<i32, Error> {
+        // Held until the thread is added to the list, so `stop` can't miss it
+        let mut running = lock(&self.threads);
+        if running.stopped {
+            anyhow::bail!("Threads have been stopped");
+        }
+        running.threads.retain(|(_, t)| !t.is_finished());
+
+        if self.running.fetch_add(1, Ordering::SeqCst) >= self.max {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            anyhow::bail!("Thread limit reached ({})", self.max);
+        }
+
+        let id = self
+            .next_id
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| {
+                Some(if id >= MAX_THREAD_ID { 1 } else { id + 1 })
+            })
+            .unwrap();
+        let plugin_id = plugin.id;
+        let current = CurrentPlugin::new(
+            plugin.manifest.clone(),
+            plugin.policy.clone(),
+            self.with_wasi,
+            plugin.available_pages,
+        );
+        let mut current = match current {
+            Ok(x) => x,
+            Err(e) => {
+                self.running.fetch_sub(1, Ordering::SeqCst);
+                return Err(e);
+            }
+        };
+        current.deadline = plugin.deadline;
+
+        let engine = engine.clone();
+        let threads = self.clone();
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let thread_interrupted = interrupted.clone();
+        let res = std::thread::Builder::new()
+            .name(format!("extism-thread-{id}"))
+            .spawn(move || {
+                trace!("Plugin {plugin_id}: starting thread {id}");
+                let res = threads
+                    .clone()
+                    .run(&engine, current, fuel, thread_interrupted, id, arg);
+                if let Err(e) = res {
+                    error!("Plugin {plugin_id}: thread {id} failed: {e:?}");
+                }
+                threads.running.fetch_sub(1, Ordering::SeqCst);
+            });
+        match res {
+            Ok(t) => running.threads.push((interrupted, t)),
+            Err(e) => {
+                self.running.fetch_sub(1, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        }
+        Ok(id)
+    }
+
+    fn run(
+        self: Arc<Self>,
+        engine: &Engine,
+        current: CurrentPlugin,
+        fuel: Option<u64>,
+        interrupted: Arc<AtomicBool>,
+        id: i32,
+        arg: i32,
+    ) -> Result<(), Error> {
+        // The timer thread increments the epoch once the deadline has passed, so the epoch deadline callback
+        // below notices that it expired
+        let timer = current.deadline.map(|deadline| {
+            let timer_id = uuid::Uuid::new_v4();
+            let _ = Timer::tx().send(TimerAction::Start {
+                id: timer_id,
+                engine: engine.clone(),
+                duration: Some(deadline.saturating_duration_since(std::time::Instant::now())),
+                interrupted: interrupted.clone(),
+            });
+            timer_id
+        });
+
+        let mut store = Store::new(engine, current);
+        let plugin_interrupted = self.interrupted.clone();
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |ctx| {
+            let expired = ctx
+                .data()
+                .deadline
+                .is_some_and(|x| std::time::Instant::now() >= x);
+            if expired
+                || interrupted.load(Ordering::SeqCst)
+                || plugin_interrupted.load(Ordering::SeqCst)
+            {
+                return Err(Error::msg("timeout"));
+            }
+            Ok(UpdateDeadline::Continue(1))
+        });
+        set_fuel(&mut store, fuel);
+
+        let res = self.run_in_store(store, id, arg);
+        if let Some(id) = timer {
+            let _ = Timer::tx().send(TimerAction::Stop { id });
+        }
+        res
+    }
+
+    fn run_in_store(
+        self: Arc<Self>,
+        mut store: Store<CurrentPlugin>,
+        id: i32,
+        arg: i32,
+    ) -> Result<(), Error> {
+        let mut linker = self.linker.clone();
+        let store_ptr = &mut store as *mut _;
+        let linker_ptr = &mut linker as *mut _;
+        let current = store.data_mut();
+        current.store = store_ptr;
+        current.linker = linker_ptr;
+        current.threads = Some(self.clone());
+        if current.memory_limiter.is_some() {
+            store.limiter(|internal| internal.memory_limiter.as_mut().unwrap());
+        }
+
+        link_modules(
+            &mut linker,
+            &mut store,
+            &self.modules,
+            &self.module_config,
+            &self.main_name,
+        )?;
+        Kernel::new(&linker, &mut store)?;
+        self.define_memory(&mut linker, &store)?;
+
+        let instance = linker.instantiate(&mut store, &self.modules[&self.main_name])?;
+        store.data_mut().instance = Some(instance);
+        let start = instance.get_typed_func::<(i32, i32), ()>(&mut store, "wasi_thread_start")?;
+        start.call(&mut store, (id, arg))
+    }
+}
+
+// Find the shared memory imported by `module` and create it
+fn shared_memory(
+    engine: &Engine,
+    module: &Module,
+) -> Result<Option<(String, String, SharedMemory)>, Error> {
+    for import in module.imports() {
+        if let ExternType::Memory(ty) = import.ty() {
+            if ty.is_shared() {
+                let memory = SharedMemory::new(engine, ty)?;
+                return Ok(Some((
+                    import.module().to_string(),
+                    import.name().to_string(),
+                    memory,
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Define `wasi::thread-spawn`, this returns a negative value when the thread can't be started
+pub(crate) fn add_to_linker(linker: &mut Linker<CurrentPlugin>) -> Result<(), Error> {
+    linker.func_wrap(
+        "wasi",
+        "thread-spawn",
+        |mut caller: Caller<'_, CurrentPlugin>, arg: i32| -> i32 {
+            // Returns the remaining fuel, or an error when fuel metering is disabled
+            let fuel = caller.consume_fuel(0).ok();
+            let plugin = caller.data();
+            let threads = match &plugin.threads {
+                Some(t) => t.clone(),
+                None => return -1,
+            };
+            match threads.spawn(caller.engine(), plugin, fuel, arg) {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("Plugin {}: unable to spawn thread: {e:?}", plugin.id);
+                    -1
+                }
+            }
+        },
+    )?;
+    Ok(())
+}
+
+fn lock(running: &Mutex<Running>) -> std::sync::MutexGuard<'_, Running> {
+    match running.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
@@ -5,6 +5,7 @@ pub(crate) enum TimerAction {
         id: uuid::Uuid,
         engine: Engine,
         duration: Option<std::time::Duration>,
+        interrupted: std::sync::Arc<std::sync::atomic::AtomicBool>,
     },
     Stop {
         id: uuid::Uuid,
@@ -12,6 +13,10 @@ pub(crate) enum TimerAction {
     Cancel {
         id: uuid::Uuid,
     },
+    CancelAt {
+        handle: CancelHandle,
+        at: std::time::Instant,
+    },
     Shutdown,
 }
 
@@ -22,18 +27,195 @@ pub(crate) struct Timer {
 
 #[cfg(not(target_family = "windows"))]
 extern "C" fn cleanup_timer() {
-    let mut timer = match unsafe { TIMER.lock() } {
+    let mut timer = match TIMER.lock() {
         Ok(x) => x,
         Err(e) => e.into_inner(),
     };
     drop(timer.take());
 }
 
-static mut TIMER: std::sync::Mutex<Option<Timer>> = std::sync::Mutex::new(None);
+// Mark a plugin as interrupted and increment the epoch of its engine, the plugin's epoch deadline callback
+// will return an error the next time it's invoked
+fn interrupt(engine: &Engine, interrupted: &std::sync::atomic::AtomicBool) {
+    interrupted.store(true, std::sync::atomic::Ordering::SeqCst);
+    engine.increment_epoch();
+}
+
+// Tracks running plugins for the timer thread, deadlines are kept in order so the thread only needs to wake up
+// when the nearest one expires instead of polling every plugin
+#[derive(Default)]
+struct Scheduler {
+    plugins: BTreeMap<
+        uuid::Uuid,
+        (
+            Engine,
+            Option<std::time::Instant>,
+            std::sync::Arc<std::sync::atomic::AtomicBool>,
+        ),
+    >,
+    deadlines: std::collections::BTreeSet<(std::time::Instant, uuid::Uuid)>,
+
+    // Scheduled using `CancelHandle::cancel_after`, the counter keeps keys unique
+    cancels: BTreeMap<(std::time::Instant, u64), CancelHandle>,
+    next_cancel: u64,
+}
+
+impl Scheduler {
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        let deadline = self.deadlines.first().map(|(deadline, _)| *deadline);
+        let cancel = self.cancels.keys().next().map(|(at, _)| *at);
+        match (deadline, cancel) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn cancel(&mut self, id: &uuid::Uuid) {
+        if let Some((engine, interrupted)) = self.remove(id) {
+            interrupt(&engine, &interrupted);
+        }
+    }
+
+    fn remove(
+        &mut self,
+        id: &uuid::Uuid,
+    ) -> Option<(Engine, std::sync::Arc<std::sync::atomic::AtomicBool>)> {
+        let (engine, deadline, interrupted) = self.plugins.remove(id)?;
+        if let Some(deadline) = deadline {
+            self.deadlines.remove(&(deadline, *id));
+        }
+        Some((engine, interrupted))
+    }
+
+    // Returns `false` when the timer thread should exit
+    fn handle(&mut self, action: TimerAction) -> bool {
+        match action {
+            TimerAction::Start {
+                id,
+                engine,
+                duration,
+                interrupted,
+            } => {
+                self.remove(&id);
+                let deadline = duration.map(|x| std::time::Instant::now() + x);
+                if let Some(deadline) = deadline {
+                    self.deadlines.insert((deadline, id));
+                }
+                self.plugins.insert(id, (engine, deadline, interrupted));
+            }
+            TimerAction::Stop { id } => {
+                self.remove(&id);
+            }
+            TimerAction::Cancel { id } => self.cancel(&id),
+            TimerAction::CancelAt { handle, at } => {
+                self.cancels.insert((at, self.next_cancel), handle);
+                self.next_cancel += 1;
+            }
+            TimerAction::Shutdown => {
+                for (_, (engine, _, interrupted)) in self.plugins.iter() {
+                    interrupt(engine, interrupted);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    // Interrupt any plugins whose deadline has passed
+    fn expire(&mut self, now: std::time::Instant) {
+        while let Some((deadline, id)) = self.deadlines.first().copied() {
+            if deadline > now {
+                break;
+            }
+
+            self.cancel(&id);
+        }
+
+        while let Some(entry) = self.cancels.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+
+            let handle = entry.remove();
+            match &handle.interrupted {
+                Some(interrupted) => interrupted.store(true, std::sync::atomic::Ordering::SeqCst),
+                None => self.cancel(&handle.id),
+            }
+        }
+    }
+}
+
+/// How often the epoch ticker increments the epoch of registered engines, this determines how precise timeouts
+/// are for plugins that use the ticker
+pub(crate) const EPOCH_TICK: std::time::Duration = std::time::Duration::from_millis(10);
+
+// Engines incremented by the ticker thread along with the number of plugins using each one, the thread exits
+// once no engines are registered
+struct Ticker {
+    engines: Vec<(Engine, usize)>,
+    running: bool,
+}
+
+static TICKER: std::sync::Mutex<Ticker> = std::sync::Mutex::new(Ticker {
+    engines: Vec::new(),
+    running: false,
+});
+
+fn ticker() -> std::sync::MutexGuard<'static, Ticker> {
+    match TICKER.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
+
+/// Keeps a plugin's engine registered with the epoch ticker, the engine is unregistered when this is dropped.
+/// Plugins using the ticker check their own deadline each time the epoch is incremented, so calls don't need to
+/// send messages to the timer thread.
+pub(crate) struct TickerGuard(Engine);
+
+impl TickerGuard {
+    pub(crate) fn register(engine: &Engine) -> TickerGuard {
+        let mut t = ticker();
+        match t.engines.iter_mut().find(|(e, _)| Engine::same(e, engine)) {
+            Some((_, n)) => *n += 1,
+            None => t.engines.push((engine.clone(), 1)),
+        }
+
+        if !t.running {
+            t.running = true;
+            std::thread::spawn(|| loop {
+                std::thread::sleep(EPOCH_TICK);
+                let mut t = ticker();
+                if t.engines.is_empty() {
+                    t.running = false;
+                    return;
+                }
+                for (engine, _) in t.engines.iter() {
+                    engine.increment_epoch();
+                }
+            });
+        }
+        TickerGuard(engine.clone())
+    }
+}
+
+impl Drop for TickerGuard {
+    fn drop(&mut self) {
+        let mut t = ticker();
+        if let Some(i) = t.engines.iter().position(|(e, _)| Engine::same(e, &self.0)) {
+            t.engines[i].1 -= 1;
+            if t.engines[i].1 == 0 {
+                t.engines.swap_remove(i);
+            }
+        }
+    }
+}
+
+static TIMER: std::sync::Mutex<Option<Timer>> = std::sync::Mutex::new(None);
 
 impl Timer {
     pub(crate) fn tx() -> std::sync::mpsc::Sender<TimerAction> {
-        let mut timer = match unsafe { TIMER.lock() } {
+        let mut timer = match TIMER.lock() {
             Ok(x) => x,
             Err(e) => e.into_inner(),
         };
@@ -49,61 +231,31 @@ impl Timer {
     pub fn init(timer: &mut Option<Timer>) -> std::sync::mpsc::Sender<TimerAction> {
         let (tx, rx) = std::sync::mpsc::channel();
         let thread = std::thread::spawn(move || {
-            let mut plugins = std::collections::BTreeMap::new();
-
-            macro_rules! handle {
-                ($x:expr) => {
-                    match $x {
-                        TimerAction::Start {
-                            id,
-                            engine,
-                            duration,
-                        } => {
-                            let duration = duration.map(|x| std::time::Instant::now() + x);
-                            plugins.insert(id, (engine, duration));
-                        }
-                        TimerAction::Stop { id } => {
-                            plugins.remove(&id);
-                        }
-                        TimerAction::Cancel { id } => {
-                            if let Some((engine, _)) = plugins.remove(&id) {
-                                engine.increment_epoch();
-                            }
-                        }
-                        TimerAction::Shutdown => {
-                            for (_, (engine, _)) in plugins.iter() {
-                                engine.increment_epoch();
-                            }
-                            return;
+            let mut scheduler = Scheduler::default();
+            loop {
+                // Block until the next action is received or the nearest deadline is reached
+                let action = match scheduler.next_deadline() {
+                    None => match rx.recv() {
+                        Ok(x) => Some(x),
+                        Err(_) => return,
+                    },
+                    Some(deadline) => {
+                        let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+                        match rx.recv_timeout(timeout) {
+                            Ok(x) => Some(x),
+                            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => None,
+                            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
                         }
                     }
                 };
-            }
 
-            loop {
-                if plugins.is_empty() {
-                    if let Ok(x) = rx.recv() {
-                        handle!(x)
+                if let Some(action) = action {
+                    if !scheduler.handle(action) {
+                        return;
                     }
                 }
 
-                plugins = plugins
-                    .into_iter()
-                    .filter(|(_k, (engine, end))| {
-                        if let Some(end) = end {
-                            let now = std::time::Instant::now();
-                            if end <= &now {
-                                engine.increment_epoch();
-                                return false;
-                            }
-                        }
-                        true
-                    })
-                    .collect();
-
-                for x in rx.try_iter() {
-                    handle!(x)
-                }
+                scheduler.expire(std::time::Instant::now());
             }
         });
         *timer = Some(Timer {
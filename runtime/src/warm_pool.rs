@@ -0,0 +1,132 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::*;
+
+// How long to wait before retrying after a plugin fails to build
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+struct State {
+    plugins: Vec<Plugin>,
+    shutdown: bool,
+}
+
+struct Shared {
+    builder: PluginBuilder,
+    size: usize,
+    state: Mutex<State>,
+    refill: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn build(&self) -> Result<Plugin, Error> {
+        let mut plugin = self.builder.clone().build()?;
+        plugin.preinstantiate()?;
+        Ok(plugin)
+    }
+}
+
+/// `WarmPool` keeps a number of instantiated plugins ready to be checked out, when a plugin is taken
+/// from the pool a replacement is created on a background thread. This moves instantiation out of the
+/// request path for bursty workloads.
+pub struct WarmPool {
+    shared: Arc<Shared>,
+}
+
+impl WarmPool {
+    /// Create a new pool that keeps `size` plugins created from `builder` ready. The pool is filled in the
+    /// background, so it may be empty immediately after it's created. `size` is capped by the manifest's
+    /// `max_concurrent_calls` and `max_instances`.
+    pub fn new(builder: PluginBuilder, size: usize) -> WarmPool {
+        let size = match builder.concurrency_limit() {
+            Some(limit) => size.min(limit),
+            None => size,
+        };
+        let shared = Arc::new(Shared {
+            builder,
+            size,
+            state: Mutex::new(State {
+                plugins: Vec::with_capacity(size),
+                shutdown: false,
+            }),
+            refill: Condvar::new(),
+        });
+
+        let s = shared.clone();
+        std::thread::spawn(move || loop {
+            {
+                let mut state = s.lock();
+                while !state.shutdown && state.plugins.len() >= s.size {
+                    state = match s.refill.wait(state) {
+                        Ok(x) => x,
+                        Err(e) => e.into_inner(),
+                    };
+                }
+
+                if state.shutdown {
+                    return;
+                }
+            }
+
+            match s.build() {
+                Ok(plugin) => {
+                    let mut state = s.lock();
+                    if state.shutdown {
+                        return;
+                    }
+                    state.plugins.push(plugin);
+                }
+                Err(e) => {
+                    error!("Unable to create plugin for WarmPool: {e:?}");
+                    let state = s.lock();
+                    let _ = s.refill.wait_timeout(state, RETRY_DELAY);
+                }
+            }
+        });
+
+        WarmPool { shared }
+    }
+
+    /// Take a plugin from the pool, if the pool is empty a new plugin is created on the calling thread
+    pub fn get(&self) -> Result<Plugin, Error> {
+        match self.try_get() {
+            Some(plugin) => Ok(plugin),
+            None => {
+                debug!("WarmPool is empty, creating a new plugin");
+                self.shared.build()
+            }
+        }
+    }
+
+    /// Take a plugin from the pool, returns `None` if the pool is empty
+    pub fn try_get(&self) -> Option<Plugin> {
+        let plugin = self.shared.lock().plugins.pop();
+        if plugin.is_some() {
+            self.shared.refill.notify_one();
+        }
+        plugin
+    }
+
+    /// The number of plugins that are ready to be checked out
+    pub fn available(&self) -> usize {
+        self.shared.lock().plugins.len()
+    }
+
+    /// The number of plugins the pool tries to keep ready
+    pub fn size(&self) -> usize {
+        self.shared.size
+    }
+}
+
+impl Drop for WarmPool {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.refill.notify_all();
+    }
+}
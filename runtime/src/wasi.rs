@@ -0,0 +1,243 @@
+// Read-only WASI directories
+//
+// The preview1 WASI context in wasmtime 13 has no per-directory permissions, so read-only mounts are
+// implemented by wrapping the directory and rejecting every operation that would modify it
+use std::any::Any;
+use std::path::PathBuf;
+
+use wasi_common::dir::{OpenResult, ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{FdFlags, Filestat, OFlags};
+use wasi_common::{ErrorExt, SystemTimeSpec, WasiDir};
+
+type Error = wasi_common::Error;
+
+// Used when `WasiOptions::random` is disabled, `random_get` fails instead of returning data. WASI only uses
+// `try_fill_bytes`, the infallible methods return zeros instead of panicking so a caller that uses them can't
+// abort the host
+pub(crate) struct NoRandom;
+
+impl rand_core::RngCore for NoRandom {
+    fn next_u32(&mut self) -> u32 {
+        0
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        dest.fill(0);
+    }
+
+    fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        // Any code above `CUSTOM_START` is treated as a custom error
+        Err(std::num::NonZeroU32::new(1 << 31).unwrap().into())
+    }
+}
+
+// Used in deterministic mode, random data is generated from the manifest's seed using SplitMix64
+pub(crate) struct SeededRandom(u64);
+
+impl SeededRandom {
+    pub(crate) fn new(seed: u64) -> SeededRandom {
+        SeededRandom(seed)
+    }
+}
+
+impl rand_core::RngCore for SeededRandom {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// Used in deterministic mode, the clock doesn't depend on the host's time. It starts at zero, or the UNIX epoch for
+// wall clocks, and advances by `VirtualClock::STEP` each time it's read so plugins waiting for time to pass still
+// make progress
+pub(crate) struct VirtualClock {
+    reads: std::sync::atomic::AtomicU64,
+    start: cap_std::time::Instant,
+}
+
+impl VirtualClock {
+    const STEP: std::time::Duration = std::time::Duration::from_millis(1);
+
+    pub(crate) fn new() -> VirtualClock {
+        VirtualClock {
+            reads: std::sync::atomic::AtomicU64::new(0),
+            start: cap_std::time::Instant::from_std(std::time::Instant::now()),
+        }
+    }
+
+    fn elapsed(&self) -> std::time::Duration {
+        let n = self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        std::time::Duration::from_nanos((n + 1) * Self::STEP.as_nanos() as u64)
+    }
+}
+
+impl wasi_common::WasiSystemClock for VirtualClock {
+    fn resolution(&self) -> std::time::Duration {
+        Self::STEP
+    }
+
+    fn now(&self, _precision: std::time::Duration) -> cap_std::time::SystemTime {
+        cap_std::time::SystemTime::from_std(std::time::UNIX_EPOCH + self.elapsed())
+    }
+}
+
+impl wasi_common::WasiMonotonicClock for VirtualClock {
+    fn resolution(&self) -> std::time::Duration {
+        Self::STEP
+    }
+
+    // Only the difference from the first reading is visible to the plugin
+    fn now(&self, _precision: std::time::Duration) -> cap_std::time::Instant {
+        self.start + self.elapsed()
+    }
+}
+
+impl wasmtime_wasi::preview2::HostWallClock for VirtualClock {
+    fn resolution(&self) -> std::time::Duration {
+        Self::STEP
+    }
+
+    fn now(&self) -> std::time::Duration {
+        self.elapsed()
+    }
+}
+
+impl wasmtime_wasi::preview2::HostMonotonicClock for VirtualClock {
+    fn resolution(&self) -> u64 {
+        Self::STEP.as_nanos() as u64
+    }
+
+    fn now(&self) -> u64 {
+        self.elapsed().as_nanos() as u64
+    }
+}
+
+pub(crate) struct ReadOnlyDir(pub(crate) Box<dyn WasiDir>);
+
+impl ReadOnlyDir {
+    // Open `path` on disk as a read-only WASI directory
+    pub(crate) fn open(path: &std::path::Path) -> Result<ReadOnlyDir, anyhow::Error> {
+        let d = wasmtime_wasi::Dir::open_ambient_dir(path, wasmtime_wasi::ambient_authority())?;
+        Ok(ReadOnlyDir(Box::new(
+            wasmtime_wasi::sync::dir::Dir::from_cap_std(d),
+        )))
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<OpenResult, Error> {
+        let modifies = OFlags::CREATE | OFlags::EXCLUSIVE | OFlags::TRUNCATE;
+        if write || oflags.intersects(modifies) || fdflags.contains(FdFlags::APPEND) {
+            return Err(Error::perm());
+        }
+
+        match self
+            .0
+            .open_file(symlink_follow, path, oflags, read, false, fdflags)
+            .await?
+        {
+            OpenResult::Dir(d) => Ok(OpenResult::Dir(Box::new(ReadOnlyDir(d)))),
+            f => Ok(f),
+        }
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn remove_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn unlink_file(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+
+    async fn rename(
+        &self,
+        _path: &str,
+        _dest_dir: &dyn WasiDir,
+        _dest_path: &str,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn hard_link(
+        &self,
+        _path: &str,
+        _target_dir: &dyn WasiDir,
+        _target_path: &str,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn set_times(
+        &self,
+        _path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+}
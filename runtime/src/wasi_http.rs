@@ -0,0 +1,103 @@
+// wasi-http support for plugins that use WASI preview 2
+//
+// wasmtime-wasi-http provides the core module bindings, `wasi:http/outgoing-handler#handle` is replaced so every
+// request is checked against the plugin's `Policy` before it's sent, the same as `extism_http_request`. The host
+// is resolved by wasmtime-wasi-http when it connects, so requests are rejected if any of the addresses the host
+// resolves to now aren't allowed
+use wasmtime_wasi_http::bindings::http::types::Scheme;
+use wasmtime_wasi_http::bindings::sync::http::{outgoing_handler, types};
+use wasmtime_wasi_http::types::TableHttpExt;
+use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+
+use crate::*;
+
+impl WasiHttpView for CurrentPlugin {
+    fn http_ctx(&self) -> &WasiHttpCtx {
+        &self.wasi_preview2.as_ref().unwrap().http
+    }
+
+    fn http_ctx_mut(&mut self) -> &mut WasiHttpCtx {
+        &mut self.wasi_preview2.as_mut().unwrap().http
+    }
+}
+
+// Get the URL of an outgoing request
+fn request_url(plugin: &CurrentPlugin, request: u32) -> Result<String, Error> {
+    use wasmtime_wasi::preview2::WasiView;
+
+    let req = plugin.table().get_request(request)?;
+    let scheme = match req.scheme() {
+        Some(Scheme::Http) => "http",
+        Some(Scheme::Https) | None => "https",
+        Some(Scheme::Other(s)) => s.as_str(),
+    };
+    Ok(format!(
+        "{scheme}://{}{}",
+        req.authority(),
+        req.path_with_query()
+    ))
+}
+
+// Reject requests to hosts that resolve to addresses denied by the policy, lookup failures are reported when
+// wasmtime-wasi-http connects
+fn check_addrs(policy: &Policy, url: &str) -> Result<(), Error> {
+    use std::net::ToSocketAddrs;
+
+    let allowed = match policy.resolve(url) {
+        Ok(x) => x,
+        Err(e) if e.is::<std::io::Error>() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let parsed = url::Url::parse(url)?;
+    let port = parsed.port_or_known_default().unwrap_or_default();
+    let host = match parsed.host() {
+        Some(url::Host::Ipv6(x)) => x.to_string(),
+        _ => parsed.host_str().unwrap_or_default().to_string(),
+    };
+    if let Ok(addrs) = (host.as_str(), port).to_socket_addrs() {
+        if let Some(addr) = addrs.into_iter().find(|x| !allowed.contains(x)) {
+            anyhow::bail!(
+                "HTTP request to {url} is not allowed, {host} resolves to {}",
+                addr.ip()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn add_to_linker(linker: &mut Linker<CurrentPlugin>) -> Result<(), Error> {
+    wasmtime_wasi_http::sync::add_to_linker(linker)?;
+
+    let flag = |is_some: i32, value: u32| (is_some == 1).then_some(value);
+    linker.func_wrap(
+        "wasi:http/outgoing-handler",
+        "handle",
+        move |mut caller: Caller<'_, CurrentPlugin>,
+              request: u32,
+              has_options: i32,
+              has_timeout: i32,
+              timeout_ms: u32,
+              has_first_byte_timeout: i32,
+              first_byte_timeout_ms: u32,
+              has_between_bytes_timeout: i32,
+              between_bytes_timeout_ms: u32|
+              -> Result<u32, Error> {
+            let plugin = caller.data_mut();
+            if plugin.manifest.deterministic_seed.is_some() {
+                anyhow::bail!("HTTP requests aren't allowed in deterministic mode");
+            }
+            let url = request_url(plugin, request)?;
+            plugin.policy.check_http(&url)?;
+            check_addrs(&plugin.policy, &url)?;
+            trace!("Plugin {}: wasi-http request to {url}", plugin.id);
+
+            let options = (has_options == 1).then(|| types::RequestOptions {
+                connect_timeout_ms: flag(has_timeout, timeout_ms),
+                first_byte_timeout_ms: flag(has_first_byte_timeout, first_byte_timeout_ms),
+                between_bytes_timeout_ms: flag(has_between_bytes_timeout, between_bytes_timeout_ms),
+            });
+            outgoing_handler::Host::handle(plugin, request, options)
+        },
+    )?;
+    Ok(())
+}
@@ -0,0 +1,309 @@
+// wasi-nn support for plugins that use WASI
+//
+// This implements the `wasi_ephemeral_nn` module using the backends from wasmtime-wasi-nn. Plugins can only load
+// the models listed in `WasiOptions::nn`: model directories loaded by name, or model files matching one of the
+// allowed hashes
+use anyhow::Context;
+use wasmtime_wasi_nn::backend::BackendError;
+use wasmtime_wasi_nn::wit::types::{ExecutionTarget, GraphEncoding, Tensor, TensorType};
+use wasmtime_wasi_nn::{Backend, ExecutionContext, Graph};
+
+use crate::*;
+
+const MODULE: &str = "wasi_ephemeral_nn";
+
+// `nn_errno` values returned to the plugin
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_INVALID_ARGUMENT: i32 = 1;
+const ERRNO_INVALID_ENCODING: i32 = 2;
+const ERRNO_MISSING_MEMORY: i32 = 3;
+const ERRNO_RUNTIME_ERROR: i32 = 5;
+const ERRNO_NOT_FOUND: i32 = 8;
+
+/// wasi-nn state, graphs and execution contexts are referenced by their index
+pub(crate) struct WasiNn {
+    backends: Vec<Backend>,
+    models: BTreeMap<String, Graph>,
+    hashes: Vec<String>,
+    graphs: Vec<Graph>,
+    contexts: Vec<ExecutionContext>,
+}
+
+impl WasiNn {
+    pub(crate) fn new(options: &extism_manifest::NnOptions) -> Result<WasiNn, Error> {
+        let mut backends = wasmtime_wasi_nn::backend::list();
+        let mut models = BTreeMap::new();
+        for (name, path) in &options.models {
+            let backend = match backends.iter_mut().find_map(|b| b.as_dir_loadable()) {
+                Some(b) => b,
+                None => anyhow::bail!("No wasi-nn backend can load model directories"),
+            };
+            let graph = backend
+                .load_from_dir(path, ExecutionTarget::Cpu)
+                .with_context(|| {
+                    format!(
+                        "Unable to load wasi-nn model {name} from {}",
+                        path.display()
+                    )
+                })?;
+            models.insert(name.clone(), graph);
+        }
+
+        Ok(WasiNn {
+            backends,
+            models,
+            hashes: options.hashes.clone(),
+            graphs: vec![],
+            contexts: vec![],
+        })
+    }
+
+    fn allows(&self, data: &[u8]) -> bool {
+        self.hashes
+            .iter()
+            .any(|hash| match extism_manifest::HashAlgorithm::split(hash) {
+                Some((alg, expected)) => alg.digest(data).eq_ignore_ascii_case(expected),
+                None => false,
+            })
+    }
+
+    fn add_graph(&mut self, graph: Graph) -> u32 {
+        self.graphs.push(graph);
+        self.graphs.len() as u32 - 1
+    }
+
+    fn context(&mut self, index: u32) -> Result<&mut ExecutionContext, NnError> {
+        self.contexts
+            .get_mut(index as usize)
+            .ok_or(NnError::Errno(ERRNO_INVALID_ARGUMENT))
+    }
+}
+
+// Errors are either returned to the plugin as an `nn_errno` or stop the call
+enum NnError {
+    Errno(i32),
+    Trap(Error),
+}
+
+impl From<BackendError> for NnError {
+    fn from(e: BackendError) -> Self {
+        debug!("wasi-nn backend error: {e:?}");
+        NnError::Errno(ERRNO_RUNTIME_ERROR)
+    }
+}
+
+// Offsets are `usize` so pointer arithmetic on guest values can't overflow
+fn slice(mem: &[u8], ptr: usize, len: usize) -> Result<&[u8], NnError> {
+    mem.get(ptr..ptr + len)
+        .ok_or(NnError::Errno(ERRNO_INVALID_ARGUMENT))
+}
+
+fn read_u32(mem: &[u8], ptr: usize) -> Result<usize, NnError> {
+    let bytes = slice(mem, ptr, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+fn write_u32(mem: &mut [u8], ptr: u32, value: u32) -> Result<(), NnError> {
+    let start = ptr as usize;
+    match mem.get_mut(start..start + 4) {
+        Some(dest) => {
+            dest.copy_from_slice(&value.to_le_bytes());
+            Ok(())
+        }
+        None => Err(NnError::Errno(ERRNO_INVALID_ARGUMENT)),
+    }
+}
+
+// Run `f` with the plugin's memory and wasi-nn state, converting the result to an `nn_errno`
+fn with_nn(
+    caller: &mut Caller<'_, CurrentPlugin>,
+    f: impl FnOnce(&mut [u8], &mut WasiNn) -> Result<(), NnError>,
+) -> Result<i32, Error> {
+    let memory = match caller.get_export("memory") {
+        Some(Extern::Memory(m)) => m,
+        _ => return Ok(ERRNO_MISSING_MEMORY),
+    };
+    let (mem, plugin) = memory.data_and_store_mut(caller);
+    let nn = match plugin.wasi_nn.as_mut() {
+        Some(nn) => nn,
+        None => anyhow::bail!("wasi-nn is only available when WASI is enabled"),
+    };
+    match f(mem, nn) {
+        Ok(()) => Ok(ERRNO_SUCCESS),
+        Err(NnError::Errno(errno)) => Ok(errno),
+        Err(NnError::Trap(e)) => Err(e),
+    }
+}
+
+fn graph_encoding(encoding: u32) -> Result<GraphEncoding, NnError> {
+    Ok(match encoding {
+        0 => GraphEncoding::Openvino,
+        1 => GraphEncoding::Onnx,
+        2 => GraphEncoding::Tensorflow,
+        3 => GraphEncoding::Pytorch,
+        4 => GraphEncoding::Tensorflowlite,
+        5 => GraphEncoding::Autodetect,
+        _ => return Err(NnError::Errno(ERRNO_INVALID_ENCODING)),
+    })
+}
+
+fn execution_target(target: u32) -> Result<ExecutionTarget, NnError> {
+    Ok(match target {
+        0 => ExecutionTarget::Cpu,
+        1 => ExecutionTarget::Gpu,
+        2 => ExecutionTarget::Tpu,
+        _ => return Err(NnError::Errno(ERRNO_INVALID_ARGUMENT)),
+    })
+}
+
+fn tensor_type(ty: u8) -> Result<TensorType, NnError> {
+    Ok(match ty {
+        0 => TensorType::Fp16,
+        1 => TensorType::Fp32,
+        2 => TensorType::U8,
+        3 => TensorType::I32,
+        _ => return Err(NnError::Errno(ERRNO_INVALID_ARGUMENT)),
+    })
+}
+
+pub(crate) fn add_to_linker(linker: &mut Linker<CurrentPlugin>) -> Result<(), Error> {
+    linker.func_wrap(
+        MODULE,
+        "load",
+        |mut caller: Caller<'_, CurrentPlugin>,
+         builders: u32,
+         count: u32,
+         encoding: u32,
+         target: u32,
+         out: u32|
+         -> Result<i32, Error> {
+            with_nn(&mut caller, |mem, nn| {
+                let encoding = graph_encoding(encoding)?;
+                let target = execution_target(target)?;
+                let mut buffers = Vec::with_capacity(count as usize);
+                for i in 0..count as usize {
+                    let ptr = builders as usize + i * 8;
+                    let data = slice(mem, read_u32(mem, ptr)?, read_u32(mem, ptr + 4)?)?;
+                    if !nn.allows(data) {
+                        return Err(NnError::Trap(Error::msg(
+                            "wasi-nn model isn't allowed, its hash isn't listed in the manifest",
+                        )));
+                    }
+                    buffers.push(data);
+                }
+
+                let backend = nn
+                    .backends
+                    .iter_mut()
+                    .find(|b| b.encoding() == encoding)
+                    .ok_or(NnError::Errno(ERRNO_INVALID_ENCODING))?;
+                let graph = backend.load(&buffers, target)?;
+                let id = nn.add_graph(graph);
+                write_u32(mem, out, id)
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "load_by_name",
+        |mut caller: Caller<'_, CurrentPlugin>,
+         name: u32,
+         len: u32,
+         out: u32|
+         -> Result<i32, Error> {
+            with_nn(&mut caller, |mem, nn| {
+                let name = std::str::from_utf8(slice(mem, name as usize, len as usize)?)
+                    .map_err(|_| NnError::Errno(ERRNO_INVALID_ARGUMENT))?;
+                let graph = match nn.models.get(name) {
+                    Some(g) => g.clone(),
+                    None => return Err(NnError::Errno(ERRNO_NOT_FOUND)),
+                };
+                let id = nn.add_graph(graph);
+                write_u32(mem, out, id)
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "init_execution_context",
+        |mut caller: Caller<'_, CurrentPlugin>, graph: u32, out: u32| -> Result<i32, Error> {
+            with_nn(&mut caller, |mem, nn| {
+                let graph = nn
+                    .graphs
+                    .get(graph as usize)
+                    .ok_or(NnError::Errno(ERRNO_INVALID_ARGUMENT))?;
+                let ctx = graph.init_execution_context()?;
+                nn.contexts.push(ctx);
+                write_u32(mem, out, nn.contexts.len() as u32 - 1)
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "set_input",
+        |mut caller: Caller<'_, CurrentPlugin>,
+         ctx: u32,
+         index: u32,
+         tensor: u32|
+         -> Result<i32, Error> {
+            with_nn(&mut caller, |mem, nn| {
+                // The tensor is a record of `dimensions: list<u32>`, `type: u8` and `data: list<u8>`
+                let tensor = tensor as usize;
+                let dims = slice(mem, read_u32(mem, tensor)?, read_u32(mem, tensor + 4)? * 4)?;
+                let tensor_type = tensor_type(slice(mem, tensor + 8, 1)?[0])?;
+                let data = slice(
+                    mem,
+                    read_u32(mem, tensor + 12)?,
+                    read_u32(mem, tensor + 16)?,
+                )?;
+                let tensor = Tensor {
+                    dimensions: dims
+                        .chunks_exact(4)
+                        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+                        .collect(),
+                    tensor_type,
+                    data: data.to_vec(),
+                };
+                nn.context(ctx)?.set_input(index, &tensor)?;
+                Ok(())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "compute",
+        |mut caller: Caller<'_, CurrentPlugin>, ctx: u32| -> Result<i32, Error> {
+            with_nn(&mut caller, |_mem, nn| {
+                nn.context(ctx)?.compute()?;
+                Ok(())
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "get_output",
+        |mut caller: Caller<'_, CurrentPlugin>,
+         ctx: u32,
+         index: u32,
+         buf: u32,
+         max: u32,
+         out: u32|
+         -> Result<i32, Error> {
+            with_nn(&mut caller, |mem, nn| {
+                let start = buf as usize;
+                let dest = mem
+                    .get_mut(start..start + max as usize)
+                    .ok_or(NnError::Errno(ERRNO_INVALID_ARGUMENT))?;
+                let n = nn.context(ctx)?.get_output(index, dest)?;
+                write_u32(mem, out, n)
+            })
+        },
+    )?;
+
+    Ok(())
+}